@@ -0,0 +1,161 @@
+"""Times md0 on a fixed set of documents and prints the results as JSON.
+
+    python benches/bench.py [--scale N] [--repeat N] [--output FILE]
+    python benches/bench.py --compare OLD.json NEW.json [--threshold 0.2]
+
+The documents are a vendored README and ones made up by `synthesize` in a
+given shape: paragraphs heavy with links, or many code fences. `--scale`
+multiplies the size of the made-up ones. Every document is timed through
+`parse` and `tokens_to_html`, and the `call` case times many tiny `parse`
+calls, which is mostly the cost of crossing into Rust.
+
+The summary holds the best and median time of each case, in seconds, so two
+runs (say, before and after a change) can be compared case by case with
+`--compare OLD.json NEW.json`, which fails if any case got slower by more
+than `--threshold` (20% by default).
+"""
+
+import argparse
+import json
+import platform
+import random
+import statistics
+import sys
+import time
+from pathlib import Path
+
+import md0
+
+FIXTURES = Path(__file__).with_name("fixtures")
+
+WORDS = "the parser joins lines into paragraphs and finds links images and code in them".split()
+
+
+def sentence(rng, words):
+    return " ".join(rng.choice(WORDS) for _ in range(words)).capitalize() + "."
+
+
+def link(rng, i):
+    kind = rng.choice(["link", "image", "autolink", "angle"])
+    if kind == "link":
+        return f"[{rng.choice(WORDS)} {i}](https://example.com/{i}#{rng.choice(WORDS)})"
+    if kind == "image":
+        return f"![{rng.choice(WORDS)}](img/{i}.png =64x64)"
+    if kind == "autolink":
+        return f"<https://example.com/{i}>"
+    return f"[{rng.choice(WORDS)}](<docs/file {i}.md>)"
+
+
+def synthesize(shape, size, seed=0):
+    """Makes up a document of `size` blocks in one of the `SHAPES`.
+
+    `links` is paragraphs packed with links and images, wrapped over several
+    lines, and `fences` is mostly code fences of a few lines each. The same
+    shape, size and seed always give the same document.
+    """
+    rng = random.Random(seed)
+    blocks = []
+
+    for i in range(size):
+        if i % 20 == 0:
+            blocks.append(f"## Section {i // 20}")
+        elif shape == "links":
+            parts = [link(rng, i * 10 + j) if j % 2 else sentence(rng, 6) for j in range(12)]
+            lines = [" ".join(parts[j : j + 3]) for j in range(0, len(parts), 3)]
+            blocks.append("\n".join(lines))
+        elif shape == "fences":
+            if i % 4 == 0:
+                blocks.append(sentence(rng, 12))
+            else:
+                language = rng.choice(["py", "rust", "sh", ""])
+                code = "\n".join(f"    value_{j} = compute({j}, {i})" for j in range(rng.randint(2, 12)))
+                blocks.append(f"```{language}\n{code}\n```")
+        else:
+            raise ValueError(f"unknown shape {shape!r}, expected one of {sorted(SHAPES)}")
+
+    return "\n\n".join(blocks) + "\n"
+
+
+SHAPES = {"links": 400, "fences": 400}
+
+
+def documents(scale):
+    yield "readme", (FIXTURES / "readme.md").read_text(encoding="utf-8")
+    for shape, size in SHAPES.items():
+        yield shape, synthesize(shape, size * scale)
+
+
+def measure(function, repeat):
+    times = []
+    for _ in range(repeat):
+        start = time.perf_counter()
+        function()
+        times.append(time.perf_counter() - start)
+
+    return {"best": min(times), "median": statistics.median(times)}
+
+
+def run(scale, repeat):
+    cases = {}
+
+    for name, markdown in documents(scale):
+        tokens = md0.parse(markdown)
+        cases[f"parse/{name}"] = measure(lambda: md0.parse(markdown), repeat)
+        cases[f"html/{name}"] = measure(lambda: md0.tokens_to_html(tokens), repeat)
+        cases[f"parse/{name}"]["bytes"] = len(markdown.encode())
+
+    calls = 1000
+    cases["call"] = measure(lambda: [md0.parse("# x") for _ in range(calls)], repeat)
+    cases["call"]["calls"] = calls
+
+    return {
+        "md0": md0.__version__,
+        "python": platform.python_version(),
+        "scale": scale,
+        "repeat": repeat,
+        "cases": cases,
+    }
+
+
+def compare(old, new, threshold):
+    """Prints how much faster or slower each case got, by best time, and
+    returns the cases that got slower by more than `threshold`."""
+    slower = []
+    for case, timing in new["cases"].items():
+        before = old["cases"].get(case)
+        if before is None:
+            print(f"{case:20} new")
+            continue
+        change = timing["best"] / before["best"] - 1
+        print(f"{case:20} {before['best'] * 1e3:9.3f} ms -> {timing['best'] * 1e3:9.3f} ms  {change:+.1%}")
+        if change > threshold:
+            slower.append(case)
+
+    return slower
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.split("\n")[0])
+    parser.add_argument("--scale", type=int, default=1)
+    parser.add_argument("--repeat", type=int, default=20)
+    parser.add_argument("--output", type=Path)
+    parser.add_argument("--compare", nargs=2, type=Path, metavar=("OLD", "NEW"))
+    parser.add_argument("--threshold", type=float, default=0.2)
+    args = parser.parse_args()
+
+    if args.compare:
+        old, new = (json.loads(path.read_text(encoding="utf-8")) for path in args.compare)
+        slower = compare(old, new, args.threshold)
+        if slower:
+            sys.exit(f"slower by more than {args.threshold:.0%}: {', '.join(slower)}")
+        return
+
+    summary = json.dumps(run(args.scale, args.repeat), indent=2)
+    if args.output:
+        args.output.write_text(summary + "\n", encoding="utf-8")
+    else:
+        sys.stdout.write(summary + "\n")
+
+
+if __name__ == "__main__":
+    main()
@@ -1,4 +1,5 @@
 from .md0 import *
+from .md0 import __version__
 
 
 __doc__ = md0.__doc__
@@ -0,0 +1,478 @@
+from typing import Callable, Iterable, Literal, Optional, Sequence, Union, overload
+
+__version__: str
+
+class Metadata:
+    class Link(Metadata):
+        location: tuple[int, int]
+        label: str
+        url: str
+        span: Optional[tuple[int, int]]
+        def __init__(
+            self, location: tuple[int, int], label: str, url: str, span: Optional[tuple[int, int]] = None
+        ) -> None: ...
+
+    class Image(Metadata):
+        location: tuple[int, int]
+        label: str
+        url: str
+        width: Optional[int]
+        height: Optional[int]
+        span: Optional[tuple[int, int]]
+        def __init__(
+            self,
+            location: tuple[int, int],
+            label: str,
+            url: str,
+            width: Optional[int] = None,
+            height: Optional[int] = None,
+            span: Optional[tuple[int, int]] = None,
+        ) -> None: ...
+
+    class Abbreviation(Metadata):
+        location: tuple[int, int]
+        short: str
+        title: str
+        def __init__(self, location: tuple[int, int], short: str, title: str) -> None: ...
+
+    class Raw(Metadata):
+        location: tuple[int, int]
+        closed: bool
+        def __init__(self, location: tuple[int, int], closed: bool = True) -> None: ...
+
+    class LineBreak(Metadata):
+        location: tuple[int, int]
+        def __init__(self, location: tuple[int, int]) -> None: ...
+
+    class InlineMath(Metadata):
+        location: tuple[int, int]
+        content: str
+        def __init__(self, location: tuple[int, int], content: str) -> None: ...
+
+    class WikiLink(Metadata):
+        location: tuple[int, int]
+        target: str
+        alias: Optional[str]
+        def __init__(self, location: tuple[int, int], target: str, alias: Optional[str] = None) -> None: ...
+
+class Token:
+    @property
+    def span(self) -> tuple[int, int]: ...
+    @property
+    def raw(self) -> Optional[str]: ...
+    @property
+    def id(self) -> int: ...
+    @property
+    def parent_id(self) -> Optional[int]: ...
+    @property
+    def kind(
+        self,
+    ) -> Literal[
+        "heading", "paragraph", "horizontal_rule", "definition_list", "abbreviation", "code", "container", "math_block"
+    ]: ...
+    @property
+    def metadata(self) -> list[Metadata]: ...
+    def __len__(self) -> int: ...
+
+    class Heading(Token):
+        level: int
+        content: str
+        span: tuple[int, int]
+        raw: Optional[str]
+        number: Optional[str]
+        id: int
+        parent_id: Optional[int]
+        def __init__(
+            self,
+            level: int,
+            content: str,
+            span: tuple[int, int] = (0, 0),
+            raw: Optional[str] = None,
+            number: Optional[str] = None,
+            id: int = 0,
+            parent_id: Optional[int] = None,
+        ) -> None: ...
+
+    class Paragraph(Token):
+        _0: str
+        _1: list[Metadata]
+        _2: tuple[int, int]
+        _3: Optional[str]
+        _4: list[int]
+        _5: int
+        _6: Optional[int]
+        def __init__(
+            self,
+            _0: str,
+            _1: Sequence[Metadata],
+            _2: tuple[int, int] = (0, 0),
+            _3: Optional[str] = None,
+            _4: Sequence[int] = [],
+            _5: int = 0,
+            _6: Optional[int] = None,
+        ) -> None: ...
+
+    class HorizontalRule(Token):
+        span: tuple[int, int]
+        raw: Optional[str]
+        marker: str
+        length: int
+        id: int
+        parent_id: Optional[int]
+        def __init__(
+            self,
+            span: tuple[int, int] = (0, 0),
+            raw: Optional[str] = None,
+            marker: str = "-",
+            length: int = 3,
+            id: int = 0,
+            parent_id: Optional[int] = None,
+        ) -> None: ...
+
+    class DefinitionList(Token):
+        items: list[tuple[str, list[tuple[str, list[Metadata]]]]]
+        span: tuple[int, int]
+        raw: Optional[str]
+        id: int
+        parent_id: Optional[int]
+        def __init__(
+            self,
+            items: Sequence[tuple[str, Sequence[tuple[str, Sequence[Metadata]]]]],
+            span: tuple[int, int] = (0, 0),
+            raw: Optional[str] = None,
+            id: int = 0,
+            parent_id: Optional[int] = None,
+        ) -> None: ...
+
+    class Abbreviation(Token):
+        abbr: str
+        expansion: str
+        span: tuple[int, int]
+        raw: Optional[str]
+        id: int
+        parent_id: Optional[int]
+        def __init__(
+            self,
+            abbr: str,
+            expansion: str,
+            span: tuple[int, int] = (0, 0),
+            raw: Optional[str] = None,
+            id: int = 0,
+            parent_id: Optional[int] = None,
+        ) -> None: ...
+
+    class Code(Token):
+        language: str
+        content: str
+        fence_char: Optional[str]
+        fence_length: int
+        indent: int
+        span: tuple[int, int]
+        raw: Optional[str]
+        info: str
+        flags: list[str]
+        attrs: dict[str, str]
+        id: int
+        parent_id: Optional[int]
+        def __init__(
+            self,
+            language: str,
+            content: str,
+            fence_char: Optional[str] = None,
+            fence_length: int = 0,
+            indent: int = 0,
+            span: tuple[int, int] = (0, 0),
+            raw: Optional[str] = None,
+            info: str = "",
+            flags: Sequence[str] = [],
+            attrs: dict[str, str] = {},
+            id: int = 0,
+            parent_id: Optional[int] = None,
+        ) -> None: ...
+
+    class Container(Token):
+        name: str
+        attrs: str
+        content: list[Token]
+        span: tuple[int, int]
+        raw: Optional[str]
+        id: int
+        parent_id: Optional[int]
+        def __init__(
+            self,
+            name: str,
+            attrs: str,
+            content: Sequence[Token],
+            span: tuple[int, int] = (0, 0),
+            raw: Optional[str] = None,
+            id: int = 0,
+            parent_id: Optional[int] = None,
+        ) -> None: ...
+
+    class MathBlock(Token):
+        content: str
+        span: tuple[int, int]
+        raw: Optional[str]
+        id: int
+        parent_id: Optional[int]
+        def __init__(
+            self,
+            content: str,
+            span: tuple[int, int] = (0, 0),
+            raw: Optional[str] = None,
+            id: int = 0,
+            parent_id: Optional[int] = None,
+        ) -> None: ...
+
+class ParseOptions:
+    trim_lines: bool
+    collapse_whitespace: bool
+    keep_raw: bool
+    definition_lists: bool
+    abbreviations: bool
+    autolink_emails: bool
+    nbsp_is_whitespace: bool
+    tab_width: int
+    containers: bool
+    source_spans: bool
+    lenient_headings: bool
+    preserve_delimiters: list[tuple[str, str]]
+    strict: bool
+    math: bool
+    sanitize_control_chars: bool
+    wiki_links: bool
+    max_depth: int
+    def __init__(
+        self,
+        *,
+        trim_lines: bool = True,
+        collapse_whitespace: bool = False,
+        keep_raw: bool = False,
+        definition_lists: bool = False,
+        abbreviations: bool = False,
+        autolink_emails: bool = False,
+        nbsp_is_whitespace: bool = True,
+        tab_width: int = 4,
+        containers: bool = False,
+        source_spans: bool = False,
+        lenient_headings: bool = False,
+        preserve_delimiters: Sequence[tuple[str, str]] = (),
+        strict: bool = False,
+        math: bool = False,
+        sanitize_control_chars: bool = False,
+        wiki_links: bool = False,
+        max_depth: int = 64,
+    ) -> None: ...
+
+class HtmlOptions:
+    normalize_languages: bool
+    auto_dir: bool
+    lazy_images: bool
+    preserve_soft_breaks: bool
+    obfuscate_emails: bool
+    decorative_role: bool
+    heading_ids: bool
+    slug_style: Literal["simple", "github"]
+    encode_urls: bool
+    group_images: bool
+    figures: bool
+    escape: bool
+    escape_code: bool
+    strip_title: bool
+    shorten_urls: bool
+    heading_case: Literal["preserve", "sentence", "title", "upper"]
+    recompute_slugs: bool
+    output_style: Literal["html5", "xhtml"]
+    trim_code_lines: bool
+    collapse_level: Optional[int]
+    open_first: bool
+    sanitize_control_chars: bool
+    def __init__(
+        self,
+        *,
+        normalize_languages: bool = False,
+        auto_dir: bool = False,
+        lazy_images: bool = False,
+        preserve_soft_breaks: bool = False,
+        obfuscate_emails: bool = False,
+        decorative_role: bool = False,
+        heading_ids: bool = False,
+        slug_style: Literal["simple", "github"] = "simple",
+        encode_urls: bool = True,
+        group_images: bool = False,
+        figures: bool = False,
+        escape: bool = True,
+        escape_code: bool = True,
+        strip_title: bool = False,
+        shorten_urls: bool = False,
+        heading_case: Literal["preserve", "sentence", "title", "upper"] = "preserve",
+        recompute_slugs: bool = False,
+        output_style: Literal["html5", "xhtml"] = "html5",
+        trim_code_lines: bool = False,
+        collapse_level: Optional[int] = None,
+        open_first: bool = False,
+        sanitize_control_chars: bool = True,
+    ) -> None: ...
+
+class ParseError(ValueError):
+    offset: int
+    code: Literal["unclosed-fence", "unclosed-container", "unclosed-math", "max-depth"]
+
+class Metrics:
+    operation: Literal["parse", "render"]
+    seconds: float
+    phases: dict[str, float]
+    input_bytes: Optional[int]
+    output_bytes: Optional[int]
+    tokens: dict[str, int]
+    metadata: dict[str, int]
+    def as_dict(self) -> dict[str, float]: ...
+
+class Parser:
+    def __init__(self, options: Optional[ParseOptions] = None) -> None: ...
+    def parse(self, markdown: str) -> list[Token]: ...
+
+class CachedParser:
+    def __init__(self, capacity: int = 128, options: Optional[ParseOptions] = None) -> None: ...
+    def parse(self, markdown: str) -> list[Token]: ...
+    def clear(self) -> None: ...
+    def stats(self) -> dict[str, int]: ...
+
+class StreamParser:
+    def __init__(self, options: Optional[ParseOptions] = None) -> None: ...
+    def feed(self, chunk: str) -> None: ...
+    def drain(self) -> list[Token]: ...
+    def finish(self) -> list[Token]: ...
+
+class Renderer:
+    def __init__(self, options: Optional[HtmlOptions] = None) -> None: ...
+    def render(self, tokens: Sequence[Token]) -> str: ...
+
+class Document:
+    text: str
+    tokens: list[Token]
+    abbreviations: dict[str, str]
+    def __init__(self, text: str, options: Optional[ParseOptions] = None) -> None: ...
+    def edit(self, start: int, old_len: int, new_text: str) -> None: ...
+
+class Element:
+    tag: str
+    attrs: dict[str, str]
+    children: list[Union[Element, str]]
+    @property
+    def text(self) -> str: ...
+    def __init__(
+        self,
+        tag: str,
+        attrs: Optional[dict[str, str]] = None,
+        children: Optional[list[Union[Element, str]]] = None,
+    ) -> None: ...
+    def to_html(self, *, output_style: Literal["html5", "xhtml"] = "html5") -> str: ...
+
+class CodeBlock:
+    language: str
+    info: str
+    content: str
+    span: tuple[int, int]
+    lines: Optional[tuple[int, int]]
+
+class OutlineEntry:
+    level: int
+    content: str
+    number: Optional[str]
+    slug: str
+    span: tuple[int, int]
+    children: list[OutlineEntry]
+
+class AnchorProblem:
+    path: Optional[str]
+    label: str
+    url: str
+    span: tuple[int, int]
+    location: tuple[int, int]
+    message: str
+
+class LintProblem:
+    rule: str
+    message: str
+    severity: Literal["error", "warning", "info"]
+    span: tuple[int, int]
+    location: Optional[tuple[int, int]]
+    def __init__(
+        self,
+        rule: str,
+        message: str,
+        span: tuple[int, int],
+        severity: Literal["error", "warning", "info"] = "warning",
+        location: Optional[tuple[int, int]] = None,
+    ) -> None: ...
+
+def capabilities() -> frozenset[str]: ...
+@overload
+def parse(
+    markdown: str, options: Optional[ParseOptions] = None, *, collect_metrics: Literal[False] = False
+) -> list[Token]: ...
+@overload
+def parse(
+    markdown: str, options: Optional[ParseOptions] = None, *, collect_metrics: Literal[True]
+) -> tuple[list[Token], Metrics]: ...
+@overload
+def tokens_to_html(
+    tokens: Sequence[Token], options: Optional[HtmlOptions] = None, *, collect_metrics: Literal[False] = False
+) -> str: ...
+@overload
+def tokens_to_html(
+    tokens: Sequence[Token], options: Optional[HtmlOptions] = None, *, collect_metrics: Literal[True]
+) -> tuple[str, Metrics]: ...
+def token_to_html(token: Token, options: Optional[HtmlOptions] = None) -> str: ...
+def tokens_to_elements(tokens: Sequence[Token], options: Optional[HtmlOptions] = None) -> list[Element]: ...
+def elements_to_html(elements: Sequence[Element], *, output_style: Literal["html5", "xhtml"] = "html5") -> str: ...
+def parse_inline(text: str, options: Optional[ParseOptions] = None) -> list[Metadata]: ...
+def inline_to_html(text: str, options: Optional[HtmlOptions] = None) -> str: ...
+def tokens_to_markdown(tokens: Sequence[Token], width: Optional[int] = 80) -> str: ...
+def tokens_to_json(tokens: Sequence[Token]) -> str: ...
+def tokens_to_pandoc_json(
+    tokens: Sequence[Token], slug_style: Literal["simple", "github"] = "simple"
+) -> str: ...
+def line_offsets(markdown: str) -> list[int]: ...
+def normalize(tokens: Sequence[Token]) -> list[Token]: ...
+def promote_headings(
+    tokens: Sequence[Token], level: int = 3, max_length: int = 80, endings: str = "?:"
+) -> list[Token]: ...
+def filter_tokens(tokens: Sequence[Token], kind: Union[str, Sequence[str]]) -> list[Token]: ...
+def walk(tokens: Sequence[Token]) -> list[tuple[Token, int, Optional[Token]]]: ...
+def abbreviations(tokens: Sequence[Token]) -> dict[str, str]: ...
+def code_blocks(
+    tokens: Sequence[Token], language: Optional[str] = None, source: Optional[str] = None
+) -> list[CodeBlock]: ...
+@overload
+def extract_code(
+    markdown: str, language: Optional[str] = None, concatenate: Literal[False] = False
+) -> list[CodeBlock]: ...
+@overload
+def extract_code(markdown: str, language: Optional[str] = None, *, concatenate: Literal[True]) -> str: ...
+def number_headings(
+    tokens: Sequence[Token], start_level: int = 1, format: str = "1.2.3"
+) -> list[Token]: ...
+def outline(
+    tokens: Sequence[Token], slug_style: Literal["simple", "github"] = "simple"
+) -> list[OutlineEntry]: ...
+def heading_outline(
+    tokens: Sequence[Token], slug_style: Literal["simple", "github"] = "simple"
+) -> list[tuple[int, str, str]]: ...
+def sections(tokens: Sequence[Token]) -> list[tuple[Token, list[Token]]]: ...
+def anchor_map(
+    tokens: Sequence[Token], slug_style: Literal["simple", "github"] = "simple"
+) -> dict[str, str]: ...
+def title(tokens: Sequence[Token], loose: bool = False) -> Optional[str]: ...
+def check_anchors(
+    tokens: Sequence[Token], slug_style: Literal["simple", "github"] = "simple"
+) -> list[AnchorProblem]: ...
+def check_anchors_many(
+    documents: dict[str, Sequence[Token]], slug_style: Literal["simple", "github"] = "simple"
+) -> list[AnchorProblem]: ...
+def lint(
+    tokens: Sequence[Token],
+    rules: Optional[Sequence[str]] = None,
+    custom: Optional[dict[str, Callable[[list[Token]], Iterable[LintProblem]]]] = None,
+) -> list[LintProblem]: ...
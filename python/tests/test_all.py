@@ -1,6 +1,1864 @@
+import hashlib
+import html
+import json
+import random
+import re
+import threading
+import time
+from concurrent.futures import ThreadPoolExecutor
+from pathlib import Path
+from xml.etree import ElementTree
+
 import pytest
 import md0
 
 
 def test_sum_as_string():
     assert md0.sum_as_string(1, 1) == "2"
+
+
+def test_version_and_capabilities():
+    assert re.fullmatch(r"\d+\.\d+\.\d+.*", md0.__version__)
+
+    capabilities = md0.capabilities()
+    assert isinstance(capabilities, frozenset)
+    flags = set()
+    for options in (md0.ParseOptions(), md0.HtmlOptions()):
+        for name in dir(options):
+            if not name.startswith("_") and isinstance(getattr(options, name), bool):
+                flags.add(name.replace("_", "-"))
+    assert flags - capabilities == {"open-first"}
+
+    # Every capability is a flag or something the parser always does
+    features = {
+        "autolinks": lambda: isinstance(md0.parse("<https://e.com>")[0].metadata[0], md0.Metadata.Link),
+        "angle-bracket-destinations": lambda: md0.parse("[a](<b c>)")[0].metadata[0].url == "b c",
+        "image-sizes": lambda: md0.parse("![a](i.png =10x20)")[0].metadata[0].width == 10,
+        "fence-attributes": lambda: md0.parse("```py title=x\n```")[0].attrs == {"title": "x"},
+        "setext-headings": lambda: md0.parse("Title\n---")[0].kind == "heading",
+        "thematic-break-markers": lambda: md0.parse("***")[0].marker == "*",
+        "github-slugs": lambda: 'id="ab-c"' in md0.tokens_to_html(
+            md0.parse("# A.b c"), md0.HtmlOptions(heading_ids=True, slug_style="github")
+        ),
+    }
+    assert capabilities - flags == set(features)
+    assert all(works() for works in features.values())
+    assert {"tab-width", "max-depth", "slug-style", "output-style", "heading-case", "tables"}.isdisjoint(capabilities)
+
+
+def test_document_edits_match_full_parse():
+    rng = random.Random(0)
+    pieces = ["# Title", "para", "[a](b)", "---", "```", "```py", "\n", "\n\n", " ", "x"]
+    text = "\n".join(rng.choice(pieces) for _ in range(40))
+    document = md0.Document(text)
+
+    for _ in range(1000):
+        start = rng.randint(0, len(text))
+        old_len = rng.randint(0, min(10, len(text) - start))
+        new_text = "".join(rng.choice(pieces) for _ in range(rng.randint(0, 3)))
+
+        document.edit(start, old_len, new_text)
+        text = text[:start] + new_text + text[start + old_len :]
+
+        assert document.text == text
+        assert [(repr(token), token.span) for token in document.tokens] == [
+            (repr(token), token.span) for token in md0.parse(text)
+        ]
+
+
+def test_document_edits_with_definition_lists():
+    rng = random.Random(1)
+    options = md0.ParseOptions(definition_lists=True)
+    pieces = ["Term", ": def", "    more", "text", "# H", "\n", "\n\n", ":", " "]
+    text = "\n".join(rng.choice(pieces) for _ in range(40))
+    document = md0.Document(text, options)
+
+    for _ in range(1000):
+        start = rng.randint(0, len(text))
+        old_len = rng.randint(0, min(10, len(text) - start))
+        new_text = "".join(rng.choice(pieces) for _ in range(rng.randint(0, 3)))
+
+        document.edit(start, old_len, new_text)
+        text = text[:start] + new_text + text[start + old_len :]
+
+        assert [(repr(token), token.span) for token in document.tokens] == [
+            (repr(token), token.span) for token in md0.parse(text, options)
+        ]
+
+
+def test_document_edits_with_abbreviations():
+    rng = random.Random(2)
+    options = md0.ParseOptions(abbreviations=True)
+    pieces = ["*[HTML]: markup", "*[W3C]: consortium", "HTML", "W3C text", "[HTML](u)", "```", "\n", "\n\n", " "]
+    text = "\n".join(rng.choice(pieces) for _ in range(40))
+    document = md0.Document(text, options)
+
+    for _ in range(1000):
+        start = rng.randint(0, len(text))
+        old_len = rng.randint(0, min(10, len(text) - start))
+        new_text = "".join(rng.choice(pieces) for _ in range(rng.randint(0, 3)))
+
+        document.edit(start, old_len, new_text)
+        text = text[:start] + new_text + text[start + old_len :]
+
+        tokens = md0.parse(text, options)
+        assert repr(document.tokens) == repr(tokens)
+        assert document.abbreviations == md0.abbreviations(tokens)
+
+
+def test_stream_parser():
+    chunks = ["# Notes\n\nfirst ", "line\nsecond ", "line\n\n```\ncode\n", "\nmore\n"]
+    parser = md0.StreamParser()
+    drained = []
+    for chunk in chunks:
+        parser.feed(chunk)
+        drained.append(repr(parser.drain()))
+
+    assert drained == ["[]", "[]", '[Heading(1, "Notes"), Paragraph("first line second line", [])]', "[]"]
+    (code,) = parser.finish()
+    assert md0.tokens_to_json([code]) == md0.tokens_to_json(md0.parse("".join(chunks))[2:])
+    assert parser.finish() == [] and parser.drain() == []
+    with pytest.raises(ValueError):
+        parser.feed("late")
+
+    strict = md0.StreamParser(md0.ParseOptions(strict=True, containers=True))
+    strict.feed("text\n\n::: note\nbody\n")
+    with pytest.raises(md0.ParseError) as error:
+        strict.finish()
+    assert (error.value.offset, error.value.code) == (6, "unclosed-container")
+
+    with pytest.raises(ValueError):
+        md0.StreamParser(md0.ParseOptions(abbreviations=True))
+
+
+@pytest.mark.parametrize("opening", ["```\n", "", "$$\n", "::: note\n"])
+def test_stream_parser_takes_linear_time_in_an_open_block(opening):
+    options = md0.ParseOptions(containers=True, math=True, definition_lists=True)
+    parser = md0.StreamParser(options)
+    lines = [opening] + ["some words of text\n"] * 20000
+
+    # Parsing the block again on every line takes minutes
+    started = time.perf_counter()
+    for line in lines:
+        parser.feed(line)
+        assert parser.drain() == []
+    tokens = parser.finish()
+
+    assert time.perf_counter() - started < 5
+    assert md0.tokens_to_json(tokens) == md0.tokens_to_json(md0.parse("".join(lines), options))
+
+
+def test_collect_metrics():
+    markdown = "# Title\n\nSee [a](u) and [b](v).\n\n::: note\n<https://e.com>\n:::\n\n*[HTML]: markup"
+    options = md0.ParseOptions(containers=True, abbreviations=True)
+    tokens, metrics = md0.parse(markdown, options, collect_metrics=True)
+
+    assert repr(tokens) == repr(md0.parse(markdown, options))
+    assert (metrics.operation, metrics.input_bytes, metrics.output_bytes) == ("parse", len(markdown.encode()), None)
+    assert metrics.tokens == {"heading": 1, "paragraph": 2, "container": 1, "abbreviation": 1}
+    assert metrics.metadata == {"link": 3}
+    assert set(metrics.phases) == {"blocks", "abbreviations"}
+    assert 0 <= sum(metrics.phases.values()) <= metrics.seconds
+
+    html, rendered = md0.tokens_to_html(tokens, collect_metrics=True)
+    assert html == md0.tokens_to_html(tokens)
+    assert (rendered.operation, rendered.input_bytes, rendered.output_bytes) == ("render", None, len(html.encode()))
+    assert set(rendered.phases) == {"nodes", "write"}
+
+    exported = rendered.as_dict()
+    assert exported['md0_tokens{kind="paragraph"}'] == 2
+    assert exported["md0_output_bytes"] == len(html.encode())
+    assert set(exported) >= {"md0_render_seconds", 'md0_render_phase_seconds{phase="nodes"}', 'md0_metadata{kind="link"}'}
+    assert "md0_input_bytes" not in exported
+
+
+def test_shared_parser_across_threads():
+    parser = md0.Parser(md0.ParseOptions())
+    renderer = md0.Renderer(md0.HtmlOptions())
+    documents = [f"# Doc {i}\n\nSome [text](#{i}) here.\n\n```\ncode {i}\n```" for i in range(64)]
+    expected = [md0.tokens_to_html(md0.parse(document)) for document in documents]
+
+    def work(i):
+        return renderer.render(parser.parse(documents[i % len(documents)]))
+
+    with ThreadPoolExecutor(max_workers=8) as pool:
+        results = list(pool.map(work, range(2000)))
+
+    assert results == [expected[i % len(documents)] for i in range(2000)]
+
+
+def test_rendering_is_deterministic():
+    fixtures = sorted((Path(__file__).parent / "fixtures").glob("*.md"))
+    markdown = "\n\n".join(path.read_text(encoding="utf-8") for path in fixtures) * 4
+    parse_options = md0.ParseOptions(abbreviations=True, definition_lists=True, containers=True)
+    html_options = md0.HtmlOptions(heading_ids=True, slug_style="github", figures=True, auto_dir=True)
+    renderer = md0.Renderer(html_options)
+
+    def digest(_):
+        html = renderer.render(md0.parse(markdown, parse_options))
+        return hashlib.sha256(html.encode()).hexdigest()
+
+    serial = {digest(i) for i in range(100)}
+    with ThreadPoolExecutor(max_workers=8) as pool:
+        parallel = set(pool.map(digest, range(100)))
+    elements = md0.elements_to_html(md0.tokens_to_elements(md0.parse(markdown, parse_options), html_options))
+
+    assert len(serial) == 1
+    assert parallel == serial == {hashlib.sha256(elements.encode()).hexdigest()}
+
+
+def test_cached_parser():
+    parser = md0.CachedParser(2)
+    first = parser.parse("# One")
+    assert repr(parser.parse("# One")) == repr(first) == repr(md0.parse("# One"))
+    assert parser.stats() == {"hits": 1, "misses": 1, "size": 1, "capacity": 2}
+
+    parser.parse("two")
+    parser.parse("# One")
+    parser.parse("three")  # drops "two", the least recently used
+    parser.parse("# One")
+    parser.parse("two")
+    assert parser.stats() == {"hits": 3, "misses": 4, "size": 2, "capacity": 2}
+
+    parser.clear()
+    assert parser.stats() == {"hits": 0, "misses": 0, "size": 0, "capacity": 2}
+    with pytest.raises(ValueError):
+        md0.CachedParser(0)
+
+
+BLOCKS = {
+    "heading": ("# H", ['Heading(1, "H")']),
+    "paragraph": ("text", ['Paragraph("text", [])']),
+    "rule": ("---", ["HorizontalRule"]),
+    "fence": ("```\ncode\n```", ['Code("", "code\\n")']),
+}
+
+
+@pytest.mark.parametrize("first", list(BLOCKS))
+@pytest.mark.parametrize("second", list(BLOCKS))
+@pytest.mark.parametrize("separator", ["\n", "\n\n"])
+def test_block_adjacency(first, second, separator):
+    (first_source, first_tokens), (second_source, second_tokens) = BLOCKS[first], BLOCKS[second]
+    expected = first_tokens + second_tokens
+
+    if separator == "\n" and first == "paragraph":
+        if second == "paragraph":
+            expected = ['Paragraph("text text", [])']
+        elif second == "rule":
+            expected = ['Heading(1, "text")']
+
+    tokens = md0.parse(first_source + separator + second_source)
+
+    assert [repr(token) for token in tokens] == expected
+
+
+def test_heading_directly_followed_by_paragraph():
+    tokens = md0.parse("# Title\nbody text\nmore")
+
+    assert repr(tokens) == '[Heading(1, "Title"), Paragraph("body text more", [])]'
+    assert [token.span for token in tokens] == [(0, 7), (8, 22)]
+
+
+@pytest.mark.parametrize("marker", ["#", "-", "+", "*", "`", ">", "="])
+def test_escaped_block_marker_at_line_start(marker):
+    tokens = md0.parse(f"\\{marker} not a block")
+
+    assert [type(token).__name__ for token in tokens] == ["Token_Paragraph"]
+    assert md0.tokens_to_html(tokens) == f"<p>{html.escape(marker, quote=False)} not a block</p>"
+
+
+def test_heading_trailing_whitespace_is_trimmed():
+    assert md0.parse("## Title   ")[0].content == "Title"
+    assert md0.parse("Title  \n---")[0].content == "Title"
+
+
+def test_lenient_headings():
+    options = md0.ParseOptions(lenient_headings=True)
+
+    def parse(markdown):
+        return [repr(token) for token in md0.parse(markdown, options)]
+
+    assert parse("\uff03 标题\n\n\uff03\uff03\u3000小节") == ['Heading(1, "标题")', 'Heading(2, "小节")']
+    assert parse("#标题\n\n##見出し") == ['Heading(1, "标题")', 'Heading(2, "見出し")']
+    assert parse("== Section ==\n\n=== Sub") == ['Heading(2, "Section")', 'Heading(3, "Sub")']
+    assert md0.parse("\uff03 标题", options)[0].span == (0, 10)
+
+    # Hashtags and stray markers stay text
+    assert parse("#tag and #标题") == ['Paragraph("#tag and #标题", [])']
+    assert parse("#话题# 今天") == ['Paragraph("#话题# 今天", [])']
+    assert parse("\uff03tag") == ['Paragraph("\uff03tag", [])']
+    assert parse("= =\n\n==") == ['Paragraph("= =", [])', 'Paragraph("==", [])']
+    # Only the forms with a space after the hashes interrupt a paragraph
+    assert parse("text\n#标签\n== x ==") == ['Paragraph("text #标签 == x ==", [])']
+    assert parse("text\n\uff03 标题") == ['Paragraph("text", [])', 'Heading(1, "标题")']
+
+    for markdown in ("\uff03 标题", "#标题", "== Section =="):
+        assert md0.parse(markdown)[0].kind == "paragraph"
+    # A paragraph that starts with a full-width hash is written so it stays one
+    (paragraph,) = md0.parse("    \uff03 not a heading", options)
+    written = md0.tokens_to_markdown([paragraph])
+    assert md0.tokens_to_html(md0.parse(written, options)) == "<p>\uff03 not a heading</p>"
+
+
+def test_heading_whitespace_is_collapsed():
+    assert md0.parse("##   Spaced   Title  ")[0].content == "Spaced Title"
+    assert md0.parse("Spaced \t Title\n---")[0].content == "Spaced Title"
+    (definitions,) = md0.parse("Spaced   term\n: its   definition", md0.ParseOptions(definition_lists=True))
+    assert definitions.items == [("Spaced term", [("its   definition", [])])]
+
+
+def test_paragraph_lines_are_trimmed_before_metadata():
+    markdown = "   A wrapped   sentence with\n    a [link](https://example.com)  \n  at the end"
+    (paragraph,) = md0.parse(markdown)
+    (link,) = paragraph._1
+
+    assert paragraph._0 == "A wrapped   sentence with a [link](https://example.com) at the end"
+    assert paragraph._0[link.location[0] : link.location[1]] == "[link](https://example.com)"
+
+
+def test_collapse_whitespace_keeps_metadata_offsets():
+    options = md0.ParseOptions(collapse_whitespace=True)
+    (paragraph,) = md0.parse("a    b\t\t[c](d)    e", options)
+    (link,) = paragraph._1
+
+    assert paragraph._0 == "a b [c](d) e"
+    assert paragraph._0[link.location[0] : link.location[1]] == "[c](d)"
+
+
+def test_untrimmed_lines_are_kept_as_written():
+    options = md0.ParseOptions(trim_lines=False)
+
+    assert md0.parse("  indented\n  lines  ", options)[0]._0 == "  indented   lines  "
+
+
+def test_code_language_class():
+    tokens = md0.parse("```JS\nx\n```")
+
+    assert md0.tokens_to_html(tokens) == '<pre><code class="language-JS">x\n</code></pre>'
+    assert md0.tokens_to_html(tokens, md0.HtmlOptions(normalize_languages=True)) == (
+        '<pre><code class="language-javascript">x\n</code></pre>'
+    )
+    assert tokens[0].language == "JS"
+
+
+def test_unknown_language_is_only_lowercased():
+    tokens = md0.parse("```Zig\nx\n```")
+
+    assert 'class="language-zig"' in md0.tokens_to_html(tokens, md0.HtmlOptions(normalize_languages=True))
+
+
+def test_concurrent_parsing_from_many_threads():
+    # On a free-threaded interpreter these threads really run in parallel.
+    markdown = "\n\n".join(f"# Section {i}\n\nText with a [link](#{i})." for i in range(200))
+    expected = repr(md0.parse(markdown))
+    barrier = threading.Barrier(8)
+    results = []
+
+    def work():
+        barrier.wait()
+        results.append(repr(md0.parse(markdown)))
+
+    threads = [threading.Thread(target=work) for _ in range(8)]
+    for thread in threads:
+        thread.start()
+    for thread in threads:
+        thread.join()
+
+    assert results == [expected] * 8
+
+
+def test_tokens_are_immutable():
+    (token,) = md0.parse("# Title")
+
+    with pytest.raises(AttributeError):
+        token.content = "Other"
+
+
+def test_raw_is_the_source_slice():
+    markdown = "Intro  line\n  wrapped\n\n## Heading  \n---\n\n```py\nx = 1\n```\n\nSetext\n---"
+    tokens = md0.parse(markdown, md0.ParseOptions(keep_raw=True))
+
+    assert [token.raw for token in tokens] == [
+        "Intro  line\n  wrapped",
+        "## Heading  ",
+        "---",
+        "```py\nx = 1\n```",
+        "Setext\n---",
+    ]
+    for token in tokens:
+        start, end = token.span
+        assert markdown[start:end] == token.raw
+
+
+def test_raw_is_not_kept_by_default():
+    assert md0.parse("# Title")[0].raw is None
+
+
+def test_number_headings():
+    tokens = md0.parse("# Intro\n\n## Scope\n\n#### Deep\n\n## Terms\n\n# Usage\n\n## Setup")
+    numbered = md0.number_headings(tokens)
+
+    assert [(t.number, t.content) for t in numbered] == [
+        ("1", "1 Intro"),
+        ("1.1", "1.1 Scope"),
+        ("1.1.0.1", "1.1.0.1 Deep"),
+        ("1.2", "1.2 Terms"),
+        ("2", "2 Usage"),
+        ("2.1", "2.1 Setup"),
+    ]
+    assert all(t.number is None for t in tokens)
+
+
+def test_number_headings_start_level_and_format():
+    tokens = md0.parse("# Title\n\n## A\n\n### B\n\n# Appendix\n\n## C")
+    numbered = md0.number_headings(tokens, start_level=2, format="1-2.")
+
+    assert [t.content for t in numbered] == ["Title", "1. A", "1-1. B", "Appendix", "1. C"]
+
+    with pytest.raises(ValueError):
+        md0.number_headings(tokens, format="a.b")
+    with pytest.raises(ValueError):
+        md0.number_headings(tokens, start_level=7)
+
+
+def test_outline():
+    markdown = "# Intro\n\ntext\n\n## Setup\n\n## Setup\n\n# Usage"
+    (intro, usage) = md0.outline(md0.number_headings(md0.parse(markdown)))
+
+    assert (intro.number, intro.slug, intro.span) == ("1", "1-intro", (0, 7))
+    assert [(c.content, c.slug) for c in intro.children] == [
+        ("1.1 Setup", "1-1-setup"),
+        ("1.2 Setup", "1-2-setup"),
+    ]
+    assert (usage.slug, usage.children) == ("2-usage", [])
+    assert [e.slug for e in md0.outline(md0.parse("# A\n\n# A\n\n# A"))] == ["a", "a-1", "a-2"]
+
+
+def test_title():
+    tokens = md0.parse("Intro text\n\n# The [md0](https://e.com) \\*guide\\*\n\n## Usage\n\n# The md0 *guide*")
+
+    assert md0.title(tokens) == "The md0 *guide*"
+    assert md0.title(md0.parse("## Usage\n\n# Later")) is None
+    assert md0.title(md0.parse("## Usage\n\n# Later"), loose=True) == "Usage"
+    assert md0.title(md0.parse("text")) is None
+
+    options = md0.HtmlOptions(strip_title=True, heading_ids=True)
+    assert md0.tokens_to_html(md0.parse("Intro\n\n# Guide\n\n## Usage\n\n# Guide"), options) == (
+        '<p>Intro</p>\n<h2 id="usage">Usage</h2>\n<h1 id="guide-1">Guide</h1>'
+    )
+    assert md0.tokens_to_html(md0.parse("## Usage"), options) == '<h2 id="usage">Usage</h2>'
+
+
+def test_heading_outline():
+    tokens = md0.parse("# Setup\n\n## Install\n\n# Setup\n\n## Install\n\ntext\n\n### Über uns")
+
+    assert md0.heading_outline(tokens) == [
+        (1, "Setup", "setup"),
+        (2, "Install", "install"),
+        (1, "Setup", "setup-1"),
+        (2, "Install", "install-1"),
+        (3, "Über uns", "über-uns"),
+    ]
+    rendered = md0.tokens_to_html(tokens, md0.HtmlOptions(heading_ids=True))
+    assert re.findall(r' id="([^"]*)"', rendered) == [slug for _, _, slug in md0.heading_outline(tokens)]
+    with pytest.raises(ValueError):
+        md0.heading_outline(tokens, slug_style="gitlab")
+
+
+def test_sections():
+    markdown = "Preamble\n\n# One\n\na\n\n## One.1\n\nb\n\n### One.1.1\n\nc\n\n## One.2\n\n# Two\n\nd"
+    sections = md0.sections(md0.parse(markdown))
+
+    assert [(heading.content, repr(body)) for heading, body in sections] == [
+        (
+            "One",
+            '[Paragraph("a", []), Heading(2, "One.1"), Paragraph("b", []), '
+            'Heading(3, "One.1.1"), Paragraph("c", []), Heading(2, "One.2")]',
+        ),
+        ("One.1", '[Paragraph("b", []), Heading(3, "One.1.1"), Paragraph("c", [])]'),
+        ("One.1.1", '[Paragraph("c", [])]'),
+        ("One.2", "[]"),
+        ("Two", '[Paragraph("d", [])]'),
+    ]
+
+    # The body of a section groups into its subsections
+    (_, one), *_ = sections
+    assert [heading.content for heading, _ in md0.sections(one)] == ["One.1", "One.1.1", "One.2"]
+    assert md0.sections(md0.parse("no headings")) == []
+
+
+def test_cross_references():
+    tokens = md0.parse("# Getting Started\n\n## Über uns\n\nsee [](#getting-started), [](#über-uns) and [](#nope)")
+
+    assert md0.anchor_map(tokens) == {"getting-started": "Getting Started", "über-uns": "Über uns"}
+    assert md0.anchor_map(tokens, slug_style="github") == md0.anchor_map(tokens)
+    assert md0.tokens_to_html(tokens).split("\n")[2] == (
+        '<p>see <a href="#getting-started">Getting Started</a>, '
+        '<a href="#%C3%BCber-uns">Über uns</a> and <a href="#nope">#nope</a></p>'
+    )
+    problems = md0.lint(tokens, rules=["cross-reference"])
+    assert [(problem.location, problem.message) for problem in problems] == [
+        ((45, 54), "link to #nope has no label and no heading to take it from")
+    ]
+
+
+def test_preserve_delimiters():
+    options = md0.ParseOptions(preserve_delimiters=[("{{", "}}"), ("{%", "%}")])
+    markdown = "{% if user %}Hi {{ user.name | default('[you](x)') }} & <b>{% endif %}\n\nsee {{ unclosed [a](b)"
+    tokens = md0.parse(markdown, options)
+
+    assert all(isinstance(metadata, md0.Metadata.Raw) for metadata in tokens[0].metadata)
+    assert [metadata.location for metadata in tokens[0].metadata] == [(0, 13), (16, 53), (59, 70)]
+    assert md0.tokens_to_html(tokens) == (
+        "<p>{% if user %}Hi {{ user.name | default('[you](x)') }} &amp; &lt;b&gt;{% endif %}</p>\n"
+        "<p>see {{ unclosed [a](b)</p>"
+    )
+    assert md0.tokens_to_markdown(tokens, width=20) == (
+        "{% if user %}Hi\n{{ user.name | default('[you](x)') }}\n& <b>{% endif %}\n\nsee\n{{ unclosed [a](b)"
+    )
+    assert [problem.location for problem in md0.lint(tokens, rules=["unclosed-delimiter"])] == [(4, 22)]
+    assert repr(tokens[1].metadata) == "[Raw((4, 22), False)]"
+    assert md0.parse(markdown)[0].metadata[0].url == "x"
+    with pytest.raises(ValueError):
+        md0.ParseOptions(preserve_delimiters=[("{{", "")])
+
+
+def test_strict_parse_errors():
+    strict = md0.ParseOptions(strict=True, containers=True)
+    fenced = "text\n\n  ```py\nx = 1\n"
+
+    with pytest.raises(md0.ParseError) as error:
+        md0.parse(fenced, strict)
+    assert (error.value.offset, error.value.code) == (6, "unclosed-fence")
+    assert fenced[error.value.offset :].startswith("  ```")
+    assert str(error.value) == "code fence at byte 6 is never closed"
+    assert isinstance(error.value, ValueError)
+    assert md0.parse(fenced)[1].content == "x = 1\n"
+
+    with pytest.raises(md0.ParseError) as error:
+        md0.parse("# Title\n\n:::note\n:::tip\ninside\n:::\n", strict)
+    assert (error.value.offset, error.value.code) == (9, "unclosed-container")
+
+    closed = "```\n```\n\n:::note\n:::tip\ninside\n:::\n:::\n\n~~~~\n~~~\n~~~~"
+    assert len(md0.parse(closed, strict)) == 3
+
+
+def test_strict_document():
+    strict = md0.ParseOptions(strict=True)
+
+    with pytest.raises(md0.ParseError) as error:
+        md0.Document("text\n\n```py\nx = 1\n", strict)
+    assert (error.value.offset, error.value.code) == (6, "unclosed-fence")
+
+    document = md0.Document("text\n\n```py\nx = 1\n```\n\nafter\n", strict)
+    before = [repr(token) for token in document.tokens]
+    with pytest.raises(md0.ParseError) as error:
+        document.edit(len("text\n\n```py\nx = 1\n"), 3, "")
+    assert error.value.code == "unclosed-fence"
+    assert (document.text, [repr(token) for token in document.tokens]) == (
+        "text\n\n```py\nx = 1\n```\n\nafter\n",
+        before,
+    )
+
+    document.edit(0, 4, "new")
+    assert repr(document.tokens[0]) == repr(md0.parse("new")[0])
+
+
+def test_promote_headings():
+    markdown = (
+        "**How do I install it?**\n\nRun pip.\n\nRequirements:\n\n__Bold__ and more\n\n"
+        "**See [docs](x)?**\n\nWhy\nthis?\n\n" + "Long " * 20 + "question?"
+    )
+    tokens = md0.promote_headings(md0.parse(markdown))
+
+    assert [(t.kind, t.level if t.kind == "heading" else None) for t in tokens] == [
+        ("heading", 3),
+        ("paragraph", None),
+        ("heading", 3),
+        ("paragraph", None),
+        ("paragraph", None),
+        ("paragraph", None),
+        ("paragraph", None),
+    ]
+    assert (tokens[0].content, tokens[0].span) == ("How do I install it?", md0.parse(markdown)[0].span)
+    assert tokens[2].content == "Requirements:"
+    assert md0.heading_outline(tokens) == [(3, "How do I install it?", "how-do-i-install-it"), (3, "Requirements:", "requirements")]
+
+    promoted = md0.promote_headings(md0.parse(markdown), level=2, max_length=200, endings="")
+    assert [t.content for t in promoted if t.kind == "heading"] == ["How do I install it?"]
+    assert promoted[0].level == 2
+    assert len([t for t in md0.promote_headings(md0.parse(markdown), max_length=200) if t.kind == "heading"]) == 3
+    with pytest.raises(ValueError):
+        md0.promote_headings(tokens, level=7)
+
+
+def test_token_len():
+    markdown = "# Tête\n\nA short paragraph.\n\n---\n\n```py\nx = 1\n```\n\n*[HTML]: Hyper Text\n\nTerm\n: Definition"
+    heading, paragraph, rule, code, abbreviation, definitions = md0.parse(
+        markdown, md0.ParseOptions(abbreviations=True, definition_lists=True)
+    )
+
+    assert len(heading) == 4
+    assert len(paragraph) == len("A short paragraph.")
+    assert len(rule) == 0
+    assert len(code) == len(code.content)
+    assert len(abbreviation) == len("HTML") + len("Hyper Text")
+    assert len(definitions) == len("Term") + len("Definition")
+
+    (container,) = md0.parse("::: note\nOne\n\n# Two\n:::", md0.ParseOptions(containers=True))
+    assert len(container) == 6
+    assert [t[0] for t in md0.parse("Hi\n\nA longer one") if len(t) > 2] == ["A longer one"]
+
+
+def test_parse_inline():
+    text = "see [docs](https://e.com) and ![logo](l.png =32x32) <https://x.io>"
+
+    assert repr(md0.parse_inline(text)) == repr(md0.parse(text)[0].metadata)
+    link, image = md0.parse_inline(text)[:2]
+    assert text[slice(*link.location)] == "[docs](https://e.com)"
+    assert (image.url, image.width) == ("l.png", 32)
+    assert md0.parse_inline("me@x.io") == []
+    assert md0.parse_inline("me@x.io", md0.ParseOptions(autolink_emails=True))[0].url == "mailto:me@x.io"
+
+    fragment = "A <b> & [link](u?a=1&b=2) \\* &copy;"
+    assert md0.inline_to_html(fragment) == 'A &lt;b&gt; &amp; <a href="u?a=1&amp;b=2">link</a> * \u00a9'
+    assert md0.tokens_to_html(md0.parse(fragment)) == f"<p>{md0.inline_to_html(fragment)}</p>"
+    assert md0.inline_to_html("<b>x</b> [l](u)", md0.HtmlOptions(escape=False)) == '<b>x</b> <a href="u">l</a>'
+    assert md0.inline_to_html("# not a heading") == "# not a heading"
+
+
+@pytest.mark.parametrize(
+    "markdown, offsets",
+    [
+        ("# a\nb\n\nc\n", [0, 4, 6, 7, 9]),
+        ("# a\r\nb\r\n\r\nc\r\n", [0, 5, 8, 10, 13]),
+        ("a\nbc", [0, 2]),
+        ("é\nb", [0, 3]),
+        ("", [0]),
+    ],
+)
+def test_line_offsets(markdown, offsets):
+    assert md0.line_offsets(markdown) == offsets
+
+
+def test_line_offsets_locate_token_spans():
+    markdown = "intro\r\n\r\n## Title\r\ntext"
+    offsets = md0.line_offsets(markdown)
+    heading = md0.parse(markdown)[1]
+
+    assert offsets.index(heading.span[0]) == 2
+    assert markdown.encode()[offsets[2] : offsets[3]] == b"## Title\r\n"
+
+
+def test_source_spans():
+    text = "# Notes\n\nA sentence   that is\n\t  wrapped over  \n  three lines, [then a](https://e.com)\nlink and ![an](i.png)."
+    options = md0.ParseOptions(source_spans=True, collapse_whitespace=True)
+    _, paragraph = md0.parse(text, options)
+
+    link = paragraph.metadata[0]
+    image = next(m for m in paragraph.metadata if isinstance(m, md0.Metadata.Image))
+    assert paragraph._0[slice(*link.location)] == "[then a](https://e.com)"
+    assert text[slice(*link.span)] == "[then a](https://e.com)"
+    assert text[slice(*image.span)] == "![an](i.png)"
+    assert md0.parse(text)[1].metadata[0].span is None
+
+    options = md0.ParseOptions(source_spans=True, definition_lists=True)
+    definitions = md0.parse("Term\n:   See\n    [a](b)\n\n    and [c](d)", options)
+    _, metadata = definitions[0].items[0][1][0]
+    assert [m.span for m in metadata] == [(17, 23), (33, 39)]
+
+    document = md0.Document(text, md0.ParseOptions(source_spans=True))
+    document.edit(0, 7, "# Longer notes")
+    assert document.text[slice(*document.tokens[1].metadata[0].span)] == "[then a](https://e.com)"
+
+
+def test_github_slugs():
+    pairs = json.loads(Path(__file__).with_name("github_slugs.json").read_text(encoding="utf-8"))
+    tokens = md0.parse("\n\n".join(f"## {heading}" for heading, _ in pairs))
+    expected = [anchor for _, anchor in pairs]
+
+    assert [entry.slug for entry in md0.outline(tokens, slug_style="github")] == expected
+    rendered = md0.tokens_to_html(tokens, md0.HtmlOptions(heading_ids=True, slug_style="github"))
+    assert re.findall(r'<h2 id="([^"]*)">', rendered) == expected
+
+
+def test_heading_ids():
+    tokens = md0.parse("# Hello, World!\n\n# Hello, World!")
+    options = md0.HtmlOptions(heading_ids=True, auto_dir=True)
+
+    assert md0.tokens_to_html(tokens, options) == (
+        '<h1 id="hello-world" dir="auto">Hello, World!</h1>\n'
+        '<h1 id="hello-world-1" dir="auto">Hello, World!</h1>'
+    )
+    assert md0.HtmlOptions().slug_style == "simple"
+    assert "id=" not in md0.tokens_to_html(tokens)
+    with pytest.raises(ValueError):
+        md0.HtmlOptions(slug_style="gitlab")
+    with pytest.raises(ValueError):
+        md0.outline(tokens, slug_style="gitlab")
+
+
+def test_heading_case():
+    tokens = md0.parse(
+        "# getting Started With the HTML API\n\n"
+        "## use `fooBar()` in [The Docs Page](https://E.com/Some_Path) or <https://X.io/A>\n\n"
+        "## straße and ıstanbul"
+    )
+
+    def headings(**options):
+        html = md0.tokens_to_html(tokens, md0.HtmlOptions(heading_ids=True, **options))
+        return re.findall(r'<h\d id="([^"]*)">(.*)</h\d>', html)
+
+    ids = [id for id, _ in headings()]
+    assert [text for _, text in headings()] == [t.content.replace("<", "&lt;").replace(">", "&gt;") for t in tokens]
+    assert [text for _, text in headings(heading_case="sentence")] == [
+        "Getting started with the HTML API",
+        "Use `fooBar()` in [the docs page](https://E.com/Some_Path) or &lt;https://X.io/A&gt;",
+        "Straße and ıstanbul",
+    ]
+    assert [text for _, text in headings(heading_case="title")] == [
+        "Getting Started with the HTML API",
+        "Use `fooBar()` in [the Docs Page](https://E.com/Some_Path) or &lt;https://X.io/A&gt;",
+        "Straße and Istanbul",
+    ]
+    upper = headings(heading_case="upper")
+    assert upper[2] == ("straße-and-ıstanbul", "STRASSE AND ISTANBUL")
+    assert "USE `fooBar()` IN [THE DOCS PAGE](https://E.com/Some_Path)" in upper[1][1]
+    assert [id for id, _ in upper] == ids
+    assert [id for id, _ in headings(heading_case="upper", recompute_slugs=True)][2] == "strasse-and-istanbul"
+
+    assert md0.HtmlOptions().heading_case == "preserve"
+    with pytest.raises(ValueError):
+        md0.HtmlOptions(heading_case="lower")
+
+
+def test_empty_slugs_fall_back_to_section():
+    tokens = md0.parse("## 🎉\n\n## 🎉\n\n#\n\n## ???\n\n[back](#section-2)")
+    assert [t.content for t in tokens if t.kind == "heading"] == ["🎉", "🎉", "", "???"]
+
+    for style in ("simple", "github"):
+        options = md0.HtmlOptions(heading_ids=True, slug_style=style)
+        ids = re.findall(r' id="([^"]*)"', md0.tokens_to_html(tokens, options))
+
+        assert ids == ["section", "section-1", "section-2", "section-3"]
+        assert [slug for _, _, slug in md0.heading_outline(tokens, slug_style=style)] == ids
+        assert md0.check_anchors(tokens, slug_style=style) == []
+
+    assert md0.tokens_to_html(md0.parse("#")) == "<h1></h1>"
+    assert md0.tokens_to_markdown(md0.parse("##  ")) == "##"
+
+
+def test_check_anchors():
+    tokens = md0.parse(
+        "# Configuration\n\n## Über uns\n\n"
+        "See [config](#configuration), [old](#config), [us](#über-uns), [too](#%C3%BCber-uns), "
+        "[top](#top), [site](https://e.com/#nope) and [file](other.md#x)."
+    )
+    (problem,) = md0.check_anchors(tokens)
+
+    assert (problem.label, problem.url, problem.path) == ("old", "#config", None)
+    assert problem.span == tokens[2].span
+    assert problem.location == (30, 44)
+    assert problem.message == 'no heading with the id "config"'
+    assert md0.check_anchors(md0.parse("# A.B\n\n[x](#ab)"), slug_style="github") == []
+    assert len(md0.check_anchors(md0.parse("# A.B\n\n[x](#ab)"))) == 1
+
+
+def test_check_anchors_many():
+    documents = {
+        "index.md": md0.parse("# Index\n\n[setup](guide/setup.md#install) [bad](guide/setup.md#missing)"),
+        "guide/setup.md": md0.parse("# Install\n\n[back](../index.md#index) [self](#gone) [ext](../README.md#x)"),
+    }
+    problems = md0.check_anchors_many(documents)
+
+    assert [(p.path, p.url, p.message) for p in problems] == [
+        ("guide/setup.md", "#gone", 'no heading with the id "gone"'),
+        ("index.md", "guide/setup.md#missing", 'no heading with the id "missing" in guide/setup.md'),
+    ]
+
+
+GUIDE = "Intro\n\n```python\nprint(1)\n```\n\n```PY title=demo.py\nprint(2)\n```\n\n```sh\nls\n```\n"
+
+
+def test_lint():
+    tokens = md0.parse(
+        "# Intro\n\n### Setup:\n\nSee https://e.com/a, <https://e.com/b> and ![](x.png).\n\n"
+        "# Intro\n\n```\ncode\n```\n\n" + "word " * 250
+    )
+    problems = md0.lint(tokens)
+
+    assert [(problem.rule, problem.severity) for problem in problems] == [
+        ("heading-increment", "warning"),
+        ("heading-punctuation", "info"),
+        ("bare-url", "info"),
+        ("image-alt", "warning"),
+        ("duplicate-heading", "warning"),
+        ("code-language", "info"),
+        ("long-paragraph", "info"),
+    ]
+    assert problems[0].message == "h1 is followed by h3"
+    assert (problems[2].span, problems[2].location) == (tokens[2].span, (4, 19))
+    assert problems[2].message == "bare URL https://e.com/a, write it as <https://e.com/a>"
+    assert problems[4].message == 'h1 "Intro" repeats the one at byte 0'
+
+    assert [problem.rule for problem in md0.lint(tokens, rules=["bare-url", "image-alt"])] == ["bare-url", "image-alt"]
+    assert len(md0.lint(tokens, rules=["-long-paragraph", "-code-language"])) == 5
+    with pytest.raises(ValueError):
+        md0.lint(tokens, rules=["no-such-rule"])
+
+
+def test_custom_lint_rules():
+    def todo(tokens):
+        for token in tokens:
+            if token.kind == "paragraph" and "TODO" in token._0:
+                yield md0.LintProblem("todo", "leftover TODO", token.span, severity="error")
+
+    tokens = md0.parse("# A.\n\nTODO: write")
+    problems = md0.lint(tokens, custom={"todo": todo})
+
+    assert [(problem.rule, problem.severity) for problem in problems] == [("heading-punctuation", "info"), ("todo", "error")]
+    assert [problem.rule for problem in md0.lint(tokens, rules=["todo"], custom={"todo": todo})] == ["todo"]
+    with pytest.raises(ValueError):
+        md0.lint(tokens, custom={"bare-url": todo})
+    with pytest.raises(ValueError):
+        md0.LintProblem("todo", "bad", (0, 0), severity="fatal")
+
+
+def test_code_blocks_filter_by_language():
+    tokens = md0.parse(GUIDE)
+    blocks = md0.code_blocks(tokens, "python", GUIDE)
+
+    assert [(b.language, b.info, b.content, b.lines) for b in blocks] == [
+        ("python", "python", "print(1)\n", (3, 5)),
+        ("PY", "PY title=demo.py", "print(2)\n", (7, 9)),
+    ]
+    assert [b.language for b in md0.code_blocks(tokens)] == ["python", "PY", "sh"]
+    assert md0.code_blocks(tokens)[0].lines is None
+
+
+def test_extract_code():
+    assert [b.content for b in md0.extract_code(GUIDE, "sh")] == ["ls\n"]
+    assert md0.extract_code(GUIDE, "py", concatenate=True) == "# line 4\nprint(1)\n# line 8\nprint(2)\n"
+
+
+def test_auto_dir():
+    tokens = md0.parse("# שלום\n\nمرحبا\n\n---")
+
+    assert md0.tokens_to_html(tokens, md0.HtmlOptions(auto_dir=True)) == (
+        '<h1 dir="auto">שלום</h1>\n<p dir="auto">مرحبا</p>\n<hr>'
+    )
+    assert "dir=" not in md0.tokens_to_html(tokens)
+
+
+def test_definition_lists():
+    options = md0.ParseOptions(definition_lists=True)
+    markdown = "Intro\nApple\n: A fruit\n: A [company](https://apple.com)\n\nOrange\n: Citrus\n\nAfter"
+    intro, definitions, after = md0.parse(markdown, options)
+
+    assert (intro._0, after._0) == ("Intro", "After")
+    assert [(term, [text for text, _ in defs]) for term, defs in definitions.items] == [
+        ("Apple", ["A fruit", "A [company](https://apple.com)"]),
+        ("Orange", ["Citrus"]),
+    ]
+    ((text, (link,)),) = [d for d in definitions.items[0][1] if d[1]]
+    assert text[link.location[0] : link.location[1]] == "[company](https://apple.com)"
+    assert md0.tokens_to_html([definitions]) == (
+        '<dl><dt>Apple</dt><dd>A fruit</dd><dd>A <a href="https://apple.com">company</a></dd>'
+        "<dt>Orange</dt><dd>Citrus</dd></dl>"
+    )
+    assert repr(md0.parse("Apple\n: A fruit")) == '[Paragraph("Apple : A fruit", [])]'
+
+
+def test_definition_with_several_paragraphs():
+    options = md0.ParseOptions(definition_lists=True)
+    tokens = md0.parse("Term\n: first\n  line\n\n  second\n\n# Next", options)
+
+    assert repr(tokens) == '[DefinitionList([("Term", ["first line\\n\\nsecond"])]), Heading(1, "Next")]'
+    assert md0.tokens_to_html(tokens[:1]) == "<dl><dt>Term</dt><dd><p>first line</p><p>second</p></dd></dl>"
+
+
+def test_abbreviations():
+    options = md0.ParseOptions(abbreviations=True)
+    markdown = "# HTML\n\nHTML5 is not XHTML, and `HTML` is code.\n*[HTML]: Hyper \"Text\"\n*[HTML5]: HTML 5"
+    tokens = md0.parse(markdown, options)
+
+    assert md0.abbreviations(tokens) == {"HTML": 'Hyper "Text"', "HTML5": "HTML 5"}
+    assert md0.Document(markdown, options).abbreviations == md0.abbreviations(tokens)
+    assert md0.tokens_to_html(tokens) == (
+        '<h1><abbr title="Hyper &quot;Text&quot;">HTML</abbr></h1>\n'
+        '<p><abbr title="HTML 5">HTML5</abbr> is not XHTML, and `HTML` is code.</p>'
+    )
+    assert md0.abbreviations(md0.parse(markdown)) == {}
+
+
+def markdown(text, **kwargs):
+    return md0.tokens_to_markdown(md0.parse(text), **kwargs)
+
+
+def test_tokens_to_markdown():
+    text = 'Title\n---\n\n***\n\n````py title="x"\n```\n````\n\n*[x]: y\n\nsome text'
+
+    # Abbreviations are off, so the definition is paragraph text
+    assert markdown(text, width=None) == (
+        '# Title\n\n***\n\n````py title="x"\n```\n````\n\n\\*[x]: y\n\nsome text'
+    )
+    assert markdown("  # not a heading\n\n4) not a list") == "\\# not a heading\n\n4\\) not a list"
+    assert markdown("a\n:: b") == "a :: b"
+    with pytest.raises(ValueError):
+        md0.tokens_to_markdown([], width=0)
+
+
+def test_wrapping_cjk_text():
+    # Every character is two columns wide
+    assert markdown("漢字テキスト 漢字テキスト 漢字テキスト かな", width=20) == (
+        "漢字テキスト\n漢字テキスト\n漢字テキスト かな"
+    )
+
+
+def test_wrapping_keeps_unbreakable_tokens_whole():
+    assert markdown("see https://example.com/a/really/long/url/that/cannot/break and more", width=20) == (
+        "see\nhttps://example.com/a/really/long/url/that/cannot/break\nand more"
+    )
+    assert markdown("a `code span with spaces` b", width=10) == "a\n`code span with spaces`\nb"
+    assert markdown("words - not a list 1. nor this # heading", width=8) == (
+        "words -\nnot a\nlist 1.\nnor\nthis #\nheading"
+    )
+
+
+def test_wrapping_never_breaks_after_a_backslash():
+    text = "Open C:\\Users\\ and then the folder\n"
+    assert markdown(text, width=16) == "Open\nC:\\Users\\ and\nthen the folder"
+    assert md0.tokens_to_html(md0.parse(markdown(text, width=16))) == md0.tokens_to_html(md0.parse(text))
+
+    # An escaped backslash is text, and a line may end in one
+    assert markdown("Open C:\\Users\\\\ and then the folder", width=16) == "Open C:\\Users\\\\\nand then the\nfolder"
+
+
+def test_wrapping_links_near_the_column():
+    text = "one [first link](https://e.com/1) two [second](https://e.com/22) three"
+    wrapped = markdown(text, width=30)
+
+    assert wrapped == "one\n[first link](https://e.com/1)\ntwo [second](https://e.com/22)\nthree"
+    assert all(line == line.rstrip() for line in wrapped.split("\n"))
+    assert md0.tokens_to_html(md0.parse(wrapped)) == md0.tokens_to_html(md0.parse(text))
+    assert markdown(text, width=None) == text
+
+
+def test_wrapping_definitions():
+    options = md0.ParseOptions(definition_lists=True)
+    tokens = md0.parse("Term\n: one two three four five\n\n    second [l](u) part", options)
+    written = md0.tokens_to_markdown(tokens, width=16)
+
+    # Definitions are indented by four columns
+    assert written == "Term\n:   one two\n    three four\n    five\n\n    second\n    [l](u) part"
+    assert repr(md0.parse(written, options)) == repr(tokens)
+
+def test_filter_tokens():
+    tokens = md0.parse("# A\n\ntext\n\n```py\nx\n```\n\n---\n\n## B")
+
+    assert [token.kind for token in tokens] == ["heading", "paragraph", "code", "horizontal_rule", "heading"]
+    assert repr(md0.filter_tokens(tokens, "heading")) == '[Heading(1, "A"), Heading(2, "B")]'
+    assert [t.kind for t in md0.filter_tokens(tokens, ["code", "paragraph"])] == ["paragraph", "code"]
+
+    with pytest.raises(ValueError):
+        md0.filter_tokens(tokens, "headings")
+
+
+def test_longer_fences_nest_shorter_ones():
+    (code,) = md0.parse("````markdown\n```py\nx = 1\n```\n````")
+
+    assert (code.language, code.content) == ("markdown", "```py\nx = 1\n```\n")
+    assert (code.fence_char, code.fence_length) == ("`", 4)
+
+
+def test_tilde_fences():
+    tokens = md0.parse("~~~~ py\n~~~\n````\n~~~~~\nafter")
+
+    assert repr(tokens) == '[Code("py", "~~~\\n````\\n"), Paragraph("after", [])]'
+    assert tokens[0].fence_char == "~"
+    assert repr(md0.parse("```\n~~~\n```")) == '[Code("", "~~~\\n")]'
+
+    # An info string starting with the marker is set off from the fence
+    tokens = md0.parse("~~~ ~x\ncode\n~~~\n")
+    assert md0.tokens_to_markdown(tokens) == "~~~ ~x\ncode\n~~~"
+    assert md0.tokens_to_json(md0.parse(md0.tokens_to_markdown(tokens))) == md0.tokens_to_json(tokens)
+
+
+def test_empty_code_blocks():
+    assert repr(md0.parse("```\n```")) == '[Code("", "")]'
+    assert md0.tokens_to_html(md0.parse("```py\n```")) == '<pre><code class="language-py"></code></pre>'
+    assert repr(md0.parse("text\n~~~\n~~~\nmore")) == '[Paragraph("text", []), Code("", ""), Paragraph("more", [])]'
+
+
+
+def test_code_built_by_hand():
+    code = md0.Token.Code("py", "x = 1\n")
+
+    assert (code.fence_char, code.fence_length, code.indent) == (None, 0, 0)
+    assert md0.tokens_to_html([code]) == '<pre><code class="language-py">x = 1\n</code></pre>'
+    assert md0.tokens_to_markdown([code]) == "```py\nx = 1\n```"
+    assert repr(md0.parse(md0.tokens_to_markdown([code]))) == repr([code])
+
+    tilde = md0.Token.Code("", "```\n", "~", 4, 2)
+    assert md0.tokens_to_markdown([tilde]) == "  ~~~~\n```\n  ~~~~"
+
+def test_heading_right_after_a_fence():
+    tokens = md0.parse("```\ncode\n```\n# Heading")
+    assert repr(tokens) == '[Code("", "code\\n"), Heading(1, "Heading")]'
+    assert tokens[1].span == (13, 22)
+
+    tokens = md0.parse("text\n~~~py\nx\n~~~\n## Heading\nmore")
+    assert [t.kind for t in tokens] == ["paragraph", "code", "heading", "paragraph"]
+    assert md0.tokens_to_html(tokens) == (
+        '<p>text</p>\n<pre><code class="language-py">x\n</code></pre>\n<h2>Heading</h2>\n<p>more</p>'
+    )
+
+
+def test_indented_fences_are_dedented():
+    (code,) = md0.parse("  ```py\n      def f():\n\n          return 1\n  ```")
+
+    assert (code.indent, code.content) == (2, "def f():\n\n    return 1\n")
+    assert md0.parse("```\n    x\n```")[0].content == "    x\n"
+    assert md0.parse(" ~~~\n  a\n   \n b\n ~~~")[0].content == " a\n  \nb\n"
+
+
+def test_byte_order_mark_is_ignored():
+    markdown = "\ufeff# Title\n\ntext"
+    (heading, paragraph) = md0.parse(markdown, md0.ParseOptions(keep_raw=True))
+
+    assert (heading.content, heading.raw, heading.span) == ("Title", "# Title", (3, 10))
+    assert markdown.encode()[paragraph.span[0] : paragraph.span[1]] == b"text"
+    assert md0.tokens_to_html([heading]) == "<h1>Title</h1>"
+    assert md0.Document(markdown).tokens[0].content == "Title"
+    assert repr(md0.parse("\ufeff")) == "[]"
+
+
+def test_unicode_blank_lines():
+    for blank in ["\xa0", "\u3000", "\u200b", "\u200b\xa0 "]:
+        assert repr(md0.parse(f"one\n{blank}\ntwo")) == '[Paragraph("one", []), Paragraph("two", [])]'
+
+    options = md0.ParseOptions(nbsp_is_whitespace=False)
+    assert repr(md0.parse("one\n\xa0\ntwo", options)) == '[Paragraph("one \\u{a0} two", [])]'
+    assert md0.parse("\xa0\xa0indented", options)[0]._0 == "\xa0\xa0indented"
+    assert md0.parse("\xa0\xa0indented")[0]._0 == "indented"
+
+
+def test_zero_width_characters_before_block_markers():
+    markdown = "text\n\ufeff# Title\n\u200b---"
+    (paragraph, heading, rule) = md0.parse(markdown, md0.ParseOptions(keep_raw=True))
+
+    assert (heading.content, heading.raw) == ("Title", "# Title")
+    assert markdown.encode()[heading.span[0] : heading.span[1]] == b"# Title"
+    assert rule.kind == "horizontal_rule"
+
+
+def test_google_docs_export():
+    markdown = Path(__file__).with_name("google_docs.md").read_text(encoding="utf-8")
+    tokens = md0.parse(markdown)
+
+    assert repr(tokens) == repr(
+        [
+            md0.Token.Heading(1, "Meeting notes"),
+            md0.Token.Paragraph("Attendees: Ana, Ben and Chlo\u00e9.", []),
+            md0.Token.Heading(2, "Agenda"),
+            md0.Token.Paragraph("Review the launch checklist.", []),
+            md0.Token.Paragraph("Decide on the release date.", []),
+            md0.Token.Heading(2, "Action items"),
+            md0.Token.Paragraph("Ben to send the draft by Friday.", []),
+        ]
+    )
+    assert repr(md0.Document(markdown).tokens) == repr(tokens)
+
+
+def test_character_references():
+    markdown = Path(__file__).with_name("entities.md").read_text(encoding="utf-8")
+    tokens = md0.parse(markdown)
+
+    assert md0.tokens_to_html(tokens) == (
+        "<h1>Fish &amp; Chips #1</h1>\n"
+        "<p>Named: &amp; &lt;b&gt; \u00a9 \u00c6 \u2267 "
+        "Decimal: # \u04d2 \ufffd "
+        'Hex: " \u0d06 \u0cab '
+        "Invalid: &amp;notanentity; &amp;copy &amp;#; &amp;#xZZ; &amp;#87654321; &amp; alone "
+        'Escaped: &amp;amp; <a href="https://e.com">fish &amp; chips</a> <img src="i.png" alt="&lt;alt&gt;"></p>'
+    )
+    # Tokens keep the references as written, so they come back out as they were
+    assert tokens[0].content == "Fish &amp; Chips &#35;1"
+    assert md0.title(tokens) == "Fish & Chips #1"
+    again = md0.parse(md0.tokens_to_markdown(tokens, width=None))
+    assert md0.tokens_to_html(again) == md0.tokens_to_html(tokens)
+    # Without escaping, text is HTML already and its references are left alone
+    escape = md0.HtmlOptions(escape=False)
+    assert md0.tokens_to_html(md0.parse("&lt;b&gt; &amp;"), escape) == "<p>&lt;b&gt; &amp;</p>"
+
+
+def test_tabs_expand_to_tab_stops():
+    makefile = "build: main.o\n\t$(CC) -o app main.o\n  \t@echo done\n"
+    markdown = Path(__file__).with_name("tabs.md").read_text(encoding="utf-8")
+
+    def kinds(tab_width):
+        options = md0.ParseOptions(tab_width=tab_width, definition_lists=True)
+        return [t.kind for t in md0.parse(markdown, options)]
+
+    # Up to three columns of indentation still start a block
+    assert kinds(2) == ["heading", "paragraph", "horizontal_rule", "code", "code", "definition_list"]
+    assert kinds(4) == kinds(8) == ["heading", "paragraph", "code", "paragraph", "definition_list"]
+
+    tokens = md0.parse(markdown, md0.ParseOptions(tab_width=2, keep_raw=True))
+    code = [t for t in tokens if t.kind == "code"]
+    assert [(c.content, c.indent) for c in code] == [(makefile, 0), ("make clean\n", 2)]
+    assert code[1].raw == " \t~~~sh\nmake clean\n\t~~~"
+
+    with pytest.raises(ValueError):
+        md0.ParseOptions(tab_width=0)
+
+
+@pytest.mark.parametrize(
+    "markdown",
+    [
+        "# Title",
+        "text",
+        "text\n---",
+        "---\n---",
+        "***",
+        "term\n: definition",
+        "*[HTML]: Hyper Text Markup Language",
+        "```py\ncode\n```",
+        "```",
+        "```py",
+        "```\ncode",
+        "text\n\n# Title",
+        "# Title\n\n---",
+    ],
+)
+def test_final_line_break_changes_nothing(markdown):
+    options = md0.ParseOptions(keep_raw=True, definition_lists=True, abbreviations=True)
+
+    def summary(tokens):
+        return [(repr(t), t.span, t.raw) for t in tokens]
+
+    assert summary(md0.parse(markdown + "\n", options)) == summary(md0.parse(markdown, options))
+    assert repr(md0.Document(markdown + "\n", options).tokens) == repr(md0.parse(markdown, options))
+
+
+def test_unclosed_fence_keeps_blank_lines_before_the_end():
+    (code,) = md0.parse("```\ncode\n\n")
+
+    assert code.content == "code\n\n"
+    assert code.span == (0, 9)
+
+
+def test_rule_markers():
+    tokens = md0.parse("***\n\n_____\n\n - - -\n\ntext\n* * *\n\n    ---\n\n**")
+    rules = [t for t in tokens if isinstance(t, md0.Token.HorizontalRule)]
+
+    assert [(r.marker, r.length) for r in rules] == [("*", 3), ("_", 5), ("-", 3), ("*", 3)]
+    assert repr(tokens[3:]) == '[Paragraph("text", []), HorizontalRule, Paragraph("---", []), Paragraph("**", [])]'
+    assert md0.tokens_to_html(md0.parse("___")) == "<hr>"
+    assert (md0.Token.HorizontalRule().marker, md0.Token.HorizontalRule().length) == ("-", 3)
+
+
+def test_fence_flags_and_attrs():
+    markdown = (
+        "```rust,no_run,edition2021\nfn main() {}\n```\n\n"
+        '```python exec="true" session=\'intro\' title="a b, c" hide\nprint(1)\n```'
+    )
+    (rust, python) = md0.parse(markdown)
+
+    assert (rust.language, rust.flags, rust.attrs) == ("rust", ["no_run", "edition2021"], {})
+    assert (python.language, python.flags) == ("python", ["hide"])
+    assert python.attrs == {"exec": "true", "session": "intro", "title": "a b, c"}
+    assert python.info == 'python exec="true" session=\'intro\' title="a b, c" hide'
+
+    (bare,) = md0.parse("```linenums=1\n```")
+    assert (bare.language, bare.flags, bare.attrs) == ("", [], {"linenums": "1"})
+
+
+def test_containers():
+    options = md0.ParseOptions(containers=True, keep_raw=True)
+    markdown = ":::note Read this\ntext\n::::tip\n```\n:::\n```\n::::\n:::\nafter"
+    container, after = md0.parse(markdown, options)
+
+    assert repr(container) == 'Container("note", [Paragraph("text", []), Container("tip", [Code("", ":::\\n")])])'
+    assert (container.kind, container.name, container.attrs) == ("container", "note", "Read this")
+    assert container.raw == markdown[: markdown.index("\nafter")]
+    assert [token.raw for token in container.content] == ["text", "::::tip\n```\n:::\n```\n::::"]
+    assert md0.tokens_to_html([container]) == (
+        '<div class="note">\n<p>text</p>\n<div class="tip">\n<pre><code>:::\n</code></pre>\n</div>\n</div>'
+    )
+    assert after.raw == "after"
+
+    assert repr(md0.parse(":::warning\nnever closed\n\n# Title", options)) == (
+        '[Container("warning", [Paragraph("never closed", []), Heading(1, "Title")])]'
+    )
+    assert repr(md0.parse("text\n:::x\n:::", options)) == '[Paragraph("text", []), Container("x", [])]'
+    assert repr(md0.parse('::: tip\n:::\n\n:::"<x>"\n:::', options)) == '[Container("tip", []), Paragraph(":::\\"<x>\\" :::", [])]'
+    assert md0.parse(":::note\ntext\n:::")[0].kind == "paragraph"
+
+
+def test_math_inside_a_container():
+    options = md0.ParseOptions(containers=True, math=True, strict=True)
+
+    assert repr(md0.parse(":::n\n$$\n:::\n$$\n:::\n", options)) == '[Container("n", [MathBlock(":::")])]'
+    assert repr(md0.parse(":::note\n$$\na\n:::\nb\n$$\n:::\n", options)) == (
+        '[Container("note", [MathBlock("a\\n:::\\nb")])]'
+    )
+    assert repr(md0.parse(":::note\n$$a$$\n:::\nafter", options)) == (
+        '[Container("note", [MathBlock("a")]), Paragraph("after", [])]'
+    )
+
+
+def test_container_nesting_is_capped():
+    options = md0.ParseOptions(containers=True, max_depth=2)
+    (outer,) = md0.parse(":::a\n:::b\n:::c\ntext\n:::\n:::\n:::", options)
+
+    (inner,) = outer.content
+    assert repr(inner) == 'Container("b", [Paragraph(":::c text :::", [])])'
+    assert md0.ParseOptions().max_depth == 64
+
+    deep = md0.parse(":::a\n" * 10000, md0.ParseOptions(containers=True))
+    tokens, depth = deep, 0
+    while tokens[0].kind == "container":
+        tokens, depth = tokens[0].content, depth + 1
+    assert depth == 64
+    assert tokens[0]._0.startswith(":::a :::a")
+
+    assert md0.tokens_to_html(deep).count("<div") == 64
+    assert json.loads(md0.tokens_to_json(deep))[0]["kind"] == "container"
+    assert md0.tokens_to_markdown(deep).startswith(":::a\n")
+
+    # Strict parsing reports the cap where the first container it kept out opens
+    strict = md0.ParseOptions(containers=True, max_depth=2, strict=True)
+    with pytest.raises(md0.ParseError) as error:
+        md0.parse(":::a\n:::b\n:::c\ntext\n:::\n:::\n:::", strict)
+    assert (error.value.offset, error.value.code) == (10, "max-depth")
+    with pytest.raises(md0.ParseError) as error:
+        md0.parse(":::a\n" * 10000 + ":::\n" * 10000, md0.ParseOptions(containers=True, strict=True))
+    assert (error.value.offset, error.value.code) == (64 * 5, "max-depth")
+    with pytest.raises(md0.ParseError) as error:
+        md0.Document("text\n\n:::a\n:::b\n:::c\n:::\n:::\n:::", strict)
+    assert (error.value.offset, error.value.code) == (16, "max-depth")
+    assert md0.parse(":::a\n:::b\n```\n:::c\n```\n:::\n:::", strict)[0].kind == "container"
+
+
+def test_container_contents_take_part_in_the_document():
+    markdown = ":::note\n# Intro\n\nThe HTML [spec](#intro).\n\n```py\nx\n```\n:::\n\n*[HTML]: Hyper Text"
+    options = md0.ParseOptions(containers=True, abbreviations=True)
+    tokens = md0.parse(markdown, options)
+
+    assert md0.tokens_to_html(tokens, md0.HtmlOptions(heading_ids=True)).startswith(
+        '<div class="note">\n<h1 id="intro">Intro</h1>\n<p>The <abbr title="Hyper Text">HTML</abbr>'
+    )
+    assert md0.check_anchors(tokens) == []
+    assert [entry.slug for entry in md0.outline(tokens)] == ["intro"]
+    assert [block.language for block in md0.code_blocks(tokens)] == ["py"]
+
+    written = md0.tokens_to_markdown(tokens)
+    assert written.startswith(":::note\n# Intro\n")
+    assert md0.tokens_to_markdown(md0.parse(written, options)) == written
+
+    document = md0.Document(markdown, options)
+    document.edit(0, 0, "Start\n\n")
+    heading = document.tokens[1].content[0]
+    assert document.text.encode()[heading.span[0] : heading.span[1]] == b"# Intro"
+
+
+def test_token_ids_and_walk():
+    options = md0.ParseOptions(containers=True)
+    markdown = "# Title\n\n:::note\ntext\n::::tip\ninner\n::::\n:::\n\nend"
+    tokens = md0.parse(markdown, options)
+
+    walked = md0.walk(tokens)
+    assert [(repr(token), depth) for token, depth, _ in walked] == [
+        ('Heading(1, "Title")', 0),
+        ('Container("note", [Paragraph("text", []), Container("tip", [Paragraph("inner", [])])])', 0),
+        ('Paragraph("text", [])', 1),
+        ('Container("tip", [Paragraph("inner", [])])', 1),
+        ('Paragraph("inner", [])', 2),
+        ('Paragraph("end", [])', 0),
+    ]
+    assert [(token.id, token.parent_id) for token, _, _ in walked] == [(0, None), (1, None), (2, 1), (3, 1), (4, 3), (5, None)]
+    assert [parent and parent.id for _, _, parent in walked] == [None, None, 1, 1, 3, None]
+    assert walked[1][0] is tokens[1] and walked[2][2] is tokens[1]
+
+    (note,) = [token for token in json.loads(md0.tokens_to_json(tokens)) if token["kind"] == "container"]
+    assert (note["id"], note["parent_id"]) == (1, None)
+    assert [(child["id"], child["parent_id"]) for child in note["content"]] == [(2, 1), (3, 1)]
+
+    # Transforms keep the ids, and a document or stream gives the same ones
+    (heading,) = md0.promote_headings([md0.parse("Requirements:")[0]])
+    assert (heading.kind, heading.id) == ("heading", 0)
+    document = md0.Document("end", options)
+    document.edit(0, 0, markdown[:-3])
+    assert md0.tokens_to_json(document.tokens) == md0.tokens_to_json(tokens)
+    parser = md0.StreamParser(options)
+    for line in markdown.splitlines(keepends=True):
+        parser.feed(line)
+    assert md0.tokens_to_json(parser.drain() + parser.finish()) == md0.tokens_to_json(tokens)
+
+    flat = md0.parse("# a\n\ntext\n\n---\n\n```\ncode\n```")
+    assert [(token.id, token.parent_id) for token in flat] == [(0, None), (1, None), (2, None), (3, None)]
+    assert [depth for _, depth, _ in md0.walk(flat)] == [0, 0, 0, 0]
+    assert (md0.Token.Paragraph("x", []).id, md0.Token.Paragraph("x", []).parent_id) == (0, None)
+
+
+def test_image_size_suffix():
+    (paragraph,) = md0.parse("![a](a.png =640x480) ![b](b.png =640x) ![c](c.png?w=1&h=2) ![d](d.png x=1)")
+    images = [m for m in paragraph._1 if isinstance(m, md0.Metadata.Image)]
+
+    assert [(i.url, i.width, i.height) for i in images] == [
+        ("a.png", 640, 480),
+        ("b.png", 640, None),
+        ("c.png?w=1&h=2", None, None),
+        ("d.png x=1", None, None),
+    ]
+    assert md0.tokens_to_html([paragraph], md0.HtmlOptions(lazy_images=True)).startswith(
+        '<p><img src="a.png" alt="a" width="640" height="480" loading="lazy"> '
+        '<img src="b.png" alt="b" width="640" loading="lazy">'
+    )
+
+
+def test_links_and_images_are_rendered():
+    tokens = md0.parse('A [link](https://e.com/?a=1&b="2") and ![alt "x"](i.png).')
+
+    assert md0.tokens_to_html(tokens) == (
+        '<p>A <a href="https://e.com/?a=1&amp;b=%222%22">link</a> and '
+        '<img src="i.png" alt="alt &quot;x&quot;">.</p>'
+    )
+
+
+def test_angle_bracket_destinations():
+    (paragraph,) = md0.parse("[my file](<docs/my file (1).md>) and ![pic](<a b.png>)")
+
+    (image,) = [m for m in paragraph._1 if isinstance(m, md0.Metadata.Image)]
+
+    assert (paragraph._1[0].label, paragraph._1[0].url) == ("my file", "docs/my file (1).md")
+    assert (image.label, image.url) == ("pic", "a b.png")
+    assert md0.tokens_to_html([paragraph]) == (
+        '<p><a href="docs/my%20file%20(1).md">my file</a> and <img src="a%20b.png" alt="pic"></p>'
+    )
+
+
+def test_urls_are_percent_encoded():
+    tokens = md0.parse("![x](</a b.png>) [ü](/wiki/Übung?q=a%20b&r=%zz)")
+
+    assert md0.tokens_to_html(tokens) == (
+        '<p><img src="/a%20b.png" alt="x"> <a href="/wiki/%C3%9Cbung?q=a%20b&amp;r=%25zz">ü</a></p>'
+    )
+    assert md0.tokens_to_html(tokens, md0.HtmlOptions(encode_urls=False)) == (
+        '<p><img src="/a b.png" alt="x"> <a href="/wiki/Übung?q=a%20b&amp;r=%zz">ü</a></p>'
+    )
+
+
+def test_attributes_are_escaped():
+    tokens = md0.parse('[x](a"b>c) ![q"><s>](p.png)')
+    raw = md0.HtmlOptions(encode_urls=False)
+
+    assert md0.tokens_to_html(tokens) == '<p><a href="a%22b%3Ec">x</a> <img src="p.png" alt="q&quot;&gt;&lt;s&gt;"></p>'
+    assert md0.tokens_to_html(tokens, raw).startswith('<p><a href="a&quot;b&gt;c">x</a>')
+
+    options = md0.ParseOptions(abbreviations=True)
+    html = md0.tokens_to_html(md0.parse('*[HTML]: say "hi" <now>\n\nHTML', options))
+    assert html == '<p><abbr title="say &quot;hi&quot; &lt;now&gt;">HTML</abbr></p>'
+
+
+def test_escaping_can_be_turned_off():
+    tokens = md0.parse("# <i>Title</i>\n\nSome <b>bold</b> [<em>link</em>](u)\n\n```\n<br>\n```")
+
+    assert md0.tokens_to_html(tokens, md0.HtmlOptions(escape=False)) == (
+        '<h1><i>Title</i></h1>\n<p>Some <b>bold</b> <a href="u"><em>link</em></a></p>\n<pre><code>&lt;br&gt;\n</code></pre>'
+    )
+    assert md0.tokens_to_html(tokens, md0.HtmlOptions(escape_code=False)).endswith("<pre><code><br>\n</code></pre>")
+    assert "&lt;i&gt;" in md0.tokens_to_html(tokens)
+
+    # Attributes are escaped either way
+    html = md0.tokens_to_html(md0.parse('![a"b](p.png)'), md0.HtmlOptions(escape=False))
+    assert html == '<p><img src="p.png" alt="a&quot;b"></p>'
+
+
+def test_invalid_classes_and_ids_are_left_out():
+    html = md0.tokens_to_html(md0.parse('```"><script>alert(1)</script>\nx\n```\n\n```c++\ny\n```'))
+    assert html == '<pre><code>x\n</code></pre>\n<pre><code class="language-c++">y\n</code></pre>'
+
+    html = md0.tokens_to_html(md0.parse("# !!!\n\n# 🎉 Party"), md0.HtmlOptions(heading_ids=True))
+    assert html == '<h1 id="section">!!!</h1>\n<h1 id="party">🎉 Party</h1>'
+
+
+def test_parenthesized_destinations():
+    (paragraph,) = md0.parse("[Rust](https://en.wikipedia.org/wiki/Rust_(language)) (see ![m](m_(1).png =8x))")
+
+    (image,) = [m for m in paragraph._1 if isinstance(m, md0.Metadata.Image)]
+
+    assert (paragraph._1[0].url, paragraph._1[0].location) == (
+        "https://en.wikipedia.org/wiki/Rust_(language)",
+        (0, 53),
+    )
+    assert (image.url, image.width, image.location) == ("m_(1).png", 8, (59, 78))
+
+
+def test_token_to_html():
+    markdown = "*[HTML]: HyperText\n\n# Title\n\nUse HTML [here](u).\n\n---\n\n```py\nx\n```"
+    tokens = md0.parse(markdown, md0.ParseOptions(abbreviations=True))
+    options = md0.HtmlOptions(heading_ids=True)
+
+    for token in tokens:
+        assert md0.token_to_html(token, options) == md0.tokens_to_html([token], options)
+    assert md0.token_to_html(tokens[0]) == ""
+
+    # Abbreviation uses are kept as metadata, so a paragraph renders the same
+    # on its own
+    assert "\n".join(md0.token_to_html(token) for token in tokens[1:]) == md0.tokens_to_html(tokens)
+
+def test_preserve_soft_breaks():
+    tokens = md0.parse("  one   line\n  [two](u)  \nthree")
+    (paragraph,) = tokens
+
+    assert (paragraph._0, paragraph._4) == ("one   line [two](u) three", [10, 19])
+    assert md0.tokens_to_html(tokens) == '<p>one   line <a href="u">two</a> three</p>'
+    assert md0.tokens_to_html(tokens, md0.HtmlOptions(preserve_soft_breaks=True)) == (
+        '<p>one   line\n<a href="u">two</a>\nthree</p>'
+    )
+
+    (collapsed,) = md0.parse("a  \n  b", md0.ParseOptions(collapse_whitespace=True, trim_lines=False))
+    assert (collapsed._0, collapsed._4) == ("a b", [1])
+
+
+def test_autolinks():
+    (paragraph,) = md0.parse("Mail <sales@example.com>, see <https://e.com/a?b=1> or call [us](tel:+1-555-0100).")
+
+    assert [(m.label, m.url) for m in paragraph._1] == [
+        ("sales@example.com", "mailto:sales@example.com"),
+        ("https://e.com/a?b=1", "https://e.com/a?b=1"),
+        ("us", "tel:+1-555-0100"),
+    ]
+    assert md0.tokens_to_html([paragraph]) == (
+        '<p>Mail <a href="mailto:sales@example.com">sales@example.com</a>, see '
+        '<a href="https://e.com/a?b=1">https://e.com/a?b=1</a> or call <a href="tel:+1-555-0100">us</a>.</p>'
+    )
+
+
+
+@pytest.mark.parametrize(
+    "url",
+    [
+        "javascript:alert(1)",
+        "JavaScript:alert(1)",
+        " java\tscript:alert(1)",
+        "vbscript:msgbox(1)",
+        "data:text/html;base64,PHNjcmlwdD4=",
+    ],
+)
+def test_unsafe_urls_are_not_rendered(url):
+    tokens = md0.parse(f"[x](<{url}>) ![y](<{url}>)")
+
+    assert md0.tokens_to_html(tokens) == '<p>x <img alt="y"></p>'
+    assert md0.tokens_to_html(tokens, md0.HtmlOptions(encode_urls=False)) == '<p>x <img alt="y"></p>'
+
+
+def test_safe_urls_are_rendered():
+    markdown = "[a](https://e.com) [b](HTTP://e.com) [c](mailto:me@e.com) [d](tel:+1) [e](/docs) [f](#top) [g](./a:b) [h](x/y:z)"
+    rendered = md0.tokens_to_html(md0.parse(markdown))
+
+    assert re.findall(r'href="([^"]*)"', rendered) == [
+        "https://e.com",
+        "HTTP://e.com",
+        "mailto:me@e.com",
+        "tel:+1",
+        "/docs",
+        "#top",
+        "./a:b",
+        "x/y:z",
+    ]
+    assert "<a" not in md0.tokens_to_html(md0.parse("<javascript:alert(1)>"))
+
+def test_shorten_urls():
+    url = "https://docs.example.com/guide/installation/linux?distro=debian&arch=arm64"
+    markdown = f"<{url}> and <https://e.com/a/>, [label](https://e.com/{'x' * 40}) or <me@example.com>"
+    options = md0.HtmlOptions(shorten_urls=True)
+
+    rendered = md0.tokens_to_html(md0.parse(markdown), options)
+    assert rendered == (
+        f'<p><a href="{html.escape(url)}">docs.example.com/guide/installa…</a> and '
+        '<a href="https://e.com/a/">e.com/a</a>, '
+        f'<a href="https://e.com/{"x" * 40}">label</a> or <a href="mailto:me@example.com">me@example.com</a></p>'
+    )
+    assert re.findall(r'href="([^"]*)"', rendered) == re.findall(r'href="([^"]*)"', md0.tokens_to_html(md0.parse(markdown)))
+
+    host = f"https://{'a' * 40}.example.com/path"
+    assert f">{'a' * 40}.example.com…</a>" in md0.tokens_to_html(md0.parse(f"<{host}>"), options)
+
+
+def test_bare_emails_are_autolinked():
+    options = md0.ParseOptions(autolink_emails=True)
+    (paragraph,) = md0.parse("Write to hello@example.com. Or [me](mailto:me@example.com), not a@b", options)
+
+    assert [(m.label, m.url) for m in paragraph._1] == [
+        ("hello@example.com", "mailto:hello@example.com"),
+        ("me", "mailto:me@example.com"),
+    ]
+    assert md0.parse("hello@example.com")[0]._1 == []
+
+
+def test_metadata_is_sorted_by_position():
+    options = md0.ParseOptions(abbreviations=True, definition_lists=True)
+    tokens = md0.parse("*[HTML]: markup\n\n![i](a.png) HTML [l](b) <https://c> ![j](d.png)\n\nterm\n: ![k](e.png) [m](f)", options)
+    paragraph, definitions = tokens[1], tokens[2]
+
+    assert repr(paragraph.metadata) == repr(paragraph._1)
+    assert [type(m).__name__ for m in paragraph.metadata] == [
+        "Metadata_Image",
+        "Metadata_Link",
+        "Metadata_Abbreviation",
+        "Metadata_Link",
+        "Metadata_Link",
+        "Metadata_Image",
+        "Metadata_Link",
+    ]
+    assert [m.location for m in paragraph.metadata] == sorted(m.location for m in paragraph.metadata)
+    assert [m.location[0] for m in definitions.items[0][1][0][1]] == [0, 1, 12]
+
+    with pytest.raises(AttributeError):
+        tokens[0].metadata
+
+
+def test_obfuscated_emails_decode_to_the_address():
+    tokens = md0.parse("<hello@example.com> and [email us](mailto:hello@example.com)")
+    rendered = md0.tokens_to_html(tokens, md0.HtmlOptions(obfuscate_emails=True))
+
+    assert "hello@example.com" not in rendered
+    assert "mailto:" not in rendered
+    assert html.unescape(rendered) == (
+        '<p><a href="mailto:hello@example.com">hello@example.com</a> and '
+        '<a href="mailto:hello@example.com">email us</a></p>'
+    )
+
+
+def test_abbreviation_metadata():
+    options = md0.ParseOptions(abbreviations=True)
+    (paragraph, _) = md0.parse("The HTML spec, HTMLX and [HTML](u) and HTML.\n*[HTML]: HyperText", options)
+    abbreviations = [m for m in paragraph._1 if isinstance(m, md0.Metadata.Abbreviation)]
+
+    assert [(m.location, m.short, m.title) for m in abbreviations] == [
+        ((4, 8), "HTML", "HyperText"),
+        ((39, 43), "HTML", "HyperText"),
+    ]
+    assert md0.tokens_to_html([paragraph]) == (
+        '<p>The <abbr title="HyperText">HTML</abbr> spec, HTMLX and <a href="u">HTML</a> and '
+        '<abbr title="HyperText">HTML</abbr>.</p>'
+    )
+
+
+def test_image_alt_is_always_rendered():
+    tokens = md0.parse("![A cat](cat.png) ![](divider.png)")
+
+    assert md0.tokens_to_html(tokens) == '<p><img src="cat.png" alt="A cat"> <img src="divider.png" alt=""></p>'
+    assert md0.tokens_to_html(tokens, md0.HtmlOptions(decorative_role=True, lazy_images=True)) == (
+        '<p><img src="cat.png" alt="A cat" loading="lazy"> '
+        '<img src="divider.png" alt="" loading="lazy" role="presentation"></p>'
+    )
+
+
+def test_group_images():
+    tokens = md0.parse("![a](1.png)\n\n ![b](2.png =10x) \n\n![c](3.png) and text\n\n![d](4.png)")
+    html = md0.tokens_to_html(tokens, md0.HtmlOptions(group_images=True))
+
+    assert html == (
+        '<div class="gallery"><img src="1.png" alt="a"><img src="2.png" alt="b" width="10"></div>\n'
+        '<p><img src="3.png" alt="c"> and text</p>\n'
+        '<p><img src="4.png" alt="d"></p>'
+    )
+    assert md0.tokens_to_html(tokens).startswith('<p><img src="1.png" alt="a"></p>')
+
+
+def test_figures():
+    tokens = md0.parse("![a](1.png) The [first](u) one\n\n![b](2.png)")
+
+    assert md0.tokens_to_html(tokens, md0.HtmlOptions(figures=True)) == (
+        '<figure><img src="1.png" alt="a"><figcaption>The <a href="u">first</a> one</figcaption></figure>\n'
+        '<p><img src="2.png" alt="b"></p>'
+    )
+    assert md0.tokens_to_html(tokens, md0.HtmlOptions(figures=True, group_images=True)) == (
+        '<div class="gallery"><figure><img src="1.png" alt="a"><figcaption>The <a href="u">first</a> one'
+        '</figcaption></figure><img src="2.png" alt="b"></div>'
+    )
+
+
+CORPUS = sorted(Path(__file__).parent.glob("*.md")) + sorted(Path(__file__).parent.glob("regressions/*.md"))
+
+
+@pytest.mark.parametrize(
+    "options",
+    [
+        md0.HtmlOptions(),
+        md0.HtmlOptions(auto_dir=True, obfuscate_emails=True, lazy_images=True, figures=True, group_images=True),
+        md0.HtmlOptions(escape=False, escape_code=False, heading_ids=False),
+        md0.HtmlOptions(output_style="xhtml", lazy_images=True),
+    ],
+)
+def test_elements_write_out_as_tokens_to_html(options):
+    spec = json.loads(Path(__file__).with_name("spec.json").read_text(encoding="utf-8"))
+    documents = [path.read_text(encoding="utf-8") for path in CORPUS] + [example["markdown"] for example in spec]
+
+    for document in documents:
+        tokens = md0.parse(document, md0.ParseOptions(definition_lists=True, abbreviations=True, autolink_emails=True))
+        elements = md0.tokens_to_elements(tokens, options)
+
+        assert md0.elements_to_html(elements, output_style=options.output_style) == md0.tokens_to_html(tokens, options)
+
+
+def test_edit_elements():
+    tokens = md0.parse(
+        "# Title\n\n![cover](c.png?a=1&b=2) A HTML & co\n\n*[HTML]: Hyper Text",
+        md0.ParseOptions(abbreviations=True),
+    )
+    heading, paragraph = md0.tokens_to_elements(tokens, md0.HtmlOptions(heading_ids=True))
+
+    assert (heading.tag, heading.attrs, heading.children) == ("h1", {"id": "title"}, ["Title"])
+    image = paragraph.children[0]
+    assert (image.tag, image.attrs["src"]) == ("img", "c.png?a=1&amp;b=2")
+    assert paragraph.text == " A HTML & co"
+    assert [child.tag for child in paragraph.children if isinstance(child, md0.Element)] == ["img", "abbr"]
+
+    heading.attrs["class"] = "title"
+    heading.children.append(md0.Element("small", children=["!"]))
+    assert heading.to_html() == '<h1 id="title" class="title">Title<small>!</small></h1>'
+
+    heading.children.append(1)
+    with pytest.raises(TypeError):
+        heading.to_html()
+
+
+def test_normalize():
+    first, rule = md0.parse("see [a](u)\n\n---")
+    second = md0.Token.Paragraph("and ![b](v)", md0.parse("and ![b](v)")[0]._1, (20, 31))
+    empty = md0.Token.Paragraph("  ", [])
+
+    merged, rule_ = md0.normalize([first, empty, second, rule, rule, md0.Token.HorizontalRule()])
+
+    assert merged._0 == "see [a](u) and ![b](v)"
+    assert merged._4 == [10]
+    assert merged._2 == (0, 31)
+    for metadata in merged._1:
+        start, end = metadata.location
+        assert merged._0[start:end] in ("[a](u)", "![b](v)", "[b](v)")
+    assert len(merged._1) == 3
+    assert rule_.kind == "horizontal_rule"
+
+    nested = md0.Token.Container("tip", "", [md0.Token.Container("note", "", [first, empty, second])])
+    (container,) = md0.normalize([nested])
+    ((paragraph,),) = [inner.content for inner in container.content]
+    assert (paragraph._0, paragraph._4) == ("see [a](u) and ![b](v)", [10])
+
+
+def test_tokens_to_pandoc_json():
+    markdown = "# Hi *there*\n\nsee [docs](https://e.com)\nand ![logo](l.png =32x32)\n\n---\n\n```py run\nx = 1\n```"
+    document = json.loads(md0.tokens_to_pandoc_json(md0.parse(markdown)))
+
+    assert document["pandoc-api-version"][:2] == [1, 23]
+    assert document["meta"] == {}
+    heading, paragraph, rule, code = document["blocks"]
+    assert heading["t"] == "Header"
+    assert heading["c"][:2] == [1, ["hi-there", [], []]]
+    assert paragraph == {
+        "t": "Para",
+        "c": [
+            {"t": "Str", "c": "see"},
+            {"t": "Space"},
+            {"t": "Link", "c": [["", [], []], [{"t": "Str", "c": "docs"}], ["https://e.com", ""]]},
+            {"t": "SoftBreak"},
+            {"t": "Str", "c": "and"},
+            {"t": "Space"},
+            {
+                "t": "Image",
+                "c": [["", [], [["width", "32"], ["height", "32"]]], [{"t": "Str", "c": "logo"}], ["l.png", ""]],
+            },
+        ],
+    }
+    assert rule == {"t": "HorizontalRule"}
+    assert code == {"t": "CodeBlock", "c": [["", ["py", "run"], []], "x = 1"]}
+
+    abbreviation = md0.parse("*[HTML]: Hyper Text", md0.ParseOptions(abbreviations=True))
+    assert json.loads(md0.tokens_to_pandoc_json(abbreviation))["blocks"] == [
+        {"t": "RawBlock", "c": ["markdown", "*[HTML]: Hyper Text"]}
+    ]
+
+
+def test_backslash_at_end_of_line_is_a_hard_break():
+    (paragraph,) = md0.parse("one\\\ntwo \\* three\\\\\nfour\\")
+
+    assert paragraph._0 == "one two \\* three\\\\ four\\"
+    assert [repr(m) for m in paragraph._1] == ["LineBreak((3, 4))"]
+    assert md0.tokens_to_html([paragraph]) == "<p>one<br>\ntwo * three\\ four\\</p>"
+    assert md0.tokens_to_markdown([paragraph]) == "one\\\ntwo \\* three\\\\ four\\"
+
+    (definitions,) = md0.parse("Term\n: first\\\n  second", md0.ParseOptions(definition_lists=True))
+    ((_, [(text, metadata)]),) = definitions.items
+    assert (text, repr(metadata)) == ("first second", "[LineBreak((5, 6))]")
+    assert md0.tokens_to_markdown([definitions]) == "Term\n:   first\\\n    second"
+
+
+def test_math():
+    options = md0.ParseOptions(math=True)
+    tokens = md0.parse("Euler: $e^{i\\pi} + 1 = 0$, not `$x$`\n\n$$\nx^2 < y\n$$\n\n$$a$$", options)
+
+    paragraph, block, one_line = tokens
+    assert [repr(m) for m in paragraph._1] == ['InlineMath((7, 25), "e^{i\\\\pi} + 1 = 0")']
+    assert (block.kind, block.content, one_line.content) == ("math_block", "x^2 < y", "a")
+    assert md0.tokens_to_html(tokens) == (
+        '<p>Euler: <span class="math inline">\\(e^{i\\pi} + 1 = 0\\)</span>, not `$x$`</p>\n'
+        '<div class="math display">\\[x^2 &lt; y\\]</div>\n'
+        '<div class="math display">\\[a\\]</div>'
+    )
+    assert md0.parse(md0.tokens_to_markdown(tokens), options)[1].content == "x^2 < y"
+
+    for markdown in ["$$$$$$", "$$\nx$$$$"]:
+        written = md0.tokens_to_markdown(md0.parse(markdown, options))
+        assert md0.tokens_to_markdown(md0.parse(written, options)) == written
+    assert md0.parse("$$\nx$$$$", options)[0].content == "x"
+
+    paragraph = md0.parse("word " * 16 + "$$ is how display math opens", options)
+    written = md0.tokens_to_markdown(paragraph)
+    assert "\n$$" not in written
+    assert repr(md0.parse(written, options)) == repr(paragraph)
+
+    assert md0.parse("$x$\n\n$$\nx\n$$")[0]._1 == []
+    assert md0.tokens_to_html(md0.parse("$$\nx\n$$")) == "<p>$$ x $$</p>"
+
+    with pytest.raises(md0.ParseError) as error:
+        md0.parse("text\n\n$$\nx", md0.ParseOptions(math=True, strict=True))
+    assert (error.value.offset, error.value.code) == (6, "unclosed-math")
+
+
+@pytest.mark.parametrize(
+    "text, math",
+    [
+        ("It costs $5 or $10.", []),
+        ("Prices went from $5-$10 to $20,000.", []),
+        ("Pay US$5, then US$10", []),
+        ("Escaped \\$x$ and $ y $", []),
+        ("Area is $\\pi r^2$ for $5", ["\\pi r^2"]),
+        ("Inline $$c$$ is not display math", []),
+        ("$x$, $y_1$", ["x", "y_1"]),
+    ],
+)
+def test_math_is_told_apart_from_currency(text, math):
+    (paragraph,) = md0.parse(text, md0.ParseOptions(math=True))
+
+    assert [m.content for m in paragraph._1] == math
+
+
+def test_output_style():
+    tokens = md0.parse("a\\\nb ![i](p.png)\n\n---")
+    assert md0.tokens_to_html(tokens) == '<p>a<br>\nb <img src="p.png" alt="i"></p>\n<hr>'
+    assert md0.tokens_to_html(tokens, md0.HtmlOptions(output_style="xhtml")) == (
+        '<p>a<br />\nb <img src="p.png" alt="i" /></p>\n<hr />'
+    )
+
+    details = md0.Element("details", {"open": ""}, [md0.Element("input", {"type": "checkbox", "checked": ""})])
+    assert details.to_html() == '<details open><input type="checkbox" checked></details>'
+    assert details.to_html(output_style="xhtml") == (
+        '<details open="open"><input type="checkbox" checked="checked" /></details>'
+    )
+
+    assert md0.HtmlOptions(output_style="xhtml").output_style == "xhtml"
+    with pytest.raises(ValueError):
+        md0.HtmlOptions(output_style="xml")
+
+
+def test_xhtml_output_is_well_formed_xml():
+    spec = json.loads(Path(__file__).with_name("spec.json").read_text(encoding="utf-8"))
+    documents = [path.read_text(encoding="utf-8") for path in CORPUS] + [example["markdown"] for example in spec]
+    parse_options = md0.ParseOptions(definition_lists=True, abbreviations=True, autolink_emails=True, math=True)
+    options = md0.HtmlOptions(
+        output_style="xhtml", heading_ids=True, lazy_images=True, figures=True, group_images=True, obfuscate_emails=True
+    )
+
+    for document in documents:
+        html = md0.tokens_to_html(md0.parse(document, parse_options), options)
+
+        ElementTree.fromstring(f"<body>{html}</body>")
+
+
+def test_trim_code_lines():
+    tokens = md0.parse("```py\nx = 1   \n\t\ny = 2\t\n```")
+
+    assert md0.tokens_to_html(tokens) == '<pre><code class="language-py">x = 1   \n\t\ny = 2\t\n</code></pre>'
+    assert md0.tokens_to_html(tokens, md0.HtmlOptions(trim_code_lines=True)) == (
+        '<pre><code class="language-py">x = 1\n\ny = 2\n</code></pre>'
+    )
+
+
+def test_collapse_level():
+    tokens = md0.parse("# Guide\n\nIntro\n\n## Install\n\nRun it\n\n### Linux\n\napt\n\n## Use\n\nSee [install](#install)\n\n# Next")
+    options = md0.HtmlOptions(heading_ids=True, collapse_level=2, open_first=True)
+
+    assert md0.tokens_to_html(tokens, options) == (
+        '<h1 id="guide">Guide</h1>\n<p>Intro</p>\n'
+        '<details open><summary><h2 id="install">Install</h2></summary>\n'
+        '<p>Run it</p>\n<h3 id="linux">Linux</h3>\n<p>apt</p>\n</details>\n'
+        '<details><summary><h2 id="use">Use</h2></summary>\n'
+        '<p>See <a href="#install">install</a></p>\n</details>\n'
+        '<h1 id="next">Next</h1>'
+    )
+    assert "<details>" in md0.tokens_to_html(tokens, md0.HtmlOptions(collapse_level=2))
+    assert "details" not in md0.tokens_to_html(tokens, md0.HtmlOptions(collapse_level=4))
+
+    xhtml = md0.HtmlOptions(collapse_level=2, open_first=True, output_style="xhtml")
+    assert '<details open="open">' in md0.tokens_to_html(tokens, xhtml)
+    with pytest.raises(ValueError):
+        md0.HtmlOptions(collapse_level=7)
+
+
+def test_sanitize_control_chars():
+    markdown = "a\x00b [l\x01](u) c\x1b[0m\td\n```\n\x07\n```"
+    tokens = md0.parse(markdown)
+
+    assert tokens[0][0] == "a\x00b [l\x01](u) c\x1b[0m\td"
+    assert md0.tokens_to_html(tokens) == (
+        '<p>a�b <a href="u">l�</a> c�[0m\td</p>\n<pre><code>�\n</code></pre>'
+    )
+    assert "\x00" in md0.tokens_to_html(tokens, md0.HtmlOptions(sanitize_control_chars=False))
+    assert md0.inline_to_html("x\x00") == "x�"
+
+    sanitized = md0.parse(markdown, md0.ParseOptions(sanitize_control_chars=True, keep_raw=True))
+    text, (link,) = sanitized[0][0], sanitized[0][1]
+    assert text == "a�b [l�](u) c�[0m\td"
+    assert text.encode()[link.location[0] : link.location[1]] == "[l�](u)".encode()
+    assert sanitized[0].raw == "a\x00b [l\x01](u) c\x1b[0m\td"
+    assert sanitized[1].content == "�\n"
+
+    paste = Path(__file__).with_name("fixtures").joinpath("binary-paste.md").read_text(encoding="utf-8")
+    html = md0.tokens_to_html(md0.parse(paste), md0.HtmlOptions(output_style="xhtml"))
+    ElementTree.fromstring(f"<body>{html}</body>")
+
+
+def test_wiki_links():
+    options = md0.ParseOptions(wiki_links=True)
+    tokens = md0.parse("See [[Page Name]], [[Setup Guide#Linux|install it]] and [[#Usage]].", options)
+
+    assert [repr(m) for m in tokens[0]._1] == [
+        'WikiLink((4, 17), "Page Name")',
+        'WikiLink((19, 51), "Setup Guide#Linux", "install it")',
+        'WikiLink((56, 66), "#Usage")',
+    ]
+    assert md0.tokens_to_html(tokens) == (
+        '<p>See <a href="page-name">Page Name</a>, <a href="setup-guide#linux">install it</a>'
+        ' and <a href="#usage">#Usage</a>.</p>'
+    )
+    assert md0.parse("[[Page Name]]")[0]._1 == []
+
+    json_link = json.loads(md0.tokens_to_json(tokens))[0]["metadata"][1]
+    assert (json_link["kind"], json_link["target"], json_link["alias"]) == ("wiki_link", "Setup Guide#Linux", "install it")
+
+
+@pytest.mark.parametrize(
+    "markdown, html",
+    [
+        ("[x](y) and [[Page]]", '<p><a href="y">x</a> and <a href="page">Page</a></p>'),
+        ("[[Page]](y)", '<p><a href="page">Page</a>(y)</p>'),
+        ("![[diagram.png]]", "<p>![[diagram.png]]</p>"),
+        ("`[[code]]` [[ ]]", "<p>`[[code]]` [[ ]]</p>"),
+    ],
+)
+def test_wiki_links_leave_other_links_alone(markdown, html):
+    assert md0.tokens_to_html(md0.parse(markdown, md0.ParseOptions(wiki_links=True))) == html
@@ -0,0 +1,249 @@
+"""Property tests over randomly generated documents.
+
+Documents are built from the constructs md0 understands and checked
+against invariants that must hold for any input. A failing document is
+shrunk to a minimal one before it is reported. Minimal documents that once
+broke an invariant live in ``regressions/`` and are checked on every run.
+
+Writing the tokens back out with ``tokens_to_markdown`` and parsing the
+result must render the same HTML when nothing is wrapped, and writing must
+be a fixed point at any width. Wrapping joins the spaces at each break, so
+it can change the text itself.
+"""
+
+import random
+from html.parser import HTMLParser
+from pathlib import Path
+
+import pytest
+import md0
+
+REGRESSIONS = sorted(Path(__file__).with_name("regressions").glob("*.md"))
+VOID = {"br", "hr", "img"}
+
+WORDS = ["alpha", "beta", "HTML", "x", "ünï", "*", "`code`", "\\#", "a@b.co", "<https://e.com>", "$5", "$x^2$"]
+
+
+def words(rng, n):
+    return " ".join(rng.choice(WORDS) for _ in range(n))
+
+
+def inline(rng):
+    text = words(rng, rng.randint(1, 6))
+    for _ in range(rng.randint(0, 2)):
+        at = rng.randint(0, len(text))
+        link = rng.choice(["[l](u)", "![i](p.png)", "![](p.png =10x)", "[a](<b>)", "[", "](", ")", "{{ y }}", "{{", "}}", "[[P q]]", "[[P|a]]", "[["])
+        text = text[:at] + link + text[at:]
+    return text
+
+
+def fence(rng):
+    marker = rng.choice("`~")
+    length = rng.randint(3, 5)
+    info = rng.choice(["", "py", "js title=x", " rust "])
+    body = "\n".join(rng.choice(["code", marker * 3, marker * (length + 1), "", "  # not heading"]) for _ in range(rng.randint(0, 3)))
+    close = rng.choice([marker * length, marker * (length + 1), marker * (length - 1), ""])
+    return f"{marker * length}{info}\n{body}\n{close}"
+
+
+BLOCKS = [
+    lambda rng: "#" * rng.randint(1, 7) + " " + inline(rng),
+    lambda rng: "\n".join(inline(rng) for _ in range(rng.randint(1, 3))),
+    lambda rng: rng.choice(["---", "-----", "--- x"]),
+    lambda rng: inline(rng) + "\n---",
+    fence,
+    lambda rng: f"{words(rng, 1)}\n: {inline(rng)}" + rng.choice(["", "\n  more", "\n\n  para"]),
+    lambda rng: f"*[{rng.choice(['HTML', 'x'])}]: {words(rng, 2)}",
+    lambda rng: rng.choice(["＃", "＃＃", "#", "==", "= "]) + rng.choice(["", " ", "\u3000"]) + rng.choice(["标题", "#话题#", inline(rng)]),
+    lambda rng: f":::{rng.choice(['note', 'tip Title', 'x'])}\n{inline(rng)}" + rng.choice(["\n:::", "\n::::", "", "\n:::y\n:::\n:::"]),
+    lambda rng: rng.choice(["$$", "$$x"]) + rng.choice(["", "\na < b", "\n$x$"]) + rng.choice(["$$", "\n$$", "\ny$$", ""]),
+]
+SEPARATORS = ["\n", "\n\n", "\n  \n", "\n\n\n", "\r\n", "\n\u00a0\n", "\n\u200b\n", "\n \t"]
+
+
+def document(rng):
+    parts = [rng.choice(BLOCKS)(rng) for _ in range(rng.randint(1, 8))]
+    return "".join(part + rng.choice(SEPARATORS) for part in parts)[: rng.randint(0, 400)]
+
+
+def options(rng, abbreviations=True):
+    return md0.ParseOptions(
+        trim_lines=rng.random() < 0.8,
+        collapse_whitespace=rng.random() < 0.3,
+        keep_raw=True,
+        definition_lists=rng.random() < 0.5,
+        abbreviations=abbreviations and rng.random() < 0.5,
+        autolink_emails=rng.random() < 0.5,
+        nbsp_is_whitespace=rng.random() < 0.8,
+        tab_width=rng.choice([2, 4, 8]),
+        containers=rng.random() < 0.5,
+        source_spans=rng.random() < 0.5,
+        lenient_headings=rng.random() < 0.5,
+        preserve_delimiters=rng.choice([[], [("{{", "}}")], [("{{", "}}"), ("{", "}")]]),
+        math=rng.random() < 0.5,
+        wiki_links=rng.random() < 0.5,
+    )
+
+
+class TagBalance(HTMLParser):
+    def __init__(self):
+        super().__init__()
+        self.stack, self.errors = [], []
+
+    def handle_starttag(self, tag, attrs):
+        if tag not in VOID:
+            self.stack.append(tag)
+
+    def handle_startendtag(self, tag, attrs):
+        pass
+
+    def handle_endtag(self, tag):
+        if not self.stack or self.stack.pop() != tag:
+            self.errors.append(tag)
+
+
+def walk(tokens):
+    for token in tokens:
+        yield token
+        if token.kind == "container":
+            yield from walk(token.content)
+
+
+def without_code(tokens):
+    """Leaves out code blocks, which may end lines with spaces, at any depth."""
+    return [
+        md0.Token.Container(token.name, token.attrs, without_code(token.content)) if token.kind == "container" else token
+        for token in tokens
+        if token.kind != "code"
+    ]
+
+
+def violation(markdown, opts):
+    """Returns the first invariant `markdown` breaks, or ``None``."""
+    try:
+        tokens = md0.parse(markdown, opts)
+        html = md0.tokens_to_html(tokens)
+    except BaseException as error:  # PyO3 panics are not `Exception`s
+        return f"raised {error!r}"
+
+    source = markdown.encode()
+    end = 0
+    for token in tokens:
+        start, stop = token.span
+        if not end <= start <= stop <= len(source):
+            return f"span {token.span} of {token!r} is out of order or out of bounds"
+        if source[start:stop].decode() != token.raw:
+            return f"raw of {token!r} is not its source"
+        end = stop
+        if token.kind == "paragraph":
+            starts = [m.location[0] for m in token.metadata]
+            if starts != sorted(starts):
+                return f"metadata of {token!r} is out of order"
+
+    for token in walk(tokens) if opts.source_spans else []:
+        if token.kind == "paragraph":
+            texts = [(token._0, token.metadata)]
+        elif token.kind == "definition_list":
+            texts = [definition for _, definitions in token.items for definition in definitions]
+        else:
+            continue
+        for text, metadata in texts:
+            for m in metadata:
+                if not isinstance(m, (md0.Metadata.Link, md0.Metadata.Image)):
+                    continue
+                start, stop = m.span
+                linked = text.encode()[m.location[0] : m.location[1]].decode()
+                if not token.span[0] <= start < stop <= token.span[1]:
+                    return f"span {m.span} of {m!r} is outside of {token!r}"
+                if "".join(source[start:stop].decode().split()) != "".join(linked.split()):
+                    return f"span {m.span} of {m!r} is not its source"
+
+    if not markdown.endswith("\n"):
+        ended = md0.parse(markdown + "\n", opts)
+        if [(repr(t), t.span, t.raw) for t in ended] != [(repr(t), t.span, t.raw) for t in tokens]:
+            return "a final line break changes the tokens"
+
+    # Raw spans are written out as they are, so they may hold any HTML
+    balance = TagBalance()
+    balance.feed(html)
+    balance.close()
+    raw = opts.preserve_delimiters and "<" in markdown
+    if not raw and (balance.errors or balance.stack):
+        return f"unbalanced tags in {html!r}"
+
+    prose = without_code(tokens)
+    for width in (None, 80, 12):
+        written = md0.tokens_to_markdown(tokens, width=width)
+        again = md0.parse(written, opts)
+
+        if any(line != line.rstrip(" \t\r") for line in md0.tokens_to_markdown(prose, width=width).split("\n")):
+            return f"trailing spaces when written out at width {width}: {written!r}"
+        if width is None and opts.trim_lines and md0.tokens_to_html(again) != html:
+            return f"HTML changes when written out: {written!r}"
+        # Untrimmed continuation lines of a definition keep the indent the
+        # writer has to give them
+        indented = not opts.trim_lines and any(t.kind == "definition_list" for t in walk(tokens))
+        if not indented and md0.tokens_to_markdown(again, width=width) != written:
+            return f"writing is not a fixed point at width {width}: {written!r}"
+
+    return None
+
+
+def shrink(markdown, fails):
+    """Drops lines, then characters, for as long as `fails` still holds."""
+    for split, join in ((lambda s: s.split("\n"), "\n".join), (list, "".join)):
+        pieces = split(markdown)
+        i = 0
+        while i < len(pieces):
+            candidate = pieces[:i] + pieces[i + 1 :]
+            if fails(join(candidate)):
+                pieces = candidate
+            else:
+                i += 1
+        markdown = join(pieces)
+
+    return markdown
+
+
+def check(markdown, opts):
+    if violation(markdown, opts):
+        minimal = shrink(markdown, lambda text: violation(text, opts))
+        pytest.fail(f"{violation(minimal, opts)}\nminimal document: {minimal!r}")
+
+
+def test_shrink_finds_a_minimal_document():
+    assert shrink("# Title\n\ntext\n---\nmore", lambda text: "---" in text) == "---"
+
+
+def test_random_documents_keep_invariants():
+    rng = random.Random(116)
+
+    for _ in range(500):
+        check(document(rng), options(rng))
+
+
+def test_streamed_documents_parse_the_same():
+    rng = random.Random(144)
+
+    for _ in range(300):
+        markdown, opts = document(rng), options(rng, abbreviations=False)
+        cuts = sorted(rng.randint(0, len(markdown)) for _ in range(rng.randint(0, 12)))
+
+        parser = md0.StreamParser(opts)
+        streamed = []
+        for start, end in zip([0] + cuts, cuts + [len(markdown)]):
+            parser.feed(markdown[start:end])
+            if rng.random() < 0.5:
+                streamed += parser.drain()
+        streamed += parser.finish()
+
+        expected = md0.tokens_to_json(md0.parse(markdown, opts))
+        assert md0.tokens_to_json(streamed) == expected, (markdown, cuts)
+
+
+@pytest.mark.parametrize("path", REGRESSIONS)
+def test_regressions(path):
+    markdown = path.read_text(encoding="utf-8")
+
+    for seed in range(8):
+        check(markdown, options(random.Random(seed)))
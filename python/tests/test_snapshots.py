@@ -0,0 +1,139 @@
+"""Golden tests: every ``fixtures/*.md`` is parsed and its tokens compared
+with the ``*.tokens.json`` snapshot next to it, as ``tokens_to_json`` dumps
+them, one token to a line. A fixture parsed with options has them in ``fixtures/options.json``.
+
+After a change to the parser, run the tests with ``MD0_UPDATE_SNAPSHOTS=1``
+to write the snapshots afresh, and review the diff they make: it is the
+change in behavior.
+"""
+
+import json
+import os
+from pathlib import Path
+
+import pytest
+import md0
+
+FIXTURES = Path(__file__).with_name("fixtures")
+DOCUMENTS = sorted(FIXTURES.glob("*.md"))
+OPTIONS = json.loads((FIXTURES / "options.json").read_text(encoding="utf-8"))
+UPDATE = os.environ.get("MD0_UPDATE_SNAPSHOTS") == "1"
+
+
+def options(name):
+    options = dict(OPTIONS.get(name, {}))
+    if "preserve_delimiters" in options:
+        options["preserve_delimiters"] = [tuple(pair) for pair in options["preserve_delimiters"]]
+
+    return md0.ParseOptions(**options)
+
+
+def dump(tokens):
+    """Writes a snapshot with a token on each line, so a diff of it shows
+    which tokens changed."""
+    lines = [json.dumps(token, ensure_ascii=False) for token in tokens]
+    return "[\n" + ",\n".join(lines) + "\n]\n" if lines else "[]\n"
+
+
+def difference(expected, actual, path=""):
+    """Returns where `actual` first differs from `expected`, and how, or
+    ``None`` if they are equal."""
+    if isinstance(expected, dict) and isinstance(actual, dict):
+        for key in list(expected) + [key for key in actual if key not in expected]:
+            if key not in actual:
+                return f"{path}.{key}: missing, expected {expected[key]!r}"
+            if key not in expected:
+                return f"{path}.{key}: unexpected {actual[key]!r}"
+            found = difference(expected[key], actual[key], f"{path}.{key}")
+            if found:
+                return found
+        return None
+
+    if isinstance(expected, list) and isinstance(actual, list):
+        for i, (a, b) in enumerate(zip(expected, actual)):
+            found = difference(a, b, f"{path}[{i}]")
+            if found:
+                return found
+        if len(expected) != len(actual):
+            return f"{path}: expected {len(expected)} items, got {len(actual)}"
+        return None
+
+    if expected != actual:
+        return f"{path}: expected {expected!r}, got {actual!r}"
+    return None
+
+
+def report(expected, actual):
+    """Describes the first token where two dumps diverge."""
+    found = difference(expected, actual)
+    if found is None:
+        return None
+
+    # The path starts with the index of the token, as `[3]...`, unless the
+    # number of tokens is what differs
+    if not found.startswith("["):
+        return f"tokens{found}"
+    index = int(found[1 : found.index("]")])
+
+    kinds = expected[index]["kind"], actual[index]["kind"]
+    kind = kinds[0] if kinds[0] == kinds[1] else " -> ".join(kinds)
+    return f"token {index} ({kind}) diverged at {found}"
+
+
+@pytest.mark.parametrize("path", DOCUMENTS, ids=lambda path: path.stem)
+def test_snapshot(path):
+    markdown = path.read_bytes().decode("utf-8")
+    actual = json.loads(md0.tokens_to_json(md0.parse(markdown, options(path.stem))))
+    snapshot = path.with_suffix(".tokens.json")
+
+    if UPDATE:
+        snapshot.write_text(dump(actual), encoding="utf-8")
+        return
+    if not snapshot.exists():
+        pytest.fail(f"{path.name} has no snapshot, run with MD0_UPDATE_SNAPSHOTS=1 to write one")
+
+    expected = json.loads(snapshot.read_text(encoding="utf-8"))
+    problem = report(expected, actual)
+    if problem:
+        pytest.fail(f"{path.name}: {problem}\nrun with MD0_UPDATE_SNAPSHOTS=1 to accept the change")
+
+
+def test_fixtures_cover_every_token_kind():
+    kinds = set()
+
+    def collect(tokens):
+        for token in tokens:
+            kinds.add(token["kind"])
+            collect(token.get("content", []) if token["kind"] == "container" else [])
+
+    for path in DOCUMENTS:
+        markdown = path.read_bytes().decode("utf-8")
+        collect(json.loads(md0.tokens_to_json(md0.parse(markdown, options(path.stem)))))
+
+    assert len(DOCUMENTS) >= 20
+    assert set(OPTIONS) <= {path.stem for path in DOCUMENTS}
+    assert kinds == {
+        "heading",
+        "paragraph",
+        "horizontal_rule",
+        "definition_list",
+        "abbreviation",
+        "code",
+        "container",
+        "math_block",
+    }
+
+
+def test_report_names_the_diverging_token():
+    heading = {"kind": "heading", "level": 1, "content": "A"}
+    paragraph = {"kind": "paragraph", "text": "b", "metadata": [{"kind": "link", "url": "x"}]}
+
+    assert report([heading, paragraph], [heading, paragraph]) is None
+    changed = {**paragraph, "metadata": [{"kind": "link", "url": "y"}]}
+    assert report([heading, paragraph], [heading, changed]) == (
+        "token 1 (paragraph) diverged at [1].metadata[0].url: expected 'x', got 'y'"
+    )
+    assert report([heading, paragraph], [heading, heading]) == (
+        "token 1 (paragraph -> heading) diverged at [1].kind: expected 'paragraph', got 'heading'"
+    )
+    assert report([heading], [heading, paragraph]) == "tokens: expected 1 items, got 2"
@@ -0,0 +1,95 @@
+"""Runs the CommonMark spec examples through md0 and reports how many pass.
+
+``spec.json`` holds the examples of CommonMark 0.31.2 (CC-BY-SA 4.0). Point
+``MD0_COMMONMARK_SPEC`` at another version's ``spec.json`` to try it instead.
+
+``commonmark_failing.txt`` lists the examples md0 is known to fail. The suite
+fails on any other failure, and also when a listed example starts passing, so
+take it off the list along with the change that fixed it.
+"""
+
+import json
+import os
+import re
+from pathlib import Path
+
+import pytest
+import md0
+
+SPEC = Path(os.environ.get("MD0_COMMONMARK_SPEC", Path(__file__).with_name("spec.json")))
+FAILING = Path(__file__).with_name("commonmark_failing.txt")
+
+PRE_RE = re.compile(r"(<pre\b.*?</pre>)", re.S)
+TAG_RE = re.compile(r"<([a-zA-Z][a-zA-Z0-9-]*)((?:\s+[^\s=/>]+(?:=\"[^\"]*\"|='[^']*'|=[^\s>]+)?)*)\s*/?>")
+ATTRIBUTE_RE = re.compile(r"([^\s=/>]+)(?:=(\"[^\"]*\"|'[^']*'|[^\s>]+))?")
+
+
+def normalize_tag(match):
+    """Sorts the attributes of a start tag and drops any self-closing slash."""
+    name, attributes = match.groups()
+    attributes = sorted(
+        (key.lower(), value.strip("\"'") if value is not None else None)
+        for key, value in ATTRIBUTE_RE.findall(attributes)
+    )
+    attributes = "".join(f' {key}="{value}"' if value is not None else f" {key}" for key, value in attributes)
+
+    return f"<{name.lower()}{attributes}>"
+
+
+def normalize_html(html):
+    """Collapses whitespace between and around tags, except inside ``<pre>``,
+    and writes every start tag the same way."""
+    parts = PRE_RE.split(html.strip())
+
+    for i in range(0, len(parts), 2):
+        text = re.sub(r"\s+", " ", parts[i])
+        parts[i] = re.sub(r"\s*(<[^>]+>)\s*", r"\1", text)
+
+    return TAG_RE.sub(normalize_tag, "".join(parts).strip())
+
+
+def render(markdown):
+    return md0.tokens_to_html(md0.parse(markdown))
+
+
+def run_examples(examples):
+    passed, failed = [], []
+
+    for example in examples:
+        try:
+            ok = normalize_html(render(example["markdown"])) == normalize_html(example["html"])
+        except BaseException:  # PyO3 panics are not `Exception`s
+            ok = False
+
+        (passed if ok else failed).append(example["example"])
+
+    return passed, failed
+
+
+def known_failures():
+    lines = FAILING.read_text(encoding="utf-8").splitlines()
+
+    return {int(line) for line in lines if line.strip() and not line.startswith("#")}
+
+
+def test_normalize_html():
+    assert normalize_html("<p>a\n  b</p>\n<hr />\n") == "<p>a b</p><hr>"
+    assert normalize_html("<pre><code>a\n  b\n</code></pre>\n") == "<pre><code>a\n  b\n</code></pre>"
+    assert normalize_html('<img src="a" alt=\'b\' />') == normalize_html('<IMG alt="b" src="a">')
+
+
+def test_commonmark_spec():
+    if not SPEC.exists():
+        pytest.skip(f"{SPEC} not found")
+
+    examples = json.loads(SPEC.read_text(encoding="utf-8"))
+    passed, failed = run_examples(examples)
+
+    print(f"CommonMark spec: {len(passed)}/{len(examples)} examples pass")
+    assert len(passed) + len(failed) == len(examples)
+
+    if SPEC.parent == FAILING.parent:
+        known = known_failures()
+
+        assert not set(failed) - known, f"examples that regressed: {sorted(set(failed) - known)}"
+        assert not known - set(failed), f"examples that pass now, take them off the list: {sorted(known - set(failed))}"
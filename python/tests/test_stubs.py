@@ -0,0 +1,88 @@
+"""Cross-checks ``md0.pyi`` against the compiled module, so the two can't drift."""
+
+import ast
+import inspect
+from pathlib import Path
+
+import pytest
+import md0
+
+STUB = Path(md0.__file__).with_name("md0.pyi")
+
+
+def public(names):
+    return {name for name in names if not name.startswith("__")}
+
+
+def stub_classes(body):
+    return {node.name: node for node in body if isinstance(node, ast.ClassDef)}
+
+
+def stub_functions(body):
+    return {node.name: node for node in body if isinstance(node, ast.FunctionDef)}
+
+
+def stub_attributes(body):
+    return {node.target.id for node in body if isinstance(node, ast.AnnAssign)}
+
+
+def stub_parameters(function):
+    arguments = function.args
+    names = [argument.arg for argument in arguments.posonlyargs + arguments.args + arguments.kwonlyargs]
+
+    return [name for name in names if name != "self"]
+
+
+def runtime_parameters(obj):
+    try:
+        signature = inspect.signature(obj)
+    except (TypeError, ValueError):
+        return None
+
+    return [name for name in signature.parameters if name != "self"]
+
+
+def check_function(function, runtime):
+    parameters = runtime_parameters(runtime)
+
+    if parameters is not None:
+        assert stub_parameters(function) == parameters, function.name
+
+
+def check_class(node, runtime):
+    nested = stub_classes(node.body)
+    functions = stub_functions(node.body)
+    documented = set(nested) | set(functions) | stub_attributes(node.body)
+
+    # Exceptions get their attributes when they are raised, not from their
+    # class.
+    if issubclass(runtime, BaseException):
+        documented -= stub_attributes(node.body)
+
+    # `vars` rather than `dir`: enum variants inherit their siblings as
+    # attributes, but only a class's own names belong in its stub.
+    assert public(vars(runtime)) == public(documented), node.name
+
+    for name, function in functions.items():
+        check_function(function, runtime if name == "__init__" else getattr(runtime, name))
+
+    for name, child in nested.items():
+        check_class(child, getattr(runtime, name))
+
+
+def test_stub_matches_module():
+    tree = ast.parse(STUB.read_text(encoding="utf-8"))
+    classes, functions = stub_classes(tree.body), stub_functions(tree.body)
+
+    assert set(classes) | set(functions) == public(dir(md0.md0))
+
+    for name, function in functions.items():
+        check_function(function, getattr(md0, name))
+
+    for name, node in classes.items():
+        check_class(node, getattr(md0, name))
+
+
+def test_stub_is_shipped():
+    assert STUB.exists()
+    assert STUB.with_name("py.typed").exists()
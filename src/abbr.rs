@@ -0,0 +1,168 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    element::Node,
+    escape::{escape_attr, escape_prose, escape_text},
+    options::HtmlOptions,
+    parser::{self, Metadata, Token},
+};
+
+/// Collects the abbreviations defined in `tokens`. A later definition of the
+/// same abbreviation replaces an earlier one.
+pub(crate) fn abbreviations(tokens: &[Token]) -> BTreeMap<String, String> {
+    parser::walk(tokens)
+        .into_iter()
+        .filter_map(|token| match token {
+            Token::Abbreviation {
+                abbr, expansion, ..
+            } => Some((abbr.clone(), expansion.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Finds the uses of a document's abbreviations.
+pub(crate) struct Abbreviator {
+    /// Longest first.
+    abbreviations: Vec<(String, String)>,
+}
+
+impl From<BTreeMap<String, String>> for Abbreviator {
+    fn from(abbreviations: BTreeMap<String, String>) -> Self {
+        let mut abbreviations: Vec<_> = abbreviations.into_iter().collect();
+        abbreviations.sort_by_key(|(abbr, _)| std::cmp::Reverse(abbr.len()));
+
+        Self { abbreviations }
+    }
+}
+
+impl Abbreviator {
+    pub(crate) fn new(tokens: &[Token]) -> Self {
+        abbreviations(tokens).into()
+    }
+
+    /// Returns the start of every whole-word occurrence of an abbreviation in
+    /// `text`, with the abbreviation and its expansion.
+    ///
+    /// The longest abbreviation that matches at a position wins, and nothing
+    /// inside a backtick code span is matched.
+    fn matches<'a>(&'a self, text: &str) -> Vec<(usize, &'a str, &'a str)> {
+        let mut matches = Vec::new();
+        if self.abbreviations.is_empty() {
+            return matches;
+        }
+
+        let mut code: Option<usize> = None;
+        let mut previous: Option<char> = None;
+        let mut i = 0;
+
+        while i < text.len() {
+            let rest = &text[i..];
+
+            if rest.starts_with('`') {
+                let run = rest.len() - rest.trim_start_matches('`').len();
+
+                code = match code {
+                    Some(open) if open == run => None,
+                    None if text[i + run..].contains(&"`".repeat(run)) => Some(run),
+                    open => open,
+                };
+                previous = Some('`');
+                i += run;
+                continue;
+            }
+
+            if code.is_none() && !previous.is_some_and(is_word) {
+                let found = self.abbreviations.iter().find(|(abbr, _)| {
+                    rest.starts_with(abbr.as_str())
+                        && !rest[abbr.len()..].chars().next().is_some_and(is_word)
+                });
+
+                if let Some((abbr, expansion)) = found {
+                    matches.push((i, abbr.as_str(), expansion.as_str()));
+                    i += abbr.len();
+                    previous = abbr.chars().last();
+                    continue;
+                }
+            }
+
+            let c = rest.chars().next().unwrap();
+            previous = Some(c);
+            i += c.len_utf8();
+        }
+
+        matches
+    }
+
+    /// Escapes `text` as HTML the way `options` ask, wrapping every use of
+    /// an abbreviation in `<abbr>`.
+    pub(crate) fn html(&self, text: &str, options: &HtmlOptions) -> Vec<Node> {
+        let mut nodes = Vec::new();
+        // Start of the text not yet written out
+        let mut plain = 0;
+
+        for (start, abbr, expansion) in self.matches(text) {
+            nodes.push(Node::Html(escape_prose(&text[plain..start], options)));
+            nodes.push(abbr_html(abbr, expansion));
+            plain = start + abbr.len();
+        }
+
+        nodes.push(Node::Html(escape_prose(&text[plain..], options)));
+        nodes
+    }
+
+    /// Replaces the `Metadata::Abbreviation`s of a paragraph or definition
+    /// list, or of those in a container, with the uses of these abbreviations, leaving out those inside
+    /// a link or image.
+    pub(crate) fn annotate(&self, token: &mut Token) {
+        match token {
+            Token::Paragraph(text, metadata, ..) => self.annotate_text(text, metadata),
+            Token::DefinitionList { items, .. } => {
+                for (_, definitions) in items {
+                    for (text, metadata) in definitions {
+                        self.annotate_text(text, metadata);
+                    }
+                }
+            }
+            Token::Container { content, .. } => {
+                content.iter_mut().for_each(|token| self.annotate(token));
+            }
+            _ => {}
+        }
+    }
+
+    fn annotate_text(&self, text: &str, metadata: &mut Vec<Metadata>) {
+        metadata.retain(|metadata| !matches!(metadata, Metadata::Abbreviation { .. }));
+        let others = metadata.len();
+
+        for (start, abbr, expansion) in self.matches(text) {
+            let end = start + abbr.len();
+            let inside = metadata[..others].iter().any(|metadata| {
+                let (s, e) = metadata.location();
+                start < e && s < end
+            });
+
+            if !inside {
+                metadata.push(Metadata::Abbreviation {
+                    location: (start, end),
+                    short: abbr.to_string(),
+                    title: expansion.to_string(),
+                });
+            }
+        }
+
+        parser::sort_metadata(metadata);
+    }
+}
+
+pub(crate) fn abbr_html(abbr: &str, expansion: &str) -> Node {
+    Node::element(
+        "abbr",
+        vec![("title", escape_attr(expansion))],
+        vec![Node::Html(escape_text(abbr))],
+    )
+}
@@ -0,0 +1,258 @@
+use std::collections::{BTreeMap, HashSet};
+
+use pyo3::prelude::*;
+
+use crate::{
+    parser::{self, Metadata, Span, Token},
+    slug::{SlugStyle, Slugger},
+};
+
+/// A link to a heading that does not exist, found by `check_anchors`.
+#[pyclass(frozen)]
+#[derive(Clone)]
+pub(crate) struct AnchorProblem {
+    /// The document the link is in, for `check_anchors_many`.
+    #[pyo3(get)]
+    path: Option<String>,
+    #[pyo3(get)]
+    label: String,
+    #[pyo3(get)]
+    url: String,
+    /// The span of the token holding the link.
+    #[pyo3(get)]
+    span: Span,
+    /// Where the link is in the text of that token, like `Metadata.location`.
+    #[pyo3(get)]
+    location: (usize, usize),
+    #[pyo3(get)]
+    message: String,
+}
+
+#[pymethods]
+impl AnchorProblem {
+    #[pyo3(name = "__repr__")]
+    pub fn py_repr(&self) -> String {
+        format!("AnchorProblem({:?}, {:?})", self.url, self.message)
+    }
+}
+
+/// Maps the id the renderer gives each heading of a document to the text of
+/// that heading.
+pub(crate) fn anchor_map(tokens: &[Token], style: SlugStyle) -> BTreeMap<String, String> {
+    let mut slugger = Slugger::new(style);
+
+    parser::walk(tokens)
+        .into_iter()
+        .filter_map(|token| match token {
+            Token::Heading { content, .. } => Some((slugger.slug(content), content.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The ids the renderer gives the headings of a document.
+fn heading_ids(tokens: &[Token], style: SlugStyle) -> HashSet<String> {
+    anchor_map(tokens, style).into_keys().collect()
+}
+
+/// The fragment, percent-decoded, of a link with no label to a heading of the
+/// same document, like `[](#installation)`. Such a cross reference takes the
+/// text of that heading as its label.
+pub(crate) fn cross_reference(metadata: &Metadata) -> Option<String> {
+    let Metadata::Link { label, url, .. } = metadata else {
+        return None;
+    };
+    let fragment = url.strip_prefix('#')?;
+
+    (label.trim().is_empty() && !fragment.is_empty()).then(|| percent_decode(fragment))
+}
+
+/// Gives every cross reference in `tokens` the text of the heading it points
+/// to as its label, or its URL if `anchors` has no heading with that id, so
+/// that it does not render as an empty link.
+pub(crate) fn resolve_cross_references(tokens: &mut [Token], anchors: &BTreeMap<String, String>) {
+    for token in tokens {
+        let metadata: Vec<&mut Metadata> = match token {
+            Token::Paragraph(_, metadata, ..) => metadata.iter_mut().collect(),
+            Token::DefinitionList { items, .. } => items
+                .iter_mut()
+                .flat_map(|(_, definitions)| definitions)
+                .flat_map(|(_, metadata)| metadata)
+                .collect(),
+            Token::Container { content, .. } => {
+                resolve_cross_references(content, anchors);
+                continue;
+            }
+            _ => continue,
+        };
+
+        for metadata in metadata {
+            let Some(fragment) = cross_reference(metadata) else {
+                continue;
+            };
+            if let Metadata::Link { label, url, .. } = metadata {
+                *label = anchors.get(&fragment).unwrap_or(url).clone();
+            }
+        }
+    }
+}
+
+/// Every link in the paragraphs and definitions of a document, with the span
+/// of its token.
+fn links(tokens: &[Token]) -> Vec<(Span, &Metadata)> {
+    let mut links = Vec::new();
+
+    for token in parser::walk(tokens) {
+        let metadata: Vec<&Metadata> = match token {
+            Token::Paragraph(_, metadata, ..) => metadata.iter().collect(),
+            Token::DefinitionList { items, .. } => items
+                .iter()
+                .flat_map(|(_, definitions)| definitions.iter())
+                .flat_map(|(_, metadata)| metadata.iter())
+                .collect(),
+            _ => continue,
+        };
+
+        links.extend(
+            metadata
+                .into_iter()
+                .filter(|metadata| matches!(metadata, Metadata::Link { .. }))
+                .map(|metadata| (token.span(), metadata)),
+        );
+    }
+
+    links
+}
+
+/// Decodes the `%XX` escapes of a URL fragment.
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match hex {
+            Some(b) if bytes[i] == b'%' => {
+                decoded.push(b);
+                i += 3;
+            }
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Resolves `target` against the directory of the document at `path`, with
+/// `.` and `..` taken out.
+fn resolve(path: &str, target: &str) -> String {
+    let mut parts: Vec<&str> = path.split('/').collect();
+    parts.pop();
+
+    for part in target.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+
+    parts.join("/")
+}
+
+/// Checks the links of every document in `documents` against the headings of
+/// the document they point to.
+///
+/// `#fragment` links point to the same document and `other.md#fragment`
+/// links to another one, relative to the linking document. Links to a
+/// document not in `documents`, links with a scheme, and `#` or `#top` are
+/// not checked.
+pub(crate) fn check_anchors_many(
+    documents: &BTreeMap<String, Vec<Token>>,
+    style: SlugStyle,
+) -> Vec<AnchorProblem> {
+    let ids: BTreeMap<&str, HashSet<String>> = documents
+        .iter()
+        .map(|(path, tokens)| (path.as_str(), heading_ids(tokens, style)))
+        .collect();
+
+    let mut problems = Vec::new();
+
+    for (path, tokens) in documents {
+        for (span, metadata) in links(tokens) {
+            let Metadata::Link {
+                location,
+                label,
+                url,
+                ..
+            } = metadata
+            else {
+                continue;
+            };
+
+            let Some((file, fragment)) = url.split_once('#') else {
+                continue;
+            };
+            if file.contains(':') {
+                continue;
+            }
+
+            let target = if file.is_empty() {
+                path.clone()
+            } else {
+                resolve(path, file)
+            };
+            let Some(headings) = ids.get(target.as_str()) else {
+                continue;
+            };
+
+            // An empty fragment or `#top` goes to the top of the page
+            let fragment = percent_decode(fragment);
+            if fragment.is_empty() || fragment.eq_ignore_ascii_case("top") {
+                continue;
+            }
+
+            if !headings.contains(&fragment) {
+                let message = if file.is_empty() {
+                    format!("no heading with the id {fragment:?}")
+                } else {
+                    format!("no heading with the id {fragment:?} in {target}")
+                };
+
+                problems.push(AnchorProblem {
+                    path: Some(path.clone()),
+                    label: label.clone(),
+                    url: url.clone(),
+                    span,
+                    location: *location,
+                    message,
+                });
+            }
+        }
+    }
+
+    problems
+}
+
+/// Checks the `#fragment` links of one document against its headings.
+pub(crate) fn check_anchors(tokens: Vec<Token>, style: SlugStyle) -> Vec<AnchorProblem> {
+    let documents = BTreeMap::from([(String::new(), tokens)]);
+
+    check_anchors_many(&documents, style)
+        .into_iter()
+        .map(|problem| AnchorProblem {
+            path: None,
+            ..problem
+        })
+        .collect()
+}
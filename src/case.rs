@@ -0,0 +1,161 @@
+use pyo3::{exceptions::PyValueError, prelude::*};
+
+use crate::{
+    markdown,
+    options::ParseOptions,
+    parser::{self, Metadata},
+};
+
+/// Words a title leaves in lower case, unless one starts it.
+const SMALL_WORDS: &[&str] = &[
+    "a", "an", "and", "as", "at", "but", "by", "for", "from", "if", "in", "nor", "of", "on", "or",
+    "per", "so", "the", "to", "up", "via", "vs", "with", "yet",
+];
+
+/// How the renderer changes the case of headings.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum HeadingCase {
+    /// As written.
+    #[default]
+    Preserve,
+    /// The first word capitalized, every other in lower case, except words
+    /// with a capital past their first letter, like `HTML` or `iOS`.
+    Sentence,
+    /// Every word capitalized but [`SMALL_WORDS`] after the first. The rest
+    /// of each word is left as written.
+    Title,
+    /// Everything in upper case.
+    Upper,
+}
+
+impl HeadingCase {
+    pub(crate) fn parse(case: &str) -> PyResult<Self> {
+        match case {
+            "preserve" => Ok(Self::Preserve),
+            "sentence" => Ok(Self::Sentence),
+            "title" => Ok(Self::Title),
+            "upper" => Ok(Self::Upper),
+            _ => Err(PyValueError::new_err(format!(
+                "heading case must be \"preserve\", \"sentence\", \"title\" or \"upper\", got {case:?}"
+            ))),
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Preserve => "preserve",
+            Self::Sentence => "sentence",
+            Self::Title => "title",
+            Self::Upper => "upper",
+        }
+    }
+
+    /// Changes the case of the text of a heading. Code spans, URLs and the
+    /// rest of the syntax of links are kept as they are; only the labels of
+    /// links and images change.
+    pub(crate) fn apply(self, text: &str) -> String {
+        if self == Self::Preserve {
+            return text.to_string();
+        }
+
+        let mut cased = String::with_capacity(text.len());
+        let mut first = true;
+        let mut cursor = 0;
+
+        for (start, end) in kept(text) {
+            self.words(&text[cursor..start], &mut first, &mut cased);
+            cased += &text[start..end];
+            first &= text[start..end].trim().is_empty();
+            cursor = end;
+        }
+        self.words(&text[cursor..], &mut first, &mut cased);
+
+        cased
+    }
+
+    /// Changes the case of every word of `text`, while `first` says whether
+    /// no word of the heading came before.
+    fn words(self, text: &str, first: &mut bool, cased: &mut String) {
+        if self == Self::Upper {
+            *cased += &text.to_uppercase();
+            *first &= text.trim().is_empty();
+            return;
+        }
+
+        for (i, word) in text.split(' ').enumerate() {
+            if i > 0 {
+                cased.push(' ');
+            }
+            if word.is_empty() {
+                continue;
+            }
+
+            let lower = word.to_lowercase();
+            match self {
+                Self::Sentence if *first => *cased += &capitalize(word),
+                Self::Sentence if word.chars().skip(1).any(char::is_uppercase) => *cased += word,
+                Self::Sentence => *cased += &lower,
+                _ if !*first
+                    && SMALL_WORDS
+                        .contains(&lower.trim_matches(|c: char| !c.is_alphanumeric())) =>
+                {
+                    *cased += &lower
+                }
+                _ => *cased += &capitalize(word),
+            }
+            *first = false;
+        }
+    }
+}
+
+/// `word` with its first letter in upper case.
+fn capitalize(word: &str) -> String {
+    match word.char_indices().find(|(_, c)| c.is_alphabetic()) {
+        Some((i, c)) => {
+            let rest = &word[i + c.len_utf8()..];
+            format!("{}{}{rest}", &word[..i], c.to_uppercase())
+        }
+        None => word.to_string(),
+    }
+}
+
+/// The byte ranges of `text` a change of case keeps: its code spans, and all
+/// of its links and images but their labels, in order and not overlapping.
+fn kept(text: &str) -> Vec<(usize, usize)> {
+    let mut ranges = markdown::code_spans(text);
+
+    for metadata in parser::inline_metadata(text, &ParseOptions::default()) {
+        let (start, end) = metadata.location();
+        let label = match &metadata {
+            Metadata::Link { .. } | Metadata::Image { .. }
+                if text[start..].starts_with(['[', '!']) =>
+            {
+                let open = if text[start..].starts_with('!') { 2 } else { 1 };
+                text[start..end]
+                    .find("](")
+                    .map(|close| (start + open, start + close))
+            }
+            _ => None,
+        };
+
+        match label {
+            Some((label_start, label_end)) => {
+                ranges.push((start, label_start));
+                ranges.push((label_end, end));
+            }
+            // An autolink is all URL
+            None => ranges.push((start, end)),
+        }
+    }
+
+    ranges.sort_unstable();
+    let mut merged: Vec<(usize, usize)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+
+    merged
+}
@@ -0,0 +1,89 @@
+use pyo3::prelude::*;
+
+use crate::parser::{self, Span, Token};
+
+/// A code block pulled out of a document by `code_blocks`.
+#[pyclass(frozen)]
+#[derive(Clone)]
+pub(crate) struct CodeBlock {
+    #[pyo3(get)]
+    language: String,
+    /// The whole info string of the opening fence.
+    #[pyo3(get)]
+    info: String,
+    #[pyo3(get)]
+    content: String,
+    #[pyo3(get)]
+    span: Span,
+    /// The first and last source line of the block, fences included, counted
+    /// from 1. Only known when the source was given.
+    #[pyo3(get)]
+    lines: Option<(usize, usize)>,
+    /// Whether the block had a fence, so its content starts a line later.
+    fenced: bool,
+}
+
+#[pymethods]
+impl CodeBlock {
+    #[pyo3(name = "__repr__")]
+    pub fn py_repr(&self) -> String {
+        format!("CodeBlock({:?}, {:?})", self.language, self.content)
+    }
+}
+
+/// Returns the code blocks among `tokens`, keeping only those in `language`
+/// if given. Languages are compared after `normalize_language`, so `py`
+/// matches `python` and case does not matter.
+pub(crate) fn code_blocks(
+    tokens: &[Token],
+    language: Option<&str>,
+    source: Option<&str>,
+) -> Vec<CodeBlock> {
+    let wanted = language.map(parser::normalize_language);
+    let offsets = source.map(parser::line_offsets);
+
+    parser::walk(tokens)
+        .into_iter()
+        .filter_map(|token| match token {
+            Token::Code {
+                language,
+                content,
+                fence_char,
+                span,
+                info,
+                ..
+            } => Some(CodeBlock {
+                language: language.clone(),
+                info: info.clone(),
+                content: content.clone(),
+                span: *span,
+                lines: offsets.as_ref().map(|offsets| {
+                    let line = |offset| offsets.partition_point(|&start| start <= offset);
+                    (line(span.0), line(span.1))
+                }),
+                fenced: fence_char.is_some(),
+            }),
+            _ => None,
+        })
+        .filter(|block| {
+            wanted
+                .as_ref()
+                .is_none_or(|wanted| parser::normalize_language(&block.language) == *wanted)
+        })
+        .collect()
+}
+
+/// Joins the blocks into one script, each preceded by a `# line N` comment
+/// naming the source line its content starts on.
+pub(crate) fn concatenate(blocks: &[CodeBlock]) -> String {
+    let mut script = String::new();
+
+    for block in blocks {
+        if let Some((first, _)) = block.lines {
+            script += &format!("# line {}\n", first + block.fenced as usize);
+        }
+        script += &block.content;
+    }
+
+    script
+}
@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+
+use pyo3::{exceptions::PyValueError, prelude::*};
+
+use crate::{
+    abbr::{self, Abbreviator},
+    options::ParseOptions,
+    parser::{self, Tokens},
+    strict,
+};
+
+/// A parsed document that can be edited in place.
+///
+/// Edits only re-tokenize the blocks they touch and reuse the rest of the
+/// previous tokens, but the result is always the same as parsing the new
+/// text from scratch. With `ParseOptions(strict=True)`, a text or an edit
+/// that leaves a block unclosed raises `ParseError`, and a failed edit
+/// leaves the document as it was.
+#[pyclass]
+pub(crate) struct Document {
+    options: ParseOptions,
+    text: String,
+    tokens: Tokens,
+    /// The line and token index at which every block starts.
+    blocks: Vec<(usize, usize)>,
+    /// The abbreviations the tokens were annotated with.
+    abbreviations: BTreeMap<String, String>,
+}
+
+fn count_lines(text: &str) -> usize {
+    memchr::memchr_iter(b'\n', text.as_bytes()).count()
+}
+
+#[pymethods]
+impl Document {
+    #[new]
+    #[pyo3(signature = (text, options = None))]
+    fn new(text: String, options: Option<ParseOptions>) -> PyResult<Self> {
+        let options = options.unwrap_or_default();
+        let mut tokens: Tokens = Vec::new();
+        let mut blocks = Vec::new();
+
+        parser::parse_blocks(&text, 0, &options, &mut tokens, |line, count| {
+            blocks.push((line, count));
+            true
+        });
+
+        if options.strict {
+            strict::check(&text, 0, &tokens, &options)?;
+        }
+
+        parser::number_tokens(&mut tokens, &mut 0, None);
+
+        let mut document = Self {
+            options,
+            text,
+            tokens,
+            blocks,
+            abbreviations: BTreeMap::new(),
+        };
+        document.annotate(0..document.tokens.len());
+        Ok(document)
+    }
+
+    #[getter]
+    fn text(&self) -> String {
+        self.text.clone()
+    }
+
+    #[getter]
+    fn tokens(&self) -> Tokens {
+        self.tokens.clone()
+    }
+
+    /// The abbreviations defined in the document.
+    #[getter]
+    fn abbreviations(&self) -> BTreeMap<String, String> {
+        self.abbreviations.clone()
+    }
+
+    /// Replaces `old_len` bytes at byte offset `start` with `new_text`.
+    fn edit(&mut self, start: usize, old_len: usize, new_text: String) -> PyResult<()> {
+        let end = start.saturating_add(old_len);
+
+        if end > self.text.len()
+            || !self.text.is_char_boundary(start)
+            || !self.text.is_char_boundary(end)
+        {
+            return Err(PyValueError::new_err(format!(
+                "edit range {start}..{end} is not valid for a text of {} bytes",
+                self.text.len()
+            )));
+        }
+
+        // Lines `first..=last` of the old text are changed, and they become
+        // lines `first..=first + added` of the new one.
+        let first = count_lines(&self.text[..start]);
+        let removed = count_lines(&self.text[start..end]);
+        let added = count_lines(&new_text);
+
+        let mut text = String::with_capacity(self.text.len() - old_len + new_text.len());
+        text.push_str(&self.text[..start]);
+        text.push_str(&new_text);
+        text.push_str(&self.text[end..]);
+
+        // Restart at the last block that begins at least two lines before
+        // the first changed line. The block before that one was decided by
+        // lines up to one past that start, which we did not touch, so it
+        // cannot turn out differently.
+        let old_blocks = std::mem::take(&mut self.blocks);
+        let restart = old_blocks
+            .partition_point(|&(line, _)| line + 1 < first)
+            .saturating_sub(1);
+        let (restart_line, kept) = old_blocks[restart];
+
+        let mut tokens = std::mem::take(&mut self.tokens);
+        let mut tail = tokens.split_off(kept);
+        let mut blocks = old_blocks[..restart].to_vec();
+        let mut resume = None;
+
+        parser::parse_blocks(
+            &text,
+            restart_line,
+            &self.options,
+            &mut tokens,
+            |line, count| {
+                // Past the edit, a block that starts where an old block
+                // started sees the same lines, so the old tokens still hold.
+                if line > first + added {
+                    let old_line = line + removed - added;
+
+                    if let Ok(j) =
+                        old_blocks[restart..].binary_search_by_key(&old_line, |&(line, _)| line)
+                    {
+                        resume = Some(restart + j);
+                        return false;
+                    }
+                }
+
+                blocks.push((line, count));
+                true
+            },
+        );
+
+        let parsed = tokens.len();
+
+        // Only the blocks parsed again can be unclosed: one that is runs to
+        // the end of the text, so no old block is reused after it
+        if self.options.strict {
+            if let Err(error) = strict::check(&text, 0, &tokens[kept..], &self.options) {
+                tokens.truncate(kept);
+                tokens.append(&mut tail);
+                self.tokens = tokens;
+                self.blocks = old_blocks;
+                return Err(error);
+            }
+        }
+
+        if let Some(j) = resume {
+            let reused = old_blocks[j].1;
+            let count = tokens.len();
+
+            // Tokens after the edit keep their text but move in the source
+            tokens.extend(tail.drain(reused - kept..).map(|mut token| {
+                token.map_spans(&|offset| offset + new_text.len() - old_len);
+                token
+            }));
+            blocks.extend(
+                old_blocks[j..]
+                    .iter()
+                    .map(|&(line, index)| (line + added - removed, index - reused + count)),
+            );
+        }
+
+        // Ids count the tokens before them, which the edit changed
+        parser::number_tokens(&mut tokens, &mut 0, None);
+
+        self.text = text;
+        self.tokens = tokens;
+        self.blocks = blocks;
+        self.annotate(kept..parsed);
+
+        Ok(())
+    }
+}
+
+impl Document {
+    /// Annotates the freshly parsed `range` of tokens with the uses of
+    /// abbreviations, or every token if the set of abbreviations changed.
+    fn annotate(&mut self, range: std::ops::Range<usize>) {
+        if !self.options.abbreviations {
+            return;
+        }
+
+        let abbreviations = abbr::abbreviations(&self.tokens);
+        let range = if abbreviations == self.abbreviations {
+            range
+        } else {
+            0..self.tokens.len()
+        };
+
+        self.abbreviations = abbreviations.clone();
+        let abbreviator = Abbreviator::from(abbreviations);
+        self.tokens[range]
+            .iter_mut()
+            .for_each(|token| abbreviator.annotate(token));
+    }
+}
@@ -0,0 +1,299 @@
+use pyo3::{
+    exceptions::{PyTypeError, PyValueError},
+    prelude::*,
+    types::{PyDict, PyList, PyString},
+};
+
+/// Elements that have no content or end tag.
+const VOID: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// Attributes that are on or off, whatever their value.
+const BOOLEAN: &[&str] = &[
+    "allowfullscreen",
+    "async",
+    "autofocus",
+    "autoplay",
+    "checked",
+    "controls",
+    "default",
+    "defer",
+    "disabled",
+    "hidden",
+    "loop",
+    "multiple",
+    "muted",
+    "nomodule",
+    "novalidate",
+    "open",
+    "readonly",
+    "required",
+    "reversed",
+    "selected",
+];
+
+/// How elements are written out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum OutputStyle {
+    /// `<hr>`, and a boolean attribute as its name alone: `<input checked>`.
+    #[default]
+    Html5,
+    /// Well-formed XML, as EPUB wants: `<hr />`, and `checked="checked"`.
+    Xhtml,
+}
+
+impl OutputStyle {
+    pub(crate) fn parse(style: &str) -> PyResult<Self> {
+        match style {
+            "html5" => Ok(Self::Html5),
+            "xhtml" => Ok(Self::Xhtml),
+            _ => Err(PyValueError::new_err(format!(
+                "output style must be \"html5\" or \"xhtml\", got {style:?}"
+            ))),
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Html5 => "html5",
+            Self::Xhtml => "xhtml",
+        }
+    }
+}
+
+/// A piece of the HTML that `tokens_to_html` writes.
+///
+/// The renderer builds these and only turns them into a string at the end, so
+/// the tree handed to Python by `tokens_to_elements` is the HTML itself.
+pub(crate) enum Node {
+    Element {
+        tag: String,
+        /// Attribute values, already escaped, in the order they are written.
+        attrs: Vec<(String, String)>,
+        children: Vec<Node>,
+    },
+    /// HTML written out as it is: escaped text, or markup passed through.
+    Html(String),
+}
+
+impl Node {
+    pub(crate) fn element(
+        tag: impl Into<String>,
+        attrs: Vec<(&str, String)>,
+        children: Vec<Node>,
+    ) -> Self {
+        Self::Element {
+            tag: tag.into(),
+            attrs: attrs
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+            children,
+        }
+    }
+
+    pub(crate) fn write(&self, html: &mut String, style: OutputStyle) {
+        match self {
+            Self::Element {
+                tag,
+                attrs,
+                children,
+            } => {
+                *html += "<";
+                *html += tag;
+                for (name, value) in attrs {
+                    *html += &match style {
+                        _ if !BOOLEAN.contains(&name.as_str()) => format!(" {name}=\"{value}\""),
+                        OutputStyle::Html5 => format!(" {name}"),
+                        OutputStyle::Xhtml => format!(" {name}=\"{name}\""),
+                    };
+                }
+
+                if VOID.contains(&tag.as_str()) && children.is_empty() {
+                    *html += match style {
+                        OutputStyle::Html5 => ">",
+                        OutputStyle::Xhtml => " />",
+                    };
+                    return;
+                }
+
+                *html += ">";
+                for child in children {
+                    child.write(html, style);
+                }
+                *html += &format!("</{tag}>");
+            }
+            Self::Html(text) => *html += text,
+        }
+    }
+
+    pub(crate) fn to_html(&self, style: OutputStyle) -> String {
+        let mut html = String::new();
+        self.write(&mut html, style);
+        html
+    }
+
+    /// Turns the node into an `Element`, or a `str` for HTML. Empty HTML is
+    /// left out of an element's children.
+    fn into_py(self, py: Python<'_>) -> PyResult<PyObject> {
+        match self {
+            Self::Element {
+                tag,
+                attrs,
+                children,
+            } => {
+                let dict = PyDict::new_bound(py);
+                for (name, value) in attrs {
+                    dict.set_item(name, value)?;
+                }
+
+                let list = PyList::empty_bound(py);
+                for child in children {
+                    if !matches!(&child, Self::Html(text) if text.is_empty()) {
+                        list.append(child.into_py(py)?)?;
+                    }
+                }
+
+                let element = Element {
+                    tag,
+                    attrs: dict.unbind(),
+                    children: list.unbind(),
+                };
+                Ok(Py::new(py, element)?.into_any())
+            }
+            Self::Html(text) => Ok(PyString::new_bound(py, &text).into_any().unbind()),
+        }
+    }
+}
+
+/// Turns the nodes of a document into `Element`s.
+pub(crate) fn elements(py: Python<'_>, nodes: Vec<Node>) -> PyResult<Vec<PyObject>> {
+    nodes.into_iter().map(|node| node.into_py(py)).collect()
+}
+
+/// An HTML element built by `tokens_to_elements`.
+///
+/// The strings in `children` and the values in `attrs` are HTML, escaped the
+/// way `tokens_to_html` writes them, so `to_html` gives back the exact same
+/// markup. `text` is the content with tags left out and entities decoded.
+#[pyclass]
+pub(crate) struct Element {
+    #[pyo3(get, set)]
+    tag: String,
+    #[pyo3(get, set)]
+    attrs: Py<PyDict>,
+    /// `Element`s and strings.
+    #[pyo3(get, set)]
+    children: Py<PyList>,
+}
+
+impl Element {
+    fn to_node(&self, py: Python<'_>) -> PyResult<Node> {
+        let attrs = self
+            .attrs
+            .bind(py)
+            .iter()
+            .map(|(name, value)| Ok((name.extract()?, value.extract()?)))
+            .collect::<PyResult<_>>()?;
+        let children = self
+            .children
+            .bind(py)
+            .iter()
+            .map(|child| child_node(&child))
+            .collect::<PyResult<_>>()?;
+
+        Ok(Node::Element {
+            tag: self.tag.clone(),
+            attrs,
+            children,
+        })
+    }
+
+    /// Writes the text of the element, still escaped.
+    fn write_text(&self, py: Python<'_>, html: &mut String) -> PyResult<()> {
+        for child in self.children.bind(py) {
+            if let Ok(element) = child.downcast::<Element>() {
+                element.borrow().write_text(py, html)?;
+            } else {
+                *html += &strip_tags(&child.extract::<String>()?);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn child_node(child: &Bound<'_, PyAny>) -> PyResult<Node> {
+    if let Ok(element) = child.downcast::<Element>() {
+        element.borrow().to_node(child.py())
+    } else if let Ok(text) = child.extract::<String>() {
+        Ok(Node::Html(text))
+    } else {
+        Err(PyTypeError::new_err(format!(
+            "children must be elements or strings, got {}",
+            child.get_type().name()?
+        )))
+    }
+}
+
+#[pymethods]
+impl Element {
+    #[new]
+    #[pyo3(signature = (tag, attrs = None, children = None))]
+    fn new(
+        py: Python<'_>,
+        tag: String,
+        attrs: Option<Py<PyDict>>,
+        children: Option<Py<PyList>>,
+    ) -> Self {
+        Self {
+            tag,
+            attrs: attrs.unwrap_or_else(|| PyDict::new_bound(py).unbind()),
+            children: children.unwrap_or_else(|| PyList::empty_bound(py).unbind()),
+        }
+    }
+
+    #[getter]
+    fn text(&self, py: Python<'_>) -> PyResult<String> {
+        let mut html = String::new();
+        self.write_text(py, &mut html)?;
+
+        Ok(html_escape::decode_html_entities(&html).into_owned())
+    }
+
+    /// Writes the element out as `tokens_to_html` would, in the given
+    /// `output_style`, as `HtmlOptions.output_style` takes it.
+    #[pyo3(signature = (*, output_style = "html5"))]
+    pub(crate) fn to_html(&self, py: Python<'_>, output_style: &str) -> PyResult<String> {
+        let style = OutputStyle::parse(output_style)?;
+
+        Ok(self.to_node(py)?.to_html(style))
+    }
+
+    #[pyo3(name = "__repr__")]
+    pub fn py_repr(&self) -> String {
+        format!("Element({:?})", self.tag)
+    }
+}
+
+/// Leaves out the tags of markup passed through as it is, as with
+/// `HtmlOptions(escape=False)`.
+fn strip_tags(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        text += &rest[..start];
+        match rest[start..].find('>') {
+            Some(end) => rest = &rest[start + end + 1..],
+            None => {
+                rest = &rest[start..];
+                break;
+            }
+        }
+    }
+
+    text + rest
+}
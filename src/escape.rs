@@ -0,0 +1,102 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::options::HtmlOptions;
+
+lazy_static! {
+    static ref REFERENCE_RE: Regex =
+        Regex::new(r"^&(?:#[0-9]{1,7}|#[xX][0-9a-fA-F]{1,6}|[A-Za-z][A-Za-z0-9]{0,31});").unwrap();
+}
+
+/// Escapes text between tags: `&`, `<` and `>`.
+pub(crate) fn escape_text(text: &str) -> String {
+    html_escape::encode_text(text).into_owned()
+}
+
+/// Escapes text outside of code, unless `options.escape` is off.
+pub(crate) fn escape_prose(text: &str, options: &HtmlOptions) -> String {
+    if options.escape {
+        escape_text(text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Decodes the entity or numeric character reference at the start of `text`,
+/// like `&amp;`, `&#35;` or `&#x23;`, and returns it with its length.
+///
+/// As in CommonMark, a name must be an HTML5 entity and end with `;`, and a
+/// number that is not a character, like `&#0;`, gives U+FFFD. Anything else,
+/// like `&notanentity;`, is not a reference and stays text.
+pub(crate) fn decode_reference(text: &str) -> Option<(String, usize)> {
+    let reference = REFERENCE_RE.find(text)?.as_str();
+    let name = &reference[1..reference.len() - 1];
+
+    let decoded = match name.strip_prefix('#') {
+        Some(number) => {
+            let code = match number.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16),
+                None => number.parse(),
+            };
+            let c = code.ok().and_then(char::from_u32).filter(|&c| c != '\0');
+
+            c.unwrap_or('\u{FFFD}').to_string()
+        }
+        None => {
+            let decoded = html_escape::decode_html_entities(reference);
+            if decoded == reference {
+                return None;
+            }
+            decoded.into_owned()
+        }
+    };
+
+    Some((decoded, reference.len()))
+}
+
+/// Escapes the value of a double-quoted attribute: `&`, `<`, `>` and `"`.
+pub(crate) fn escape_attr(value: &str) -> String {
+    html_escape::encode_double_quoted_attribute(value).into_owned()
+}
+
+/// Returns `name` if it can go in a `class` attribute as one class: no
+/// whitespace or control characters, and none of `"'<>&` or a backtick.
+///
+/// Class names and ids are checked rather than escaped: a fence language
+/// with markup in it is not a language, so it gets no class at all.
+pub(crate) fn sanitize_class(name: &str) -> Option<&str> {
+    let valid = |c: char| !c.is_whitespace() && !c.is_control() && !"\"'<>&`".contains(c);
+
+    (!name.is_empty() && name.chars().all(valid)).then_some(name)
+}
+
+/// Returns `id` if it is a valid HTML id: not empty, with no whitespace.
+pub(crate) fn sanitize_id(id: &str) -> Option<&str> {
+    (!id.is_empty() && !id.chars().any(char::is_whitespace)).then_some(id)
+}
+
+/// The schemes a link or image may have. Anything else, like `javascript:`
+/// or `data:`, could run script or smuggle content in.
+const SAFE_SCHEMES: &[&str] = &["http", "https", "mailto", "tel"];
+
+/// Whether `url` is safe to link to: relative, a fragment, or with one of
+/// [`SAFE_SCHEMES`]. The scheme is read the way a browser reads it, past
+/// leading spaces and control characters and with tabs and line breaks
+/// taken out, so `" java\tscript:"` is not let through.
+pub(crate) fn safe_url(url: &str) -> bool {
+    let url: String = url
+        .trim_start_matches(|c: char| c <= ' ')
+        .chars()
+        .filter(|c| !matches!(c, '\t' | '\n' | '\r'))
+        .collect();
+    let Some((scheme, _)) = url.split_once(':') else {
+        return true;
+    };
+
+    let is_scheme = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+
+    !is_scheme || SAFE_SCHEMES.contains(&scheme.to_ascii_lowercase().as_str())
+}
@@ -0,0 +1,288 @@
+use crate::parser::{Metadata, Span, Token};
+
+/// A JSON value, only as much of one as md0 writes.
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Number(usize),
+    String(String),
+    Array(Vec<Json>),
+    /// Fields in the order they are written.
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub(crate) fn str(text: &str) -> Self {
+        Self::String(text.to_string())
+    }
+
+    pub(crate) fn object<'a>(fields: impl IntoIterator<Item = (&'a str, Json)>) -> Self {
+        Self::Object(
+            fields
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+        )
+    }
+
+    fn span((start, end): Span) -> Self {
+        Self::Array(vec![Self::Number(start), Self::Number(end)])
+    }
+
+    fn option<T>(value: &Option<T>, json: impl Fn(&T) -> Self) -> Self {
+        value.as_ref().map_or(Self::Null, json)
+    }
+
+    /// Writes the value compactly, with no space between tokens and only
+    /// `"`, `\` and control characters escaped in strings.
+    pub(crate) fn write(&self, json: &mut String) {
+        match self {
+            Self::Null => *json += "null",
+            Self::Bool(b) => *json += if *b { "true" } else { "false" },
+            Self::Number(n) => *json += &n.to_string(),
+            Self::String(text) => {
+                json.push('"');
+                for c in text.chars() {
+                    match c {
+                        '"' => *json += "\\\"",
+                        '\\' => *json += "\\\\",
+                        '\n' => *json += "\\n",
+                        '\r' => *json += "\\r",
+                        '\t' => *json += "\\t",
+                        c if c < ' ' => *json += &format!("\\u{:04x}", c as u32),
+                        c => json.push(c),
+                    }
+                }
+                json.push('"');
+            }
+            Self::Array(items) => {
+                json.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        json.push(',');
+                    }
+                    item.write(json);
+                }
+                json.push(']');
+            }
+            Self::Object(fields) => {
+                json.push('{');
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        json.push(',');
+                    }
+                    Self::str(name).write(json);
+                    json.push(':');
+                    value.write(json);
+                }
+                json.push('}');
+            }
+        }
+    }
+}
+
+fn metadata_json(metadata: &[Metadata]) -> Json {
+    Json::Array(
+        metadata
+            .iter()
+            .map(|metadata| match metadata {
+                Metadata::Link {
+                    location,
+                    label,
+                    url,
+                    span,
+                } => Json::object([
+                    ("kind", Json::str("link")),
+                    ("location", Json::span(*location)),
+                    ("label", Json::str(label)),
+                    ("url", Json::str(url)),
+                    ("span", Json::option(span, |span| Json::span(*span))),
+                ]),
+                Metadata::Image {
+                    location,
+                    label,
+                    url,
+                    width,
+                    height,
+                    span,
+                } => Json::object([
+                    ("kind", Json::str("image")),
+                    ("location", Json::span(*location)),
+                    ("label", Json::str(label)),
+                    ("url", Json::str(url)),
+                    ("width", Json::option(width, |&n| Json::Number(n as usize))),
+                    (
+                        "height",
+                        Json::option(height, |&n| Json::Number(n as usize)),
+                    ),
+                    ("span", Json::option(span, |span| Json::span(*span))),
+                ]),
+                Metadata::Abbreviation {
+                    location,
+                    short,
+                    title,
+                } => Json::object([
+                    ("kind", Json::str("abbreviation")),
+                    ("location", Json::span(*location)),
+                    ("short", Json::str(short)),
+                    ("title", Json::str(title)),
+                ]),
+                Metadata::Raw { location, closed } => Json::object([
+                    ("kind", Json::str("raw")),
+                    ("location", Json::span(*location)),
+                    ("closed", Json::Bool(*closed)),
+                ]),
+                Metadata::LineBreak { location } => Json::object([
+                    ("kind", Json::str("line_break")),
+                    ("location", Json::span(*location)),
+                ]),
+                Metadata::InlineMath { location, content } => Json::object([
+                    ("kind", Json::str("inline_math")),
+                    ("location", Json::span(*location)),
+                    ("content", Json::str(content)),
+                ]),
+                Metadata::WikiLink {
+                    location,
+                    target,
+                    alias,
+                } => Json::object([
+                    ("kind", Json::str("wiki_link")),
+                    ("location", Json::span(*location)),
+                    ("target", Json::str(target)),
+                    ("alias", Json::option(alias, |alias| Json::str(alias))),
+                ]),
+            })
+            .collect(),
+    )
+}
+
+fn token_json(token: &Token) -> Json {
+    let kind = ("kind", Json::str(token.kind()));
+    let id = ("id", Json::Number(token.id()));
+    let parent_id = (
+        "parent_id",
+        Json::option(&token.parent_id(), |&id| Json::Number(id)),
+    );
+    let span = ("span", Json::span(token.span()));
+    let raw = ("raw", Json::option(&token.raw(), |raw| Json::str(raw)));
+
+    let fields = match token {
+        Token::Heading {
+            level,
+            content,
+            number,
+            ..
+        } => vec![
+            ("level", Json::Number(*level as usize)),
+            ("content", Json::str(content)),
+            ("number", Json::option(number, |number| Json::str(number))),
+        ],
+        Token::Paragraph(text, metadata, _, _, breaks, ..) => vec![
+            ("text", Json::str(text)),
+            ("metadata", metadata_json(metadata)),
+            (
+                "breaks",
+                Json::Array(breaks.iter().map(|&i| Json::Number(i)).collect()),
+            ),
+        ],
+        Token::HorizontalRule { marker, length, .. } => vec![
+            ("marker", Json::String(marker.to_string())),
+            ("length", Json::Number(*length)),
+        ],
+        Token::DefinitionList { items, .. } => {
+            let items = items
+                .iter()
+                .map(|(term, definitions)| {
+                    let definitions = definitions
+                        .iter()
+                        .map(|(text, metadata)| {
+                            Json::object([
+                                ("text", Json::str(text)),
+                                ("metadata", metadata_json(metadata)),
+                            ])
+                        })
+                        .collect();
+
+                    Json::object([
+                        ("term", Json::str(term)),
+                        ("definitions", Json::Array(definitions)),
+                    ])
+                })
+                .collect();
+
+            vec![("items", Json::Array(items))]
+        }
+        Token::Abbreviation {
+            abbr, expansion, ..
+        } => vec![
+            ("abbr", Json::str(abbr)),
+            ("expansion", Json::str(expansion)),
+        ],
+        Token::Code {
+            language,
+            content,
+            fence_char,
+            fence_length,
+            indent,
+            info,
+            flags,
+            attrs,
+            ..
+        } => vec![
+            ("language", Json::str(language)),
+            ("content", Json::str(content)),
+            (
+                "fence_char",
+                Json::option(fence_char, |c| Json::String(c.to_string())),
+            ),
+            ("fence_length", Json::Number(*fence_length)),
+            ("indent", Json::Number(*indent)),
+            ("info", Json::str(info)),
+            (
+                "flags",
+                Json::Array(flags.iter().map(|flag| Json::str(flag)).collect()),
+            ),
+            (
+                "attrs",
+                Json::Object(
+                    attrs
+                        .iter()
+                        .map(|(key, value)| (key.clone(), Json::str(value)))
+                        .collect(),
+                ),
+            ),
+        ],
+        Token::Container {
+            name,
+            attrs,
+            content,
+            ..
+        } => vec![
+            ("name", Json::str(name)),
+            ("attrs", Json::str(attrs)),
+            (
+                "content",
+                Json::Array(content.iter().map(token_json).collect()),
+            ),
+        ],
+        Token::MathBlock { content, .. } => vec![("content", Json::str(content))],
+    };
+
+    Json::object(
+        [kind, id, parent_id]
+            .into_iter()
+            .chain(fields)
+            .chain([span, raw]),
+    )
+}
+
+/// Writes tokens as JSON: an array with an object per token, holding its
+/// `kind`, `id` and `parent_id`, then its fields as `Token` names them, then
+/// its `span` and `raw`.
+/// A paragraph's fields are `text`, `metadata` and `breaks`. The same tokens
+/// always give the same bytes, so dumps can be compared as text.
+pub(crate) fn tokens_to_json(tokens: &[Token]) -> String {
+    let mut json = String::new();
+    Json::Array(tokens.iter().map(token_json).collect()).write(&mut json);
+    json
+}
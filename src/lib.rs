@@ -1,20 +1,689 @@
-use pyo3::prelude::*;
+// `#[pyfunction]` expansion in PyO3 0.22 trips `useless_conversion` on
+// `PyResult` returns, and the generated constructors of enum variants with
+// many fields trip `too_many_arguments`.
+#![allow(clippy::useless_conversion, clippy::too_many_arguments)]
 
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap},
+    hash::{Hash, Hasher},
+    sync::Mutex,
+    time::Instant,
+};
+
+use pyo3::{
+    exceptions::PyValueError,
+    prelude::*,
+    types::{PyBool, PyFrozenSet},
+};
+
+mod abbr;
+mod anchors;
+mod case;
+mod code;
+mod document;
+mod element;
+mod escape;
+mod json;
+mod lint;
+mod markdown;
+mod metrics;
+mod options;
+mod outline;
+mod pandoc;
 mod parser;
+mod sanitize;
+mod slug;
+mod stream;
+mod strict;
+mod transform;
+
+use metrics::{Metrics, Phases};
+use options::{HtmlOptions, ParseOptions};
+
+/// Parses `markdown` into tokens. With `collect_metrics`, returns them with
+/// the `Metrics` of the parse.
+#[pyfunction]
+#[pyo3(signature = (markdown, options = None, *, collect_metrics = false))]
+fn parse(
+    py: Python<'_>,
+    markdown: String,
+    options: Option<ParseOptions>,
+    collect_metrics: bool,
+) -> PyResult<PyObject> {
+    let options = options.unwrap_or_default();
+    if !collect_metrics {
+        return Ok(py
+            .allow_threads(|| parser::parse(markdown, &options))?
+            .into_py(py));
+    }
+
+    let (tokens, metrics) = py.allow_threads(|| {
+        let start = Instant::now();
+        let mut phases = Phases::on();
+        let tokens = parser::parse_timed(markdown.clone(), &options, &mut phases)?;
+
+        let metrics = Metrics::parse(&markdown, &tokens, start.elapsed(), phases);
+        PyResult::Ok((tokens, metrics))
+    })?;
+    Ok((tokens, metrics).into_py(py))
+}
+
+/// Renders `tokens` as HTML.
+///
+/// The same tokens and options always give the same bytes, on any thread,
+/// so the output can be cached by a hash of its input. State the renderer
+/// keeps while rendering, like the slugs already handed out, is held in
+/// ordered maps for that reason.
+///
+/// With `collect_metrics`, returns the HTML with the `Metrics` of the render.
+#[pyfunction]
+#[pyo3(signature = (tokens, options = None, *, collect_metrics = false))]
+fn tokens_to_html(
+    py: Python<'_>,
+    tokens: parser::Tokens,
+    options: Option<HtmlOptions>,
+    collect_metrics: bool,
+) -> PyResult<PyObject> {
+    let options = options.unwrap_or_default();
+    if !collect_metrics {
+        return Ok(py
+            .allow_threads(|| parser::tokens_to_html(tokens, &options))?
+            .into_py(py));
+    }
+
+    let (html, metrics) = py.allow_threads(|| {
+        // The tokens are counted once they are rendered
+        let rendered = tokens.clone();
+        let start = Instant::now();
+        let mut phases = Phases::on();
+        let html = parser::tokens_to_html_timed(rendered, &options, &mut phases)?;
+
+        let metrics = Metrics::render(&tokens, &html, start.elapsed(), phases);
+        PyResult::Ok((html, metrics))
+    })?;
+    Ok((html, metrics).into_py(py))
+}
+
+/// Renders one token, exactly as `tokens_to_html` renders it on its own.
+#[pyfunction]
+#[pyo3(signature = (token, options = None))]
+fn token_to_html(
+    py: Python<'_>,
+    token: parser::Token,
+    options: Option<HtmlOptions>,
+) -> PyResult<String> {
+    let options = options.unwrap_or_default();
+    py.allow_threads(|| parser::token_to_html(token, &options))
+}
+
+/// Renders `tokens` as a tree of `Element`s, one per block, that
+/// `elements_to_html` writes out as exactly what `tokens_to_html` returns.
+#[pyfunction]
+#[pyo3(signature = (tokens, options = None))]
+fn tokens_to_elements(
+    py: Python<'_>,
+    tokens: parser::Tokens,
+    options: Option<HtmlOptions>,
+) -> PyResult<Vec<PyObject>> {
+    let options = options.unwrap_or_default();
+    let nodes = py.allow_threads(|| parser::tokens_to_nodes(tokens, &options));
+
+    element::elements(py, nodes)
+}
+
+/// Writes out `elements` the way `tokens_to_html` joins its blocks, in the
+/// given `output_style`, as `HtmlOptions.output_style` takes it.
+#[pyfunction]
+#[pyo3(signature = (elements, *, output_style = "html5"))]
+fn elements_to_html(
+    elements: Vec<PyRef<'_, element::Element>>,
+    output_style: &str,
+) -> PyResult<String> {
+    let html = elements
+        .iter()
+        .map(|element| element.to_html(element.py(), output_style))
+        .collect::<PyResult<Vec<_>>>()?;
+
+    Ok(html.join("\n"))
+}
+
+/// Writes `tokens` back out as Markdown, wrapping prose at `width` columns.
+#[pyfunction]
+#[pyo3(signature = (tokens, width = Some(80)))]
+fn tokens_to_markdown(tokens: parser::Tokens, width: Option<usize>) -> PyResult<String> {
+    if width == Some(0) {
+        return Err(PyValueError::new_err("width must be at least 1"));
+    }
+
+    Ok(markdown::tokens_to_markdown(&tokens, width))
+}
+
+/// Dumps `tokens` as JSON, with every field of every token and its metadata.
+/// The same tokens always give the same string, so dumps make snapshots.
+#[pyfunction]
+fn tokens_to_json(tokens: parser::Tokens) -> String {
+    json::tokens_to_json(&tokens)
+}
+
+/// Converts `tokens` to Pandoc's JSON AST, which `pandoc --from json` reads,
+/// so a document can be turned into anything Pandoc writes. Headings get the
+/// ids `slug_style` gives them.
+#[pyfunction]
+#[pyo3(signature = (tokens, slug_style = "simple"))]
+fn tokens_to_pandoc_json(tokens: parser::Tokens, slug_style: &str) -> PyResult<String> {
+    Ok(pandoc::tokens_to_pandoc(
+        &tokens,
+        slug::SlugStyle::parse(slug_style)?,
+    ))
+}
+
+#[pyfunction]
+fn line_offsets(markdown: String) -> PyResult<Vec<usize>> {
+    Ok(parser::line_offsets(&markdown))
+}
+
+#[pyfunction]
+fn normalize(tokens: parser::Tokens) -> PyResult<parser::Tokens> {
+    Ok(transform::normalize(tokens))
+}
+
+/// Turns paragraphs that read as headings, like a lone `**bold question?**`,
+/// into headings of `level`, so outlines and heading ids include them.
+#[pyfunction]
+#[pyo3(signature = (tokens, level = 3, max_length = 80, endings = "?:"))]
+fn promote_headings(
+    tokens: parser::Tokens,
+    level: u8,
+    max_length: usize,
+    endings: &str,
+) -> PyResult<parser::Tokens> {
+    if !(1..=6).contains(&level) {
+        return Err(PyValueError::new_err(format!(
+            "level must be between 1 and 6, got {level}"
+        )));
+    }
+
+    Ok(transform::promote_headings(
+        tokens, level, max_length, endings,
+    ))
+}
+
+/// One token kind or several.
+#[derive(FromPyObject)]
+enum Kinds {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Returns the tokens whose `kind` is `kind`, or one of `kind` if it is a list.
+#[pyfunction]
+fn filter_tokens(tokens: parser::Tokens, kind: Kinds) -> PyResult<parser::Tokens> {
+    let kinds = match kind {
+        Kinds::One(kind) => vec![kind],
+        Kinds::Many(kinds) => kinds,
+    };
+
+    if let Some(kind) = kinds
+        .iter()
+        .find(|kind| !parser::KINDS.contains(&kind.as_str()))
+    {
+        return Err(PyValueError::new_err(format!(
+            "unknown token kind {kind:?}, expected one of {:?}",
+            parser::KINDS
+        )));
+    }
+
+    Ok(tokens
+        .into_iter()
+        .filter(|token| kinds.iter().any(|kind| kind == token.kind()))
+        .collect())
+}
+
+/// Returns every token of `tokens` in document order, the tokens in a
+/// container right after it, as `(token, depth, parent)`: how many containers
+/// the token is in, and the innermost of them, or `None` at the top level.
+#[pyfunction]
+fn walk(
+    tokens: Vec<Bound<'_, parser::Token>>,
+) -> PyResult<Vec<(PyObject, usize, Option<PyObject>)>> {
+    fn visit<'py>(
+        tokens: Vec<Bound<'py, parser::Token>>,
+        depth: usize,
+        parent: Option<&PyObject>,
+        walked: &mut Vec<(PyObject, usize, Option<PyObject>)>,
+    ) -> PyResult<()> {
+        for token in tokens {
+            let py = token.py();
+            let object = token.clone().into_any().unbind();
+            walked.push((object.clone_ref(py), depth, parent.map(|p| p.clone_ref(py))));
+
+            if let parser::Token::Container { content, .. } = token.get() {
+                let content = content
+                    .iter()
+                    .map(|child| Ok(child.clone().into_py(py).into_bound(py).downcast_into()?))
+                    .collect::<PyResult<_>>()?;
+                visit(content, depth + 1, Some(&object), walked)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    let mut walked = Vec::new();
+    visit(tokens, 0, None, &mut walked)?;
+    Ok(walked)
+}
+
+/// Returns the abbreviations defined in `tokens`, as a dict.
+#[pyfunction]
+fn abbreviations(tokens: parser::Tokens) -> PyResult<BTreeMap<String, String>> {
+    Ok(abbr::abbreviations(&tokens))
+}
+
+#[pyfunction]
+#[pyo3(signature = (tokens, language = None, source = None))]
+fn code_blocks(
+    tokens: parser::Tokens,
+    language: Option<&str>,
+    source: Option<&str>,
+) -> PyResult<Vec<code::CodeBlock>> {
+    Ok(code::code_blocks(&tokens, language, source))
+}
+
+/// Parses `markdown` and returns its code blocks in `language`, or all of
+/// them joined with `# line N` markers if `concatenate` is set.
+#[pyfunction]
+#[pyo3(signature = (markdown, language = None, concatenate = false))]
+fn extract_code(
+    py: Python<'_>,
+    markdown: String,
+    language: Option<&str>,
+    concatenate: bool,
+) -> PyResult<PyObject> {
+    let options = ParseOptions::default();
+    let tokens = py.allow_threads(|| parser::parse(markdown.clone(), &options))?;
+    let blocks = code::code_blocks(&tokens, language, Some(&markdown));
 
+    Ok(if concatenate {
+        code::concatenate(&blocks).into_py(py)
+    } else {
+        blocks.into_py(py)
+    })
+}
+
+#[pyfunction]
+#[pyo3(signature = (tokens, start_level = 1, format = "1.2.3"))]
+fn number_headings(
+    tokens: parser::Tokens,
+    start_level: u8,
+    format: &str,
+) -> PyResult<parser::Tokens> {
+    outline::number_headings(tokens, start_level, format)
+}
+
+#[pyfunction]
+#[pyo3(name = "outline", signature = (tokens, slug_style = "simple"))]
+fn py_outline(tokens: parser::Tokens, slug_style: &str) -> PyResult<Vec<outline::OutlineEntry>> {
+    Ok(outline::outline(
+        &tokens,
+        slug::SlugStyle::parse(slug_style)?,
+    ))
+}
+
+/// Returns every heading as a `(level, content, slug)` tuple, in order. The
+/// slugs are the ids rendering with `heading_ids=True` writes.
+#[pyfunction]
+#[pyo3(signature = (tokens, slug_style = "simple"))]
+fn heading_outline(
+    tokens: parser::Tokens,
+    slug_style: &str,
+) -> PyResult<Vec<(u8, String, String)>> {
+    Ok(outline::heading_outline(
+        &tokens,
+        slug::SlugStyle::parse(slug_style)?,
+    ))
+}
+
+/// Maps the id of every heading, as rendering with `heading_ids=True` writes
+/// it, to the text of the heading. Links like `[](#install)`, with no label,
+/// are rendered with the text of the heading they point to.
+#[pyfunction]
+#[pyo3(signature = (tokens, slug_style = "simple"))]
+fn anchor_map(tokens: parser::Tokens, slug_style: &str) -> PyResult<BTreeMap<String, String>> {
+    Ok(anchors::anchor_map(
+        &tokens,
+        slug::SlugStyle::parse(slug_style)?,
+    ))
+}
+
+/// Groups `tokens` into sections: every heading, with the tokens up to the
+/// next heading of the same level or above. A section holds its subsections,
+/// and each of them is also a section of its own.
+#[pyfunction]
+fn sections(tokens: parser::Tokens) -> PyResult<Vec<(parser::Token, parser::Tokens)>> {
+    Ok(outline::sections(&tokens))
+}
+
+/// Finds the links and images in a piece of inline Markdown, such as a
+/// heading from elsewhere or a table cell, without parsing it into blocks.
+/// Their locations are in `text`.
 #[pyfunction]
-fn parse(markdown: String) -> PyResult<parser::Tokens> {
-    parser::parse(markdown)
+#[pyo3(signature = (text, options = None))]
+fn parse_inline(text: &str, options: Option<ParseOptions>) -> Vec<parser::Metadata> {
+    parser::inline_metadata(text, &options.unwrap_or_default())
 }
 
+/// Renders a piece of inline Markdown the way the text of a paragraph is
+/// rendered, without the `<p>` around it.
 #[pyfunction]
-fn tokens_to_html(tokens: parser::Tokens) -> PyResult<String> {
-    parser::tokens_to_html(tokens)
+#[pyo3(signature = (text, options = None))]
+fn inline_to_html(text: &str, options: Option<HtmlOptions>) -> String {
+    parser::inline_to_html(text, &options.unwrap_or_default())
+}
+
+/// Returns the title of a document: the text of its first heading, without
+/// the Markdown, if that is an `h1` or `loose` is set.
+#[pyfunction]
+#[pyo3(signature = (tokens, loose = false))]
+fn title(tokens: parser::Tokens, loose: bool) -> PyResult<Option<String>> {
+    Ok(outline::title(&tokens, loose))
+}
+
+/// Returns the names of everything this build of md0 supports: every flag
+/// of `ParseOptions` and `HtmlOptions`, spelled with `-` for `_`, and the
+/// extensions that are always on. The flags are the options whose default is
+/// a bool, read off the options themselves so none can be left out, but for
+/// those that only modify another option.
+#[pyfunction]
+fn capabilities(py: Python<'_>) -> PyResult<Bound<'_, PyFrozenSet>> {
+    let defaults = [
+        ParseOptions::default().into_py(py).into_bound(py),
+        HtmlOptions::default().into_py(py).into_bound(py),
+    ];
+
+    let mut names: Vec<String> = options::FEATURES.iter().map(|f| f.to_string()).collect();
+    for options in defaults {
+        for name in options.dir()? {
+            let name: String = name.extract()?;
+            let flag = !name.starts_with('_')
+                && !options::MODIFIERS.contains(&name.as_str())
+                && options.getattr(name.as_str())?.is_instance_of::<PyBool>();
+
+            if flag {
+                names.push(name.replace('_', "-"));
+            }
+        }
+    }
+
+    PyFrozenSet::new_bound(py, &names)
+}
+
+/// Returns the `#fragment` links in `tokens` that match no heading id.
+#[pyfunction]
+#[pyo3(signature = (tokens, slug_style = "simple"))]
+fn check_anchors(
+    tokens: parser::Tokens,
+    slug_style: &str,
+) -> PyResult<Vec<anchors::AnchorProblem>> {
+    Ok(anchors::check_anchors(
+        tokens,
+        slug::SlugStyle::parse(slug_style)?,
+    ))
+}
+
+/// Returns the links between and within `documents`, keyed by path, that
+/// match no heading id in the document they point to.
+#[pyfunction]
+#[pyo3(signature = (documents, slug_style = "simple"))]
+fn check_anchors_many(
+    documents: BTreeMap<String, parser::Tokens>,
+    slug_style: &str,
+) -> PyResult<Vec<anchors::AnchorProblem>> {
+    Ok(anchors::check_anchors_many(
+        &documents,
+        slug::SlugStyle::parse(slug_style)?,
+    ))
+}
+
+/// Checks `tokens` against the built-in lint rules and the `custom` ones.
+///
+/// `rules` selects rules by id, or leaves them out as `-id`. A custom rule is
+/// a callable that takes the tokens and returns `LintProblem`s.
+#[pyfunction]
+#[pyo3(name = "lint", signature = (tokens, rules = None, custom = None))]
+fn py_lint(
+    tokens: parser::Tokens,
+    rules: Option<Vec<String>>,
+    custom: Option<BTreeMap<String, PyObject>>,
+) -> PyResult<Vec<lint::LintProblem>> {
+    lint::lint(&tokens, rules, custom.unwrap_or_default())
+}
+
+/// A reusable parser holding a fixed set of options.
+///
+/// The configuration never changes after construction, so one instance can be
+/// shared between threads. The GIL is released while parsing.
+#[pyclass(frozen)]
+struct Parser {
+    options: ParseOptions,
+}
+
+#[pymethods]
+impl Parser {
+    #[new]
+    #[pyo3(signature = (options = None))]
+    fn new(options: Option<ParseOptions>) -> Self {
+        Self {
+            options: options.unwrap_or_default(),
+        }
+    }
+
+    fn parse(&self, py: Python<'_>, markdown: String) -> PyResult<parser::Tokens> {
+        py.allow_threads(|| parser::parse(markdown, &self.options))
+    }
+}
+
+/// A cached parse, with the input it was parsed from in case two inputs
+/// hash the same.
+struct Cached {
+    markdown: String,
+    tokens: parser::Tokens,
+    /// When the entry was last read or written, for LRU eviction
+    used: u64,
+}
+
+#[derive(Default)]
+struct Cache {
+    entries: HashMap<u64, Cached>,
+    clock: u64,
+    hits: usize,
+    misses: usize,
+}
+
+/// A [`Parser`] that keeps the tokens of the last `capacity` distinct inputs
+/// and returns a copy of them when the same input is parsed again.
+///
+/// The least recently used entry is dropped once the cache is full. The cache
+/// is shared behind a lock, so one instance can still be used from several
+/// threads; the GIL and the lock are both released while parsing.
+#[pyclass(frozen)]
+struct CachedParser {
+    options: ParseOptions,
+    capacity: usize,
+    cache: Mutex<Cache>,
+}
+
+impl CachedParser {
+    fn cache(&self) -> std::sync::MutexGuard<'_, Cache> {
+        // A panic while the lock is held leaves the counters at worst stale
+        self.cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[pymethods]
+impl CachedParser {
+    #[new]
+    #[pyo3(signature = (capacity = 128, options = None))]
+    fn new(capacity: usize, options: Option<ParseOptions>) -> PyResult<Self> {
+        if capacity == 0 {
+            return Err(PyValueError::new_err("capacity must be at least 1"));
+        }
+
+        Ok(Self {
+            options: options.unwrap_or_default(),
+            capacity,
+            cache: Mutex::default(),
+        })
+    }
+
+    fn parse(&self, py: Python<'_>, markdown: String) -> PyResult<parser::Tokens> {
+        py.allow_threads(|| {
+            let mut hasher = DefaultHasher::new();
+            markdown.hash(&mut hasher);
+            let key = hasher.finish();
+
+            {
+                let mut cache = self.cache();
+                cache.clock += 1;
+                let clock = cache.clock;
+
+                if let Some(entry) = cache
+                    .entries
+                    .get_mut(&key)
+                    .filter(|entry| entry.markdown == markdown)
+                {
+                    entry.used = clock;
+                    let tokens = entry.tokens.clone();
+                    cache.hits += 1;
+                    return Ok(tokens);
+                }
+                cache.misses += 1;
+            }
+
+            let tokens = parser::parse(markdown.clone(), &self.options)?;
+
+            let mut cache = self.cache();
+            if !cache.entries.contains_key(&key) && cache.entries.len() >= self.capacity {
+                let oldest = cache
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.used)
+                    .map(|(&key, _)| key);
+                if let Some(oldest) = oldest {
+                    cache.entries.remove(&oldest);
+                }
+            }
+            cache.clock += 1;
+            let used = cache.clock;
+            cache.entries.insert(
+                key,
+                Cached {
+                    markdown,
+                    tokens: tokens.clone(),
+                    used,
+                },
+            );
+
+            Ok(tokens)
+        })
+    }
+
+    /// Drops every cached entry and resets the counters.
+    fn clear(&self) {
+        *self.cache() = Cache::default();
+    }
+
+    /// Returns the `hits`, `misses`, `size` and `capacity` of the cache, as a
+    /// dict.
+    fn stats(&self) -> BTreeMap<&'static str, usize> {
+        let cache = self.cache();
+
+        BTreeMap::from([
+            ("hits", cache.hits),
+            ("misses", cache.misses),
+            ("size", cache.entries.len()),
+            ("capacity", self.capacity),
+        ])
+    }
+}
+
+/// A reusable HTML renderer holding a fixed set of options.
+///
+/// Like [`Parser`], it is immutable and releases the GIL while rendering.
+#[pyclass(frozen)]
+struct Renderer {
+    options: HtmlOptions,
+}
+
+#[pymethods]
+impl Renderer {
+    #[new]
+    #[pyo3(signature = (options = None))]
+    fn new(options: Option<HtmlOptions>) -> Self {
+        Self {
+            options: options.unwrap_or_default(),
+        }
+    }
+
+    fn render(&self, py: Python<'_>, tokens: parser::Tokens) -> PyResult<String> {
+        py.allow_threads(|| parser::tokens_to_html(tokens, &self.options))
+    }
 }
 
 #[pymodule]
 fn md0(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    m.add_function(wrap_pyfunction!(capabilities, m)?)?;
     m.add_function(wrap_pyfunction!(parse, m)?)?;
     m.add_function(wrap_pyfunction!(tokens_to_html, m)?)?;
+    m.add_function(wrap_pyfunction!(token_to_html, m)?)?;
+    m.add_function(wrap_pyfunction!(tokens_to_elements, m)?)?;
+    m.add_function(wrap_pyfunction!(elements_to_html, m)?)?;
+    m.add_function(wrap_pyfunction!(tokens_to_markdown, m)?)?;
+    m.add_function(wrap_pyfunction!(tokens_to_json, m)?)?;
+    m.add_function(wrap_pyfunction!(tokens_to_pandoc_json, m)?)?;
+    m.add_function(wrap_pyfunction!(line_offsets, m)?)?;
+    m.add_function(wrap_pyfunction!(normalize, m)?)?;
+    m.add_function(wrap_pyfunction!(promote_headings, m)?)?;
+    m.add_function(wrap_pyfunction!(filter_tokens, m)?)?;
+    m.add_function(wrap_pyfunction!(walk, m)?)?;
+    m.add_function(wrap_pyfunction!(abbreviations, m)?)?;
+    m.add_function(wrap_pyfunction!(code_blocks, m)?)?;
+    m.add_function(wrap_pyfunction!(extract_code, m)?)?;
+    m.add_function(wrap_pyfunction!(number_headings, m)?)?;
+    m.add_function(wrap_pyfunction!(py_outline, m)?)?;
+    m.add_function(wrap_pyfunction!(heading_outline, m)?)?;
+    m.add_function(wrap_pyfunction!(sections, m)?)?;
+    m.add_function(wrap_pyfunction!(anchor_map, m)?)?;
+    m.add_function(wrap_pyfunction!(parse_inline, m)?)?;
+    m.add_function(wrap_pyfunction!(inline_to_html, m)?)?;
+    m.add_function(wrap_pyfunction!(title, m)?)?;
+    m.add_function(wrap_pyfunction!(check_anchors, m)?)?;
+    m.add_function(wrap_pyfunction!(check_anchors_many, m)?)?;
+    m.add_function(wrap_pyfunction!(py_lint, m)?)?;
+    m.add_class::<parser::Token>()?;
+    // PyO3 gives the class of a tuple variant a `__len__` counting its
+    // fields, which would hide the one every token has
+    let token = m.py().get_type_bound::<parser::Token>();
+    token
+        .getattr("Paragraph")?
+        .setattr("__len__", token.getattr("__len__")?)?;
+    m.add_class::<parser::Metadata>()?;
+    m.add_class::<document::Document>()?;
+    m.add_class::<stream::StreamParser>()?;
+    m.add_class::<element::Element>()?;
+    m.add_class::<code::CodeBlock>()?;
+    m.add_class::<outline::OutlineEntry>()?;
+    m.add_class::<anchors::AnchorProblem>()?;
+    m.add_class::<lint::LintProblem>()?;
+    m.add_class::<Metrics>()?;
+    m.add("ParseError", m.py().get_type_bound::<strict::ParseError>())?;
+    m.add_class::<ParseOptions>()?;
+    m.add_class::<HtmlOptions>()?;
+    m.add_class::<Parser>()?;
+    m.add_class::<CachedParser>()?;
+    m.add_class::<Renderer>()?;
     Ok(())
 }
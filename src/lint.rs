@@ -0,0 +1,449 @@
+use std::collections::{BTreeMap, HashMap};
+
+use lazy_static::lazy_static;
+use pyo3::{exceptions::PyValueError, prelude::*};
+use regex::Regex;
+
+use crate::{
+    anchors,
+    parser::{self, Metadata, Span, Token},
+    slug::SlugStyle,
+};
+
+lazy_static! {
+    static ref BARE_URL_RE: Regex = Regex::new(r"https?://[^\s<>]+").unwrap();
+}
+
+/// Paragraphs longer than this many characters are reported by
+/// `long-paragraph`.
+const LONG_PARAGRAPH: usize = 1000;
+
+/// Punctuation a heading should not end with. A question mark is fine.
+const HEADING_PUNCTUATION: &str = ".,;:!。，；：！";
+
+const SEVERITIES: &[&str] = &["error", "warning", "info"];
+
+/// Something `lint` found wrong with a document.
+#[pyclass(frozen)]
+#[derive(Clone)]
+pub(crate) struct LintProblem {
+    /// The id of the rule that found it.
+    #[pyo3(get)]
+    rule: String,
+    #[pyo3(get)]
+    message: String,
+    /// `error`, `warning` or `info`.
+    #[pyo3(get)]
+    severity: String,
+    /// The span of the token it is in.
+    #[pyo3(get)]
+    span: Span,
+    /// Where it is in the text of that token, like `Metadata.location`, if it
+    /// is not the whole token.
+    #[pyo3(get)]
+    location: Option<(usize, usize)>,
+}
+
+#[pymethods]
+impl LintProblem {
+    #[new]
+    #[pyo3(signature = (rule, message, span, severity = "warning".to_string(), location = None))]
+    fn new(
+        rule: String,
+        message: String,
+        span: Span,
+        severity: String,
+        location: Option<(usize, usize)>,
+    ) -> PyResult<Self> {
+        if !SEVERITIES.contains(&severity.as_str()) {
+            return Err(PyValueError::new_err(format!(
+                "unknown severity {severity:?}, expected one of {SEVERITIES:?}"
+            )));
+        }
+
+        Ok(Self {
+            rule,
+            message,
+            severity,
+            span,
+            location,
+        })
+    }
+
+    #[pyo3(name = "__repr__")]
+    pub fn py_repr(&self) -> String {
+        format!("LintProblem({:?}, {:?})", self.rule, self.message)
+    }
+}
+
+/// A check over the tokens of a document.
+pub(crate) trait Rule {
+    fn id(&self) -> &str;
+
+    fn check(&self, tokens: &[Token], problems: &mut Vec<LintProblem>) -> PyResult<()>;
+}
+
+/// What a built-in rule reports: the span of the token, where in its text the
+/// problem is, and a message.
+type Found = (Span, Option<(usize, usize)>, String);
+
+/// A rule written in Rust. Adding one takes a function and an entry in
+/// [`BUILTIN`].
+struct Builtin {
+    id: &'static str,
+    severity: &'static str,
+    check: fn(&[Token]) -> Vec<Found>,
+}
+
+impl Rule for Builtin {
+    fn id(&self) -> &str {
+        self.id
+    }
+
+    fn check(&self, tokens: &[Token], problems: &mut Vec<LintProblem>) -> PyResult<()> {
+        problems.extend(
+            (self.check)(tokens)
+                .into_iter()
+                .map(|(span, location, message)| LintProblem {
+                    rule: self.id.to_string(),
+                    message,
+                    severity: self.severity.to_string(),
+                    span,
+                    location,
+                }),
+        );
+
+        Ok(())
+    }
+}
+
+const BUILTIN: &[Builtin] = &[
+    Builtin {
+        id: "duplicate-heading",
+        severity: "warning",
+        check: duplicate_headings,
+    },
+    Builtin {
+        id: "heading-increment",
+        severity: "warning",
+        check: heading_increments,
+    },
+    Builtin {
+        id: "heading-punctuation",
+        severity: "info",
+        check: heading_punctuation,
+    },
+    Builtin {
+        id: "bare-url",
+        severity: "info",
+        check: bare_urls,
+    },
+    Builtin {
+        id: "image-alt",
+        severity: "warning",
+        check: images_without_alt,
+    },
+    Builtin {
+        id: "cross-reference",
+        severity: "warning",
+        check: unresolved_cross_references,
+    },
+    Builtin {
+        id: "unclosed-delimiter",
+        severity: "warning",
+        check: unclosed_raw_spans,
+    },
+    Builtin {
+        id: "long-paragraph",
+        severity: "info",
+        check: long_paragraphs,
+    },
+    Builtin {
+        id: "code-language",
+        severity: "info",
+        check: fences_without_language,
+    },
+];
+
+/// A rule written in Python: a callable taking the tokens and returning the
+/// `LintProblem`s it finds.
+struct PythonRule {
+    id: String,
+    check: PyObject,
+}
+
+impl Rule for PythonRule {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn check(&self, tokens: &[Token], problems: &mut Vec<LintProblem>) -> PyResult<()> {
+        Python::with_gil(|py| {
+            let found = self.check.call1(py, (tokens.to_vec(),))?;
+
+            for problem in found.bind(py).iter()? {
+                problems.push(problem?.extract()?);
+            }
+
+            Ok(())
+        })
+    }
+}
+
+fn headings(tokens: &[Token]) -> impl Iterator<Item = (u8, &str, Span)> {
+    parser::walk(tokens)
+        .into_iter()
+        .filter_map(|token| match token {
+            Token::Heading {
+                level,
+                content,
+                span,
+                ..
+            } => Some((*level, content.as_str(), *span)),
+            _ => None,
+        })
+}
+
+/// The text and metadata of every paragraph and definition, with the span of
+/// its token.
+fn texts(tokens: &[Token]) -> Vec<(Span, &str, &[Metadata])> {
+    let mut texts = Vec::new();
+
+    for token in parser::walk(tokens) {
+        match token {
+            Token::Paragraph(text, metadata, span, ..) => {
+                texts.push((*span, text.as_str(), &metadata[..]))
+            }
+            Token::DefinitionList { items, span, .. } => texts.extend(
+                items
+                    .iter()
+                    .flat_map(|(_, definitions)| definitions)
+                    .map(|(text, metadata)| (*span, text.as_str(), &metadata[..])),
+            ),
+            _ => {}
+        }
+    }
+
+    texts
+}
+
+fn duplicate_headings(tokens: &[Token]) -> Vec<Found> {
+    let mut seen = HashMap::new();
+
+    headings(tokens)
+        .filter_map(|(level, content, span)| {
+            let first = *seen.entry((level, content)).or_insert(span);
+
+            (first != span).then(|| {
+                let message = format!("h{level} {content:?} repeats the one at byte {}", first.0);
+                (span, None, message)
+            })
+        })
+        .collect()
+}
+
+fn heading_increments(tokens: &[Token]) -> Vec<Found> {
+    let mut previous: Option<u8> = None;
+
+    headings(tokens)
+        .filter_map(|(level, _, span)| {
+            let above = previous.replace(level)?;
+
+            (level > above + 1).then(|| (span, None, format!("h{above} is followed by h{level}")))
+        })
+        .collect()
+}
+
+fn heading_punctuation(tokens: &[Token]) -> Vec<Found> {
+    headings(tokens)
+        .filter_map(|(_, content, span)| {
+            let last = content.trim_end().chars().last()?;
+
+            HEADING_PUNCTUATION
+                .contains(last)
+                .then(|| (span, None, format!("heading ends with {last:?}")))
+        })
+        .collect()
+}
+
+fn bare_urls(tokens: &[Token]) -> Vec<Found> {
+    let mut found = Vec::new();
+
+    for (span, text, metadata) in texts(tokens) {
+        for m in BARE_URL_RE.find_iter(text) {
+            let url = m
+                .as_str()
+                .trim_end_matches(['.', ',', ';', ':', '!', '?', ')']);
+            let (start, end) = (m.start(), m.start() + url.len());
+
+            let linked = metadata.iter().any(|metadata| {
+                let (s, e) = metadata.location();
+                start < e && s < end
+            });
+            if !linked {
+                let message = format!("bare URL {url}, write it as <{url}>");
+                found.push((span, Some((start, end)), message));
+            }
+        }
+    }
+
+    found
+}
+
+fn images_without_alt(tokens: &[Token]) -> Vec<Found> {
+    texts(tokens)
+        .into_iter()
+        .flat_map(|(span, _, metadata)| {
+            metadata.iter().filter_map(move |metadata| match metadata {
+                Metadata::Image {
+                    location,
+                    label,
+                    url,
+                    ..
+                } if label.trim().is_empty() => Some((
+                    span,
+                    Some(*location),
+                    format!("image {url} has no alt text"),
+                )),
+                _ => None,
+            })
+        })
+        .collect()
+}
+
+/// Reports links like `[](#install)` that take their label from a heading
+/// when there is no such heading, by the simple or the GitHub slug.
+fn unresolved_cross_references(tokens: &[Token]) -> Vec<Found> {
+    let anchors =
+        [SlugStyle::Simple, SlugStyle::Github].map(|style| anchors::anchor_map(tokens, style));
+    let mut found = Vec::new();
+
+    for (span, _, metadata) in texts(tokens) {
+        for metadata in metadata {
+            let Some(fragment) = anchors::cross_reference(metadata) else {
+                continue;
+            };
+
+            if !anchors.iter().any(|ids| ids.contains_key(&fragment)) {
+                let message =
+                    format!("link to #{fragment} has no label and no heading to take it from");
+                found.push((span, Some(metadata.location()), message));
+            }
+        }
+    }
+
+    found
+}
+
+/// Reports spans of `ParseOptions.preserve_delimiters` that are not closed in
+/// the paragraph they open in, which a template tag broken across blocks is.
+fn unclosed_raw_spans(tokens: &[Token]) -> Vec<Found> {
+    texts(tokens)
+        .into_iter()
+        .flat_map(|(span, _, metadata)| {
+            metadata.iter().filter_map(move |metadata| match metadata {
+                Metadata::Raw {
+                    location,
+                    closed: false,
+                } => Some((
+                    span,
+                    Some(*location),
+                    "raw span is not closed before the end of its paragraph".to_string(),
+                )),
+                _ => None,
+            })
+        })
+        .collect()
+}
+
+fn long_paragraphs(tokens: &[Token]) -> Vec<Found> {
+    parser::walk(tokens)
+        .into_iter()
+        .filter_map(|token| match token {
+            Token::Paragraph(text, _, span, ..) => {
+                let length = text.chars().count();
+
+                (length > LONG_PARAGRAPH).then(|| {
+                    let message = format!(
+                        "paragraph is {length} characters long, more than {LONG_PARAGRAPH}"
+                    );
+                    (*span, None, message)
+                })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn fences_without_language(tokens: &[Token]) -> Vec<Found> {
+    parser::walk(tokens)
+        .into_iter()
+        .filter_map(|token| match token {
+            Token::Code {
+                language,
+                fence_char: Some(_),
+                span,
+                ..
+            } if language.is_empty() => {
+                Some((*span, None, "code fence has no language".to_string()))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Runs the built-in rules and the `custom` ones, keyed by id, over `tokens`.
+///
+/// `rules` picks which run: an id selects its rule and `-id` leaves it out.
+/// Without any selected, every rule runs but those left out. The problems are
+/// sorted by where their token starts.
+pub(crate) fn lint(
+    tokens: &[Token],
+    rules: Option<Vec<String>>,
+    custom: BTreeMap<String, PyObject>,
+) -> PyResult<Vec<LintProblem>> {
+    let mut python = Vec::new();
+    for (id, check) in custom {
+        if BUILTIN.iter().any(|rule| rule.id == id) {
+            return Err(PyValueError::new_err(format!(
+                "custom rule {id:?} has the id of a built-in rule"
+            )));
+        }
+        python.push(PythonRule { id, check });
+    }
+
+    let all: Vec<&dyn Rule> = BUILTIN
+        .iter()
+        .map(|rule| rule as &dyn Rule)
+        .chain(python.iter().map(|rule| rule as &dyn Rule))
+        .collect();
+
+    let rules = rules.unwrap_or_default();
+    let (left_out, selected): (Vec<&str>, Vec<&str>) = rules
+        .iter()
+        .map(String::as_str)
+        .partition(|rule| rule.starts_with('-'));
+    let left_out: Vec<&str> = left_out.iter().map(|rule| &rule[1..]).collect();
+
+    if let Some(unknown) = selected
+        .iter()
+        .chain(&left_out)
+        .find(|id| !all.iter().any(|rule| rule.id() == **id))
+    {
+        let ids: Vec<&str> = all.iter().map(|rule| rule.id()).collect();
+        return Err(PyValueError::new_err(format!(
+            "unknown lint rule {unknown:?}, expected one of {ids:?}"
+        )));
+    }
+
+    let mut problems = Vec::new();
+    for rule in all {
+        let id = rule.id();
+        if (selected.is_empty() || selected.contains(&id)) && !left_out.contains(&id) {
+            rule.check(tokens, &mut problems)?;
+        }
+    }
+    problems.sort_by_key(|problem| problem.span.0);
+
+    Ok(problems)
+}
@@ -0,0 +1,394 @@
+use crate::parser::{Metadata, Token};
+
+/// How many columns a character takes up in a terminal or editor: two for
+/// East Asian wide and fullwidth characters and most emoji, none for
+/// control characters, combining marks and zero-width characters, one for
+/// everything else.
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x00..=0x1F
+        | 0x7F
+        | 0x0300..=0x036F
+        | 0x200B..=0x200F
+        | 0x20D0..=0x20FF
+        | 0xFE00..=0xFE0F => 0,
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x1F900..=0x1F9FF
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+fn display_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+/// The byte ranges of the code spans in `text`: a run of backticks up to the
+/// next run of the same length.
+pub(crate) fn code_spans(text: &str) -> Vec<(usize, usize)> {
+    let bytes = text.as_bytes();
+    let run = |i: usize| bytes[i..].iter().take_while(|&&b| b == b'`').count();
+
+    let mut spans = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] != b'`' {
+            i += 1;
+            continue;
+        }
+
+        let open = run(i);
+        let mut j = i + open;
+        let mut close = None;
+
+        while j < bytes.len() {
+            if bytes[j] == b'`' {
+                let length = run(j);
+                if length == open {
+                    close = Some(j + length);
+                    break;
+                }
+                j += length;
+            } else {
+                j += 1;
+            }
+        }
+
+        match close {
+            Some(end) => {
+                spans.push((i, end));
+                i = end;
+            }
+            None => i += open,
+        }
+    }
+
+    spans
+}
+
+/// Whether a line starting with `word` could be read as something other
+/// than the paragraph text it continues: a heading, rule, fence, definition,
+/// abbreviation, math block, block quote or list item.
+fn starts_block(word: &str) -> bool {
+    let digits = word.len() - word.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+
+    word.starts_with(['#', '＃', '>', '-', '+', '*', '_', '=', ':', '|'])
+        || word.starts_with("```")
+        || word.starts_with("~~~")
+        || word.starts_with("$$")
+        || (digits > 0 && word[digits..].starts_with(['.', ')']))
+}
+
+/// Trims the spaces, tabs and line breaks off the end of `text`. Other
+/// whitespace, like a non-breaking space, can be content.
+fn trim_end(text: &str) -> &str {
+    text.trim_end_matches(|c: char| c.is_ascii_whitespace())
+}
+
+/// Breaks `text` into lines at most `width` columns wide.
+///
+/// Lines are only broken at spaces, never inside `unbreakable` ranges or code
+/// spans, never next to other whitespace (an ideographic space, say) that
+/// parsing would trim off the line, never after a backslash that would make
+/// the break a hard one, and never before a word that would start another
+/// block. A word wider than `width` gets a line of its own. Every run of
+/// spaces where a line could break is written as one space or one line
+/// break, so no line ends with a space and wrapping the result again gives
+/// the same lines.
+fn wrap(text: &str, unbreakable: &[(usize, usize)], width: usize) -> Vec<String> {
+    let mut unbreakable = unbreakable.to_vec();
+    unbreakable.extend(code_spans(text));
+    let breakable = |i: usize| !unbreakable.iter().any(|&(start, end)| start < i && i < end);
+
+    let mut words = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+
+    while i < text.len() {
+        if text.as_bytes()[i] != b' ' {
+            i += 1;
+            continue;
+        }
+
+        let end = i + text[i..].len() - text[i..].trim_start_matches(' ').len();
+        let trimmed = text[..i].ends_with(char::is_whitespace)
+            || text[end..].starts_with(char::is_whitespace);
+        // A line ending in an unescaped backslash ends in a hard break
+        let backslashes = i - text[..i].trim_end_matches('\\').len();
+        let hard = backslashes % 2 == 1;
+        if i > start
+            && end < text.len()
+            && breakable(i)
+            && !trimmed
+            && !hard
+            && !starts_block(&text[end..])
+        {
+            words.push(&text[start..i]);
+            start = end;
+        }
+        i = end;
+    }
+    words.push(trim_end(&text[start..]));
+
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut used = 0;
+
+    for word in words {
+        let needed = display_width(word);
+
+        if !line.is_empty() {
+            if used + 1 + needed > width {
+                lines.push(std::mem::take(&mut line));
+                used = 0;
+            } else {
+                line.push(' ');
+                used += 1;
+            }
+        }
+
+        line += word;
+        used += needed;
+    }
+    lines.push(line);
+
+    lines
+        .into_iter()
+        .map(|line| trim_end(&line).to_string())
+        .collect()
+}
+
+/// Writes the text of a paragraph or definition, keeping the links and
+/// images at `locations` on one line. A line ends in a backslash at each of
+/// `hard_breaks`, the joining spaces of `Metadata::LineBreak`s.
+fn prose(
+    text: &str,
+    locations: &[(usize, usize)],
+    hard_breaks: &[usize],
+    width: Option<usize>,
+) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+
+    for &at in hard_breaks.iter().chain([&text.len()]) {
+        let piece = &text[start..at];
+        let locations: Vec<_> = locations
+            .iter()
+            .filter(|&&(s, e)| start <= s && e <= at)
+            .map(|&(s, e)| (s - start, e - start))
+            .collect();
+
+        if start > 0 {
+            if let Some(last) = lines.last_mut() {
+                *last += "\\";
+            }
+        }
+        match width {
+            Some(width) => lines.extend(wrap(piece, &locations, width)),
+            None => lines.push(trim_end(piece).to_string()),
+        }
+        start = (at + 1).min(text.len());
+    }
+
+    lines
+}
+
+/// The joining spaces of the hard breaks among `metadata`, moved by `by`,
+/// which leaves out those it returns `None` for.
+fn hard_breaks(metadata: &[Metadata], by: impl Fn(usize) -> Option<usize>) -> Vec<usize> {
+    metadata
+        .iter()
+        .filter_map(|metadata| match metadata {
+            Metadata::LineBreak { location } => by(location.0),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Backslash-escapes the marker at the start of a paragraph that would
+/// otherwise be read as another block, like the `#` of a paragraph that came
+/// from an indented ` # text` line. Spaces before the marker, kept by
+/// `trim_lines=False`, are looked past. A full-width `＃`, which only
+/// `lenient_headings` reads as a marker, cannot be backslash-escaped and is
+/// written as a character reference instead. Returns the text, and where the
+/// escape went and how many bytes it added, if one was.
+fn escape_start(text: &str) -> (String, Option<(usize, usize)>) {
+    let indent = text.len() - text.trim_start_matches(' ').len();
+    if !starts_block(&text[indent..]) {
+        return (text.to_string(), None);
+    }
+
+    if let Some(rest) = text[indent..].strip_prefix('＃') {
+        let escaped = format!("{}&#xFF03;{rest}", &text[..indent]);
+        let added = escaped.len() - text.len();
+        return (escaped, Some((indent, added)));
+    }
+
+    let marker = indent
+        + text[indent..]
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(0);
+    (
+        format!("{}\\{}", &text[..marker], &text[marker..]),
+        Some((marker, 1)),
+    )
+}
+
+/// Writes tokens back out as Markdown, wrapping paragraphs and definitions at
+/// `width` columns, or not at all if it is `None`.
+///
+/// The output parses back to tokens that render the same HTML, and writing
+/// those out again gives the same Markdown. With a `width`, the one exception
+/// is a run of spaces between words, which is written as one space or line
+/// break and so renders alike only with `collapse_whitespace`. A paragraph
+/// starting with a block marker has it backslash-escaped. Rules keep their
+/// marker and length, and fences their character, length and info string,
+/// set off by a space if it starts with the fence character. A code block
+/// without a fence is written with one, since `parse` does not read indented
+/// code. Containers are closed with `:::`, which the one they are in skips as
+/// the close of a nested container.
+pub(crate) fn tokens_to_markdown(tokens: &[Token], width: Option<usize>) -> String {
+    let mut blocks: Vec<String> = Vec::with_capacity(tokens.len());
+
+    for token in tokens {
+        match token {
+            Token::Heading { level, content, .. } => {
+                let hashes = "#".repeat(*level as usize);
+                blocks.push(match content.as_str() {
+                    "" => hashes,
+                    content => format!("{hashes} {content}"),
+                })
+            }
+            Token::Paragraph(text, metadata, ..) => {
+                let (text, escape) = escape_start(text);
+                let escaped = |i: usize| match escape {
+                    Some((at, added)) if i >= at => i + added,
+                    _ => i,
+                };
+                let locations: Vec<_> = metadata
+                    .iter()
+                    .map(Metadata::location)
+                    .map(|(start, end)| (escaped(start), escaped(end)))
+                    .collect();
+                let hard_breaks = hard_breaks(metadata, |at| Some(escaped(at)));
+
+                blocks.push(prose(&text, &locations, &hard_breaks, width).join("\n"))
+            }
+            Token::HorizontalRule { marker, length, .. } => {
+                blocks.push(marker.to_string().repeat((*length).max(3)))
+            }
+            Token::Abbreviation {
+                abbr, expansion, ..
+            } => blocks.push(format!("*[{abbr}]: {expansion}").trim_end().to_string()),
+            Token::DefinitionList { items, .. } => {
+                let mut block = Vec::new();
+
+                for (term, definitions) in items {
+                    block.push(term.clone());
+
+                    for (text, metadata) in definitions {
+                        // Continuation lines and paragraphs are indented
+                        // under the `: `
+                        let width = width.map(|width| width.saturating_sub(4).max(1));
+                        let mut offset = 0;
+
+                        for (i, paragraph) in text.split("\n\n").enumerate() {
+                            let locations: Vec<_> = metadata
+                                .iter()
+                                .map(Metadata::location)
+                                .filter(|&(start, end)| {
+                                    offset <= start && end <= offset + paragraph.len()
+                                })
+                                .map(|(start, end)| (start - offset, end - offset))
+                                .collect();
+                            let hard_breaks = hard_breaks(metadata, |at| {
+                                (offset <= at && at < offset + paragraph.len()).then(|| at - offset)
+                            });
+                            offset += paragraph.len() + 2;
+
+                            let lines = prose(paragraph, &locations, &hard_breaks, width);
+                            for (j, line) in lines.iter().enumerate() {
+                                match (i, j) {
+                                    (0, 0) => block.push(format!(":   {line}")),
+                                    (_, 0) => block.push(format!("\n    {line}")),
+                                    _ => block.push(format!("    {line}")),
+                                }
+                            }
+                        }
+                    }
+                }
+
+                blocks.push(block.join("\n"));
+            }
+            Token::Code {
+                language,
+                content,
+                fence_char,
+                fence_length,
+                indent,
+                info,
+                ..
+            } => {
+                let marker = fence_char.unwrap_or('`');
+                let info = if info.is_empty() { language } else { info };
+
+                // Lengthen the fence past any line of the content that would
+                // close it
+                let length = content
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && line.chars().all(|c| c == marker))
+                    .map(|line| line.len() + 1)
+                    .fold((*fence_length).max(3), usize::max);
+
+                let indent = " ".repeat(if fence_char.is_some() { *indent } else { 0 });
+                let fence = marker.to_string().repeat(length);
+                // An info string starting with the marker would lengthen the
+                // fence
+                let space = if info.starts_with(marker) { " " } else { "" };
+                let newline = if content.is_empty() || content.ends_with('\n') {
+                    ""
+                } else {
+                    "\n"
+                };
+
+                blocks.push(format!(
+                    "{indent}{fence}{space}{info}\n{content}{newline}{indent}{fence}"
+                ));
+            }
+            Token::Container {
+                name,
+                attrs,
+                content,
+                ..
+            } => {
+                let open = format!(":::{name} {attrs}");
+                let open = open.trim_end();
+
+                blocks.push(if content.is_empty() {
+                    format!("{open}\n:::")
+                } else {
+                    format!("{open}\n{}\n:::", tokens_to_markdown(content, width))
+                });
+            }
+            Token::MathBlock { content, .. } => blocks.push(match content.as_str() {
+                "" => "$$\n$$".to_string(),
+                content => format!("$$\n{content}\n$$"),
+            }),
+        }
+    }
+
+    blocks.join("\n\n")
+}
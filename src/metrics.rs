@@ -0,0 +1,164 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use pyo3::prelude::*;
+
+use crate::parser::{self, Token};
+
+/// Times the phases of a parse or a render, if it was asked to. Off, it
+/// only runs them.
+#[derive(Default)]
+pub(crate) struct Phases(Option<Vec<(&'static str, Duration)>>);
+
+impl Phases {
+    pub(crate) fn on() -> Self {
+        Self(Some(Vec::new()))
+    }
+
+    pub(crate) fn off() -> Self {
+        Self(None)
+    }
+
+    /// Runs `phase`, and adds the time it took to that of `name`.
+    pub(crate) fn time<T>(&mut self, name: &'static str, phase: impl FnOnce() -> T) -> T {
+        let Some(phases) = &mut self.0 else {
+            return phase();
+        };
+
+        let start = Instant::now();
+        let value = phase();
+        let elapsed = start.elapsed();
+
+        match phases.iter_mut().find(|(phase, _)| *phase == name) {
+            Some((_, total)) => *total += elapsed,
+            None => phases.push((name, elapsed)),
+        }
+        value
+    }
+}
+
+/// What one call to `parse` or `tokens_to_html` with `collect_metrics=True`
+/// did, measured in the Rust core, so time spent waiting for the GIL is left
+/// out.
+#[pyclass(frozen)]
+pub(crate) struct Metrics {
+    /// `"parse"` or `"render"`.
+    #[pyo3(get)]
+    operation: &'static str,
+    /// The wall time of the call, in seconds.
+    #[pyo3(get)]
+    seconds: f64,
+    /// The wall time of each phase, in seconds: `blocks` and `abbreviations`
+    /// for a parse, `nodes` (escaping and placing links and other metadata)
+    /// and `write` for a render.
+    #[pyo3(get)]
+    phases: BTreeMap<&'static str, f64>,
+    /// The size of the Markdown parsed. Not known for a render.
+    #[pyo3(get)]
+    input_bytes: Option<usize>,
+    /// The size of the HTML rendered. Not known for a parse.
+    #[pyo3(get)]
+    output_bytes: Option<usize>,
+    /// How many tokens there are of each kind, those inside containers
+    /// included.
+    #[pyo3(get)]
+    tokens: BTreeMap<&'static str, usize>,
+    /// How many metadata there are of each kind, in paragraphs and
+    /// definitions.
+    #[pyo3(get)]
+    metadata: BTreeMap<&'static str, usize>,
+}
+
+#[pymethods]
+impl Metrics {
+    #[pyo3(name = "__repr__")]
+    fn py_repr(&self) -> String {
+        format!("Metrics({:?}, {:.6})", self.operation, self.seconds)
+    }
+
+    /// The metrics as Prometheus names them, with the kind or phase as a
+    /// label: `md0_parse_seconds`, `md0_parse_phase_seconds{phase="blocks"}`,
+    /// `md0_input_bytes`, `md0_tokens{kind="heading"}`, and so on.
+    fn as_dict(&self) -> BTreeMap<String, f64> {
+        let operation = self.operation;
+        let mut metrics = BTreeMap::new();
+
+        metrics.insert(format!("md0_{operation}_seconds"), self.seconds);
+        for (phase, seconds) in &self.phases {
+            metrics.insert(
+                format!("md0_{operation}_phase_seconds{{phase=\"{phase}\"}}"),
+                *seconds,
+            );
+        }
+        if let Some(bytes) = self.input_bytes {
+            metrics.insert("md0_input_bytes".to_string(), bytes as f64);
+        }
+        if let Some(bytes) = self.output_bytes {
+            metrics.insert("md0_output_bytes".to_string(), bytes as f64);
+        }
+        for (kind, count) in &self.tokens {
+            metrics.insert(format!("md0_tokens{{kind=\"{kind}\"}}"), *count as f64);
+        }
+        for (kind, count) in &self.metadata {
+            metrics.insert(format!("md0_metadata{{kind=\"{kind}\"}}"), *count as f64);
+        }
+
+        metrics
+    }
+}
+
+impl Metrics {
+    pub(crate) fn parse(input: &str, tokens: &[Token], elapsed: Duration, phases: Phases) -> Self {
+        Self::new("parse", tokens, elapsed, phases, Some(input.len()), None)
+    }
+
+    pub(crate) fn render(tokens: &[Token], html: &str, elapsed: Duration, phases: Phases) -> Self {
+        Self::new("render", tokens, elapsed, phases, None, Some(html.len()))
+    }
+
+    fn new(
+        operation: &'static str,
+        tokens: &[Token],
+        elapsed: Duration,
+        phases: Phases,
+        input_bytes: Option<usize>,
+        output_bytes: Option<usize>,
+    ) -> Self {
+        let mut counts = BTreeMap::new();
+        let mut metadata = BTreeMap::new();
+
+        for token in parser::walk(tokens) {
+            *counts.entry(token.kind()).or_default() += 1;
+
+            let found: Vec<_> = match token {
+                Token::Paragraph(_, metadata, ..) => metadata.iter().collect(),
+                Token::DefinitionList { items, .. } => items
+                    .iter()
+                    .flat_map(|(_, definitions)| definitions)
+                    .flat_map(|(_, metadata)| metadata)
+                    .collect(),
+                _ => Vec::new(),
+            };
+            for found in found {
+                *metadata.entry(found.kind()).or_default() += 1;
+            }
+        }
+
+        Self {
+            operation,
+            seconds: elapsed.as_secs_f64(),
+            phases: phases
+                .0
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(name, elapsed)| (name, elapsed.as_secs_f64()))
+                .collect(),
+            input_bytes,
+            output_bytes,
+            tokens: counts,
+            metadata,
+        }
+    }
+}
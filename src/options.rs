@@ -0,0 +1,428 @@
+use pyo3::{exceptions::PyValueError, prelude::*};
+
+use crate::{case::HeadingCase, element::OutputStyle, slug::SlugStyle};
+
+/// The bool options that only change how another option works, rather than
+/// turning on something of their own.
+pub(crate) const MODIFIERS: &[&str] = &["open_first"];
+
+/// What the parser always does, without a flag to turn it on.
+pub(crate) const FEATURES: &[&str] = &[
+    "autolinks",
+    "angle-bracket-destinations",
+    "image-sizes",
+    "fence-attributes",
+    "setext-headings",
+    "thematic-break-markers",
+    "github-slugs",
+];
+
+/// Options controlling how Markdown is tokenized.
+#[pyclass(frozen)]
+#[derive(Clone)]
+pub(crate) struct ParseOptions {
+    /// Strip leading and trailing whitespace from each paragraph line before
+    /// the lines are joined. Turn off to keep hanging indents as written.
+    #[pyo3(get)]
+    pub(crate) trim_lines: bool,
+    /// Collapse runs of spaces and tabs inside paragraphs into a single space.
+    /// Headings and definition terms are always collapsed.
+    #[pyo3(get)]
+    pub(crate) collapse_whitespace: bool,
+    /// Keep the exact source text of every token as `token.raw`.
+    #[pyo3(get)]
+    pub(crate) keep_raw: bool,
+    /// Parse PHP Markdown Extra definition lists: a term line followed by one
+    /// or more `: definition` lines.
+    #[pyo3(get)]
+    pub(crate) definition_lists: bool,
+    /// Parse `*[ABBR]: expansion` abbreviation definitions.
+    #[pyo3(get)]
+    pub(crate) abbreviations: bool,
+    /// Link bare email addresses to `mailto:` them, like GFM does.
+    #[pyo3(get)]
+    pub(crate) autolink_emails: bool,
+    /// Count non-breaking spaces as whitespace, so a line of them is blank
+    /// and trimming takes them off. Turn off to keep them as indentation.
+    #[pyo3(get)]
+    pub(crate) nbsp_is_whitespace: bool,
+    /// How many columns apart tab stops are. A tab in the indentation of a
+    /// line counts as the spaces up to the next one when deciding what block
+    /// the line is; code blocks keep their tabs as written.
+    #[pyo3(get)]
+    pub(crate) tab_width: usize,
+    /// Parses `:::name` ... `:::` custom containers, the directive syntax many
+    /// static site generators use for callouts, into `Token.Container`.
+    #[pyo3(get)]
+    pub(crate) containers: bool,
+    /// Give links and images the `span` of the source they were parsed from.
+    /// Their `location` is in the text of the token, whose lines were joined
+    /// and trimmed, so it cannot be mapped back to the source on its own.
+    #[pyo3(get)]
+    pub(crate) source_spans: bool,
+    /// Also read the headings other tools write: full-width `＃` hashes, no
+    /// space between the hashes and CJK text (`#标题`), and AsciiDoc-style
+    /// `== Title ==`. The last two never interrupt a paragraph, and a line with
+    /// another hash in it, like a `#话题#` hashtag, stays text.
+    #[pyo3(get)]
+    pub(crate) lenient_headings: bool,
+    /// Pairs of delimiters, like `[("{{", "}}"), ("{%", "%}")]` for Jinja,
+    /// between which paragraph text is kept as written: no links or images
+    /// are found in it, and it is rendered without escaping as
+    /// `Metadata.Raw`. A span not closed before the end of its paragraph runs
+    /// to the end but is still escaped, and the `unclosed-delimiter` lint
+    /// rule reports it.
+    #[pyo3(get)]
+    pub(crate) preserve_delimiters: Vec<(String, String)>,
+    /// Make `parse` raise `md0.ParseError` for a code fence, container or math
+    /// block that is never closed, instead of letting it run to the end of
+    /// the input, and for a container nested deeper than `max_depth`.
+    #[pyo3(get)]
+    pub(crate) strict: bool,
+    /// Parses `$...$` inline and `$$...$$` block math, which is rendered as
+    /// written for MathJax or KaTeX to typeset. A `$` opening inline math
+    /// needs something other than a space after it, and one closing it
+    /// something other than a space before it and no digit after it, so prices
+    /// like `$5 or $10` stay text. No links or images are found in math.
+    #[pyo3(get)]
+    pub(crate) math: bool,
+    /// Replace NUL and other control characters in the text of tokens with
+    /// U+FFFD, moving metadata offsets to match. `raw` keeps them as written.
+    #[pyo3(get)]
+    pub(crate) sanitize_control_chars: bool,
+    /// Find Obsidian wiki links, `[[Page Name]]` and `[[Page Name|text]]`.
+    /// They link to the slug of the page, in `HtmlOptions.slug_style`.
+    #[pyo3(get)]
+    pub(crate) wiki_links: bool,
+    /// How deep containers may nest. The `:::` lines of any deeper are
+    /// paragraph text, so untrusted input cannot nest deep enough to
+    /// overflow the stack, and with `strict` they raise `md0.ParseError`.
+    #[pyo3(get)]
+    pub(crate) max_depth: usize,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            trim_lines: true,
+            collapse_whitespace: false,
+            keep_raw: false,
+            definition_lists: false,
+            abbreviations: false,
+            autolink_emails: false,
+            nbsp_is_whitespace: true,
+            tab_width: 4,
+            containers: false,
+            source_spans: false,
+            lenient_headings: false,
+            preserve_delimiters: Vec::new(),
+            strict: false,
+            math: false,
+            sanitize_control_chars: false,
+            wiki_links: false,
+            max_depth: 64,
+        }
+    }
+}
+
+#[pymethods]
+impl ParseOptions {
+    #[new]
+    #[pyo3(signature = (
+        *,
+        trim_lines = true,
+        collapse_whitespace = false,
+        keep_raw = false,
+        definition_lists = false,
+        abbreviations = false,
+        autolink_emails = false,
+        nbsp_is_whitespace = true,
+        tab_width = 4,
+        containers = false,
+        source_spans = false,
+        lenient_headings = false,
+        preserve_delimiters = Vec::new(),
+        strict = false,
+        math = false,
+        sanitize_control_chars = false,
+        wiki_links = false,
+        max_depth = 64,
+    ))]
+    fn new(
+        trim_lines: bool,
+        collapse_whitespace: bool,
+        keep_raw: bool,
+        definition_lists: bool,
+        abbreviations: bool,
+        autolink_emails: bool,
+        nbsp_is_whitespace: bool,
+        tab_width: usize,
+        containers: bool,
+        source_spans: bool,
+        lenient_headings: bool,
+        preserve_delimiters: Vec<(String, String)>,
+        strict: bool,
+        math: bool,
+        sanitize_control_chars: bool,
+        wiki_links: bool,
+        max_depth: usize,
+    ) -> PyResult<Self> {
+        if tab_width == 0 {
+            return Err(PyValueError::new_err("tab_width must be at least 1"));
+        }
+        if preserve_delimiters
+            .iter()
+            .any(|(open, close)| open.is_empty() || close.is_empty())
+        {
+            return Err(PyValueError::new_err(
+                "preserve_delimiters cannot hold an empty delimiter",
+            ));
+        }
+
+        Ok(Self {
+            trim_lines,
+            collapse_whitespace,
+            keep_raw,
+            definition_lists,
+            abbreviations,
+            autolink_emails,
+            nbsp_is_whitespace,
+            tab_width,
+            containers,
+            source_spans,
+            lenient_headings,
+            preserve_delimiters,
+            strict,
+            math,
+            sanitize_control_chars,
+            wiki_links,
+            max_depth,
+        })
+    }
+}
+
+/// Options controlling how tokens are rendered to HTML.
+#[pyclass(frozen)]
+#[derive(Clone)]
+pub(crate) struct HtmlOptions {
+    /// Lowercase code block languages and map common aliases (`js`, `py`,
+    /// ...) to one name in the `language-*` class. The token is untouched.
+    #[pyo3(get)]
+    pub(crate) normalize_languages: bool,
+    /// Add `dir="auto"` to headings and paragraphs, so browsers pick the text
+    /// direction from the content of each block.
+    #[pyo3(get)]
+    pub(crate) auto_dir: bool,
+    /// Add `loading="lazy"` to images.
+    #[pyo3(get)]
+    pub(crate) lazy_images: bool,
+    /// Keep the line breaks of a paragraph as newlines in the HTML instead of
+    /// joining its lines with spaces.
+    #[pyo3(get)]
+    pub(crate) preserve_soft_breaks: bool,
+    /// Write the address of `mailto:` links as numeric character references
+    /// in the `href`, and in the link text if it is the address itself.
+    #[pyo3(get)]
+    pub(crate) obfuscate_emails: bool,
+    /// Add `role="presentation"` to images with an empty `alt`, which marks
+    /// them as decorative for assistive technology.
+    #[pyo3(get)]
+    pub(crate) decorative_role: bool,
+    /// Give every heading an `id` to link to, made by `slug_style`. One with
+    /// no letters or digits to slug, as `## 🎉`, is a `section`.
+    #[pyo3(get)]
+    pub(crate) heading_ids: bool,
+    /// How heading ids are made: `"simple"`, or `"github"` for the anchors
+    /// GitHub generates.
+    pub(crate) slug_style: SlugStyle,
+    /// Percent-encode spaces, non-ASCII and other unsafe characters in link
+    /// and image URLs, leaving existing `%XX` escapes alone.
+    #[pyo3(get)]
+    pub(crate) encode_urls: bool,
+    /// Wrap runs of two or more paragraphs that are only an image in one
+    /// `<div class="gallery">`, with the bare `<img>`s inside.
+    #[pyo3(get)]
+    pub(crate) group_images: bool,
+    /// Render a paragraph that is an image followed by text as a `<figure>`,
+    /// with the text as its `<figcaption>`.
+    #[pyo3(get)]
+    pub(crate) figures: bool,
+    /// Escape `&`, `<` and `>` in the text of paragraphs, headings and
+    /// definition lists. Turning this off lets inline HTML through as it is,
+    /// which is only safe for Markdown you trust: anyone who can write the
+    /// source can then run scripts in the page (XSS). Attributes are always
+    /// escaped.
+    #[pyo3(get)]
+    pub(crate) escape: bool,
+    /// Escape the content of code blocks, like `escape` does for text. The
+    /// same warning applies.
+    #[pyo3(get)]
+    pub(crate) escape_code: bool,
+    /// Leaves out the heading `title` returns, so a page template can show it
+    /// itself. It still takes up its slug, so the ids of the other headings stay
+    /// the same.
+    #[pyo3(get)]
+    pub(crate) strip_title: bool,
+    /// Show a bare URL autolink, `<https://...>`, as its host and the start
+    /// of its path, cut short with `…` if it is long. The `href` keeps the
+    /// whole URL.
+    #[pyo3(get)]
+    pub(crate) shorten_urls: bool,
+    /// Change the case of heading text: `"preserve"` it, or make it
+    /// `"sentence"`, `"title"` or `"upper"` case. Code spans and URLs keep
+    /// theirs.
+    pub(crate) heading_case: HeadingCase,
+    /// Make heading ids from the text as `heading_case` changes it. Off by
+    /// default, so the anchors other pages link to stay the same.
+    #[pyo3(get)]
+    pub(crate) recompute_slugs: bool,
+    /// Write `"html5"`, with `<hr>` and boolean attributes by name alone, or
+    /// `"xhtml"`, with `<hr />` and `checked="checked"`, for an XML pipeline
+    /// like EPUB. Every element the renderer makes follows it; HTML passed
+    /// through as it is, like text with `escape=False`, is left as written.
+    pub(crate) output_style: OutputStyle,
+    /// Strip the whitespace off the end of every line of a code block. Off by
+    /// default, so code is rendered exactly as written.
+    #[pyo3(get)]
+    pub(crate) trim_code_lines: bool,
+    /// Render every heading of this level, with the blocks under it up to the
+    /// next heading of the same level or above, as a collapsed `<details>`
+    /// with the heading in its `<summary>`. Headings inside containers are
+    /// left alone.
+    #[pyo3(get)]
+    pub(crate) collapse_level: Option<u8>,
+    /// With `collapse_level`, leave the first section expanded.
+    #[pyo3(get)]
+    pub(crate) open_first: bool,
+    /// Replace NUL and other control characters with U+FFFD before rendering,
+    /// so the output is always valid HTML, and XML.
+    #[pyo3(get)]
+    pub(crate) sanitize_control_chars: bool,
+}
+
+impl Default for HtmlOptions {
+    fn default() -> Self {
+        Self {
+            normalize_languages: false,
+            auto_dir: false,
+            lazy_images: false,
+            preserve_soft_breaks: false,
+            obfuscate_emails: false,
+            decorative_role: false,
+            heading_ids: false,
+            slug_style: SlugStyle::Simple,
+            encode_urls: true,
+            group_images: false,
+            figures: false,
+            escape: true,
+            escape_code: true,
+            strip_title: false,
+            shorten_urls: false,
+            heading_case: HeadingCase::Preserve,
+            recompute_slugs: false,
+            output_style: OutputStyle::Html5,
+            trim_code_lines: false,
+            collapse_level: None,
+            open_first: false,
+            sanitize_control_chars: true,
+        }
+    }
+}
+
+#[pymethods]
+impl HtmlOptions {
+    #[new]
+    #[pyo3(signature = (
+        *,
+        normalize_languages = false,
+        auto_dir = false,
+        lazy_images = false,
+        preserve_soft_breaks = false,
+        obfuscate_emails = false,
+        decorative_role = false,
+        heading_ids = false,
+        slug_style = "simple",
+        encode_urls = true,
+        group_images = false,
+        figures = false,
+        escape = true,
+        escape_code = true,
+        strip_title = false,
+        shorten_urls = false,
+        heading_case = "preserve",
+        recompute_slugs = false,
+        output_style = "html5",
+        trim_code_lines = false,
+        collapse_level = None,
+        open_first = false,
+        sanitize_control_chars = true,
+    ))]
+    fn new(
+        normalize_languages: bool,
+        auto_dir: bool,
+        lazy_images: bool,
+        preserve_soft_breaks: bool,
+        obfuscate_emails: bool,
+        decorative_role: bool,
+        heading_ids: bool,
+        slug_style: &str,
+        encode_urls: bool,
+        group_images: bool,
+        figures: bool,
+        escape: bool,
+        escape_code: bool,
+        strip_title: bool,
+        shorten_urls: bool,
+        heading_case: &str,
+        recompute_slugs: bool,
+        output_style: &str,
+        trim_code_lines: bool,
+        collapse_level: Option<u8>,
+        open_first: bool,
+        sanitize_control_chars: bool,
+    ) -> PyResult<Self> {
+        if collapse_level.is_some_and(|level| !(1..=6).contains(&level)) {
+            return Err(PyValueError::new_err("collapse_level must be from 1 to 6"));
+        }
+
+        Ok(Self {
+            normalize_languages,
+            auto_dir,
+            lazy_images,
+            preserve_soft_breaks,
+            obfuscate_emails,
+            decorative_role,
+            heading_ids,
+            slug_style: SlugStyle::parse(slug_style)?,
+            encode_urls,
+            group_images,
+            figures,
+            escape,
+            escape_code,
+            strip_title,
+            shorten_urls,
+            heading_case: HeadingCase::parse(heading_case)?,
+            recompute_slugs,
+            output_style: OutputStyle::parse(output_style)?,
+            trim_code_lines,
+            collapse_level,
+            open_first,
+            sanitize_control_chars,
+        })
+    }
+
+    #[getter]
+    fn slug_style(&self) -> &'static str {
+        self.slug_style.name()
+    }
+
+    #[getter]
+    fn heading_case(&self) -> &'static str {
+        self.heading_case.name()
+    }
+
+    #[getter]
+    fn output_style(&self) -> &'static str {
+        self.output_style.name()
+    }
+}
@@ -0,0 +1,250 @@
+use pyo3::{exceptions::PyValueError, prelude::*};
+
+use crate::{
+    parser::{self, Span, Token, Tokens},
+    slug::{SlugStyle, Slugger},
+};
+
+/// How section numbers are written, read off an example such as `1.2.3`,
+/// `1-2-3` or `1.2.3.`: the first run of non-digits between two numbers is the
+/// separator, and non-digits after the last number are a suffix.
+struct NumberFormat {
+    separator: String,
+    suffix: String,
+}
+
+impl NumberFormat {
+    fn parse(format: &str) -> PyResult<Self> {
+        if !format.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(PyValueError::new_err(format!(
+                "number format must start with a digit, got {format:?}"
+            )));
+        }
+
+        let mut separator = None;
+        let mut rest = format.trim_start_matches(|c: char| c.is_ascii_digit());
+
+        while !rest.is_empty() {
+            let run = rest.len() - rest.trim_start_matches(|c: char| !c.is_ascii_digit()).len();
+            let (text, after) = rest.split_at(run);
+
+            if after.is_empty() {
+                return Ok(Self {
+                    separator: separator.unwrap_or(".").to_string(),
+                    suffix: text.to_string(),
+                });
+            }
+
+            separator.get_or_insert(text);
+            rest = after.trim_start_matches(|c: char| c.is_ascii_digit());
+        }
+
+        Ok(Self {
+            separator: separator.unwrap_or(".").to_string(),
+            suffix: String::new(),
+        })
+    }
+
+    fn write(&self, counters: &[usize]) -> String {
+        let numbers: Vec<String> = counters.iter().map(usize::to_string).collect();
+        numbers.join(&self.separator) + &self.suffix
+    }
+}
+
+/// Numbers every heading at `start_level` or deeper.
+///
+/// A heading above `start_level` resets the numbering. A skipped level counts
+/// as `0`, so an `h3` right under an `h1` is numbered `1.0.1`.
+pub(crate) fn number_headings(tokens: Tokens, start_level: u8, format: &str) -> PyResult<Tokens> {
+    if !(1..=6).contains(&start_level) {
+        return Err(PyValueError::new_err(format!(
+            "start_level must be between 1 and 6, got {start_level}"
+        )));
+    }
+
+    let format = NumberFormat::parse(format)?;
+    let mut counters = [0usize; 6];
+
+    Ok(tokens
+        .into_iter()
+        .map(|token| match token {
+            Token::Heading {
+                level,
+                content,
+                span,
+                raw,
+                id,
+                parent_id,
+                ..
+            } if level >= start_level => {
+                let depth = (level - start_level) as usize;
+                counters[depth] += 1;
+                counters[depth + 1..].fill(0);
+
+                let number = format.write(&counters[..=depth]);
+
+                Token::Heading {
+                    level,
+                    content: format!("{number} {content}"),
+                    span,
+                    raw,
+                    number: Some(number),
+                    id,
+                    parent_id,
+                }
+            }
+            Token::Heading { .. } => {
+                counters.fill(0);
+                token
+            }
+            token => token,
+        })
+        .collect())
+}
+
+/// A heading in a document outline, with the headings nested under it.
+#[pyclass(frozen)]
+#[derive(Clone)]
+pub(crate) struct OutlineEntry {
+    #[pyo3(get)]
+    level: u8,
+    #[pyo3(get)]
+    content: String,
+    /// The section number from `number_headings`, if the tokens went through it.
+    #[pyo3(get)]
+    number: Option<String>,
+    /// The anchor slug, unique within the outline.
+    #[pyo3(get)]
+    slug: String,
+    #[pyo3(get)]
+    span: Span,
+    #[pyo3(get)]
+    children: Vec<OutlineEntry>,
+}
+
+#[pymethods]
+impl OutlineEntry {
+    #[pyo3(name = "__repr__")]
+    pub fn py_repr(&self) -> String {
+        format!(
+            "OutlineEntry({}, {:?}, {:?})",
+            self.level, self.content, self.slug
+        )
+    }
+}
+
+/// Builds the heading tree: every heading holds the deeper headings that
+/// follow it, up to the next heading of the same level or above.
+pub(crate) fn outline(tokens: &[Token], style: SlugStyle) -> Vec<OutlineEntry> {
+    let mut slugger = Slugger::new(style);
+    let mut roots = Vec::new();
+    let mut stack: Vec<OutlineEntry> = Vec::new();
+
+    fn close(stack: &mut Vec<OutlineEntry>, roots: &mut Vec<OutlineEntry>) {
+        let entry = stack.pop().unwrap();
+
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(entry),
+            None => roots.push(entry),
+        }
+    }
+
+    for token in parser::walk(tokens) {
+        if let Token::Heading {
+            level,
+            content,
+            span,
+            number,
+            ..
+        } = token
+        {
+            while stack.last().is_some_and(|top| top.level >= *level) {
+                close(&mut stack, &mut roots);
+            }
+
+            stack.push(OutlineEntry {
+                level: *level,
+                content: content.clone(),
+                number: number.clone(),
+                slug: slugger.slug(content),
+                span: *span,
+                children: Vec::new(),
+            });
+        }
+    }
+
+    while !stack.is_empty() {
+        close(&mut stack, &mut roots);
+    }
+
+    roots
+}
+
+/// Every heading in document order as `(level, content, slug)`, with the
+/// slugs `HtmlOptions(heading_ids=True)` gives them.
+pub(crate) fn heading_outline(tokens: &[Token], style: SlugStyle) -> Vec<(u8, String, String)> {
+    let mut slugger = Slugger::new(style);
+
+    parser::walk(tokens)
+        .into_iter()
+        .filter_map(|token| match token {
+            Token::Heading { level, content, .. } => {
+                Some((*level, content.clone(), slugger.slug(content)))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Every heading with the tokens under it: those up to the next heading of
+/// the same level or above, deeper headings and theirs included. Headings
+/// inside containers are not looked at, and tokens before the first heading
+/// are in no section.
+pub(crate) fn sections(tokens: &[Token]) -> Vec<(Token, Tokens)> {
+    tokens
+        .iter()
+        .enumerate()
+        .filter_map(|(i, token)| {
+            let end = section_end(tokens, i)?;
+
+            Some((token.clone(), tokens[i + 1..end].to_vec()))
+        })
+        .collect()
+}
+
+/// Where the section of the heading at `i` ends: at the next heading of the
+/// same level or above, or at the end of `tokens`. `None` if the token at `i`
+/// is not a heading.
+pub(crate) fn section_end(tokens: &[Token], i: usize) -> Option<usize> {
+    let Token::Heading { level, .. } = &tokens[i] else {
+        return None;
+    };
+    let end = tokens[i + 1..]
+        .iter()
+        .position(|token| matches!(token, Token::Heading { level: next, .. } if next <= level))
+        .map_or(tokens.len(), |end| i + 1 + end);
+
+    Some(end)
+}
+
+/// The index of the heading that titles a document: its first heading, if
+/// that is an `h1` or `loose` is set.
+pub(crate) fn title_index(tokens: &[Token], loose: bool) -> Option<usize> {
+    let i = tokens
+        .iter()
+        .position(|token| matches!(token, Token::Heading { .. }))?;
+
+    match tokens[i] {
+        Token::Heading { level, .. } if level == 1 || loose => Some(i),
+        _ => None,
+    }
+}
+
+/// The title of a document as plain text, from the heading [`title_index`]
+/// finds.
+pub(crate) fn title(tokens: &[Token], loose: bool) -> Option<String> {
+    match &tokens[title_index(tokens, loose)?] {
+        Token::Heading { content, .. } => Some(parser::plain_text(content)),
+        _ => None,
+    }
+}
@@ -0,0 +1,311 @@
+use crate::{
+    json::Json,
+    parser::{self, Metadata, Token},
+    slug::{SlugStyle, Slugger},
+};
+
+/// The version of the Pandoc types the output follows, that of Pandoc 3.
+const API_VERSION: [usize; 3] = [1, 23, 1];
+
+/// A Pandoc element: `{"t": tag, "c": content}`, or just `{"t": tag}`.
+fn element(tag: &'static str, content: Option<Json>) -> Json {
+    let mut fields = vec![("t", Json::str(tag))];
+    fields.extend(content.map(|content| ("c", content)));
+
+    Json::object(fields)
+}
+
+/// An `Attr`: an id, classes and key-value pairs.
+fn attr(id: &str, classes: &[String], pairs: Vec<(String, String)>) -> Json {
+    Json::Array(vec![
+        Json::str(id),
+        Json::Array(classes.iter().map(|class| Json::str(class)).collect()),
+        Json::Array(
+            pairs
+                .into_iter()
+                .map(|(key, value)| Json::Array(vec![Json::String(key), Json::String(value)]))
+                .collect(),
+        ),
+    ])
+}
+
+/// Splits text into `Str`s, with a `Space` for every run of spaces and a
+/// `SoftBreak` for one at a line break (an offset in `breaks`, counted from
+/// `offset`).
+fn words(text: &str, offset: usize, breaks: &[usize], inlines: &mut Vec<Json>) {
+    let mut word = String::new();
+    let mut space: Option<&'static str> = None;
+
+    for (i, c) in text.char_indices() {
+        if c == ' ' {
+            if breaks.contains(&(offset + i)) {
+                space = Some("SoftBreak");
+            } else {
+                space = space.or(Some("Space"));
+            }
+            continue;
+        }
+
+        if let Some(space) = space.take() {
+            if !word.is_empty() {
+                let text = parser::unescape(&std::mem::take(&mut word), true);
+                inlines.push(element("Str", Some(Json::String(text))));
+            }
+            inlines.push(element(space, None));
+        }
+        word.push(c);
+    }
+
+    if !word.is_empty() {
+        let text = parser::unescape(&word, true);
+        inlines.push(element("Str", Some(Json::String(text))));
+    }
+    inlines.extend(space.map(|space| element(space, None)));
+}
+
+/// A `Math` inline of `kind` (`InlineMath` or `DisplayMath`).
+fn math(kind: &'static str, content: &str) -> Json {
+    element(
+        "Math",
+        Some(Json::Array(vec![element(kind, None), Json::str(content)])),
+    )
+}
+
+/// The inlines of a paragraph, heading or definition: its words, links,
+/// images, raw spans, hard breaks and math. Like the HTML renderer, this skips a metadata that starts inside
+/// an earlier one, and abbreviations are left as text. A wiki link is a
+/// `Link` to its target titled `wikilink`, as pandoc's own reader has it.
+fn inlines(text: &str, metadata: &[Metadata], breaks: &[usize]) -> Json {
+    let mut metadata: Vec<&Metadata> = metadata
+        .iter()
+        .filter(|metadata| !matches!(metadata, Metadata::Abbreviation { .. }))
+        .collect();
+    metadata.sort_by_key(|metadata| metadata.location().0);
+
+    let mut inlines = Vec::new();
+    let mut cursor = 0;
+
+    for metadata in metadata {
+        let (start, end) = metadata.location();
+        if start < cursor || text.get(start..end).is_none() {
+            continue;
+        }
+        words(&text[cursor..start], cursor, breaks, &mut inlines);
+
+        let inline = match metadata {
+            Metadata::Link { label, url, .. } => {
+                let mut label_inlines = Vec::new();
+                words(label, 0, &[], &mut label_inlines);
+
+                element(
+                    "Link",
+                    Some(Json::Array(vec![
+                        attr("", &[], Vec::new()),
+                        Json::Array(label_inlines),
+                        Json::Array(vec![Json::str(url), Json::str("")]),
+                    ])),
+                )
+            }
+            Metadata::Image {
+                label,
+                url,
+                width,
+                height,
+                ..
+            } => {
+                let mut alt = Vec::new();
+                words(label, 0, &[], &mut alt);
+
+                let mut size = Vec::new();
+                size.extend(width.map(|width| ("width".to_string(), width.to_string())));
+                size.extend(height.map(|height| ("height".to_string(), height.to_string())));
+
+                element(
+                    "Image",
+                    Some(Json::Array(vec![
+                        attr("", &[], size),
+                        Json::Array(alt),
+                        Json::Array(vec![Json::str(url), Json::str("")]),
+                    ])),
+                )
+            }
+            Metadata::Raw { .. } => element(
+                "RawInline",
+                Some(Json::Array(vec![
+                    Json::str("html"),
+                    Json::str(&text[start..end]),
+                ])),
+            ),
+            Metadata::LineBreak { .. } => element("LineBreak", None),
+            Metadata::WikiLink { target, alias, .. } => {
+                let mut label = Vec::new();
+                words(alias.as_ref().unwrap_or(target), 0, &[], &mut label);
+
+                element(
+                    "Link",
+                    Some(Json::Array(vec![
+                        attr("", &[], Vec::new()),
+                        Json::Array(label),
+                        Json::Array(vec![Json::str(target), Json::str("wikilink")]),
+                    ])),
+                )
+            }
+            Metadata::InlineMath { content, .. } => math("InlineMath", content),
+            Metadata::Abbreviation { .. } => unreachable!(),
+        };
+        inlines.push(inline);
+
+        cursor = end;
+    }
+    words(&text[cursor..], cursor, breaks, &mut inlines);
+
+    Json::Array(inlines)
+}
+
+fn blocks(tokens: &[Token], slugger: &mut Slugger) -> Vec<Json> {
+    tokens
+        .iter()
+        .map(|token| match token {
+            Token::Heading { level, content, .. } => {
+                let metadata = parser::inline_metadata(content, &Default::default());
+
+                element(
+                    "Header",
+                    Some(Json::Array(vec![
+                        Json::Number(*level as usize),
+                        attr(&slugger.slug(content), &[], Vec::new()),
+                        inlines(content, &metadata, &[]),
+                    ])),
+                )
+            }
+            Token::Paragraph(text, metadata, _, _, breaks, ..) => {
+                element("Para", Some(inlines(text, metadata, breaks)))
+            }
+            Token::HorizontalRule { .. } => element("HorizontalRule", None),
+            Token::DefinitionList { items, .. } => {
+                let items = items
+                    .iter()
+                    .map(|(term, definitions)| {
+                        let term_metadata = parser::inline_metadata(term, &Default::default());
+                        let definitions = definitions
+                            .iter()
+                            .map(|(text, metadata)| Json::Array(definition(text, metadata)))
+                            .collect();
+
+                        Json::Array(vec![
+                            inlines(term, &term_metadata, &[]),
+                            Json::Array(definitions),
+                        ])
+                    })
+                    .collect();
+
+                element("DefinitionList", Some(Json::Array(items)))
+            }
+            Token::Abbreviation {
+                abbr, expansion, ..
+            } => element(
+                "RawBlock",
+                Some(Json::Array(vec![
+                    Json::str("markdown"),
+                    Json::String(format!("*[{abbr}]: {expansion}")),
+                ])),
+            ),
+            Token::Code {
+                language,
+                content,
+                flags,
+                attrs,
+                ..
+            } => {
+                let classes: Vec<String> = std::iter::once(language)
+                    .filter(|language| !language.is_empty())
+                    .chain(flags)
+                    .cloned()
+                    .collect();
+                let pairs = attrs
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect();
+                let content = content.strip_suffix('\n').unwrap_or(content);
+
+                element(
+                    "CodeBlock",
+                    Some(Json::Array(vec![
+                        attr("", &classes, pairs),
+                        Json::str(content),
+                    ])),
+                )
+            }
+            Token::Container {
+                name,
+                attrs,
+                content,
+                ..
+            } => {
+                let title = (!attrs.is_empty()).then(|| ("title".to_string(), attrs.clone()));
+
+                element(
+                    "Div",
+                    Some(Json::Array(vec![
+                        attr("", std::slice::from_ref(name), title.into_iter().collect()),
+                        Json::Array(blocks(content, slugger)),
+                    ])),
+                )
+            }
+            Token::MathBlock { content, .. } => element(
+                "Para",
+                Some(Json::Array(vec![math("DisplayMath", content)])),
+            ),
+        })
+        .collect()
+}
+
+/// The blocks of a definition: a `Para` for each of its paragraphs.
+fn definition(text: &str, metadata: &[Metadata]) -> Vec<Json> {
+    let mut paragraphs = Vec::new();
+    let mut offset = 0;
+
+    for paragraph in text.split("\n\n") {
+        let metadata: Vec<Metadata> = metadata
+            .iter()
+            .filter(|metadata| {
+                let (start, end) = metadata.location();
+                offset <= start && end <= offset + paragraph.len()
+            })
+            .cloned()
+            .map(|mut metadata| {
+                let location = metadata.location_mut();
+                *location = (location.0 - offset, location.1 - offset);
+                metadata
+            })
+            .collect();
+        offset += paragraph.len() + 2;
+
+        paragraphs.push(element("Para", Some(inlines(paragraph, &metadata, &[]))));
+    }
+
+    paragraphs
+}
+
+/// Converts tokens to the JSON AST Pandoc reads with `--from json`.
+///
+/// Headings get the slugs `HtmlOptions(heading_ids=True)` gives them as ids,
+/// code blocks their language and flags as classes and their attributes as
+/// pairs, and containers become `Div`s with their name as the class and the
+/// rest of their opening line as a `title`. Pandoc has no abbreviation
+/// definitions, so those are kept as raw Markdown blocks.
+pub(crate) fn tokens_to_pandoc(tokens: &[Token], style: SlugStyle) -> String {
+    let mut slugger = Slugger::new(style);
+    let document = Json::object([
+        (
+            "pandoc-api-version",
+            Json::Array(API_VERSION.iter().map(|&n| Json::Number(n)).collect()),
+        ),
+        ("meta", Json::Object(Vec::new())),
+        ("blocks", Json::Array(blocks(tokens, &mut slugger))),
+    ]);
+
+    let mut json = String::new();
+    document.write(&mut json);
+    json
+}
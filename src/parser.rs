@@ -1,6 +1,24 @@
+use std::{borrow::Cow, collections::BTreeMap};
+
 use lazy_static::lazy_static;
-use pyo3::prelude::*;
-use regex::Regex;
+use pyo3::{exceptions::PyAttributeError, prelude::*};
+use regex::{Captures, Regex};
+
+use crate::{
+    abbr::{self, Abbreviator},
+    anchors,
+    element::Node,
+    escape::{
+        decode_reference, escape_attr, escape_prose, escape_text, safe_url, sanitize_class,
+        sanitize_id,
+    },
+    markdown,
+    metrics::Phases,
+    options::{HtmlOptions, ParseOptions},
+    outline, sanitize,
+    slug::{Slugger, EMPTY_SLUG},
+    strict,
+};
 
 macro_rules! make_regex {
     ($id:ident, $re:literal) => {
@@ -18,17 +36,183 @@ macro_rules! make_regex {
 }
 
 make_regex!(HEADING_RE, r"(?m)^(#{1,6})\s+(.+)$");
-make_regex!(FENCE_RE, r"(?m)^```([0-9a-zA-Z+-_]*)\s*$");
-make_regex!(LINK_RE, r"(?m)\[([^\]]+)\]\(([^\)]+)\)");
-make_regex!(IMAGE_RE, r"(?m)\!\[([^\]]+)\]\(([^\)]+)\)");
+make_regex!(WIDE_HEADING_RE, r"^([#＃]{1,6})\s+(.+)$");
+make_regex!(
+    CJK_HEADING_RE,
+    r"^([#＃]{1,6})([\p{Han}\p{Hiragana}\p{Katakana}\p{Hangul}][^#＃]*)$"
+);
+make_regex!(
+    ASCIIDOC_HEADING_RE,
+    r"^(={1,6})[ \t]+(.*?)(?:[ \t]+=+)?[ \t]*$"
+);
+make_regex!(FENCE_RE, r"(?m)^( {0,3})(?:(`{3,})([^`]*)|(~{3,})(.*))$");
+make_regex!(
+    CONTAINER_RE,
+    r"^ {0,3}(:{3,})[ \t]*([A-Za-z][A-Za-z0-9_-]*)[ \t]*(.*?)[ \t]*$"
+);
+make_regex!(ABBREVIATION_RE, r"^ {0,3}\*\[([^\]]+)\]:[ \t]*(.*?)\s*$");
+// A destination is either `<...>`, which may hold spaces and parentheses, or
+// text whose parentheses are balanced, one level deep: `Foo_(bar)`.
+make_regex!(
+    LINK_RE,
+    r"(?m)\[([^\]]*)\]\((?:<([^<>\n]*)>|((?:[^()]|\([^()]*\))+))\)"
+);
+make_regex!(
+    IMAGE_RE,
+    r"(?m)\!\[([^\]]*)\]\((?:<([^<>\n]*)>|((?:[^()]|\([^()]*\))+))\)"
+);
+make_regex!(
+    AUTOLINK_RE,
+    r"<(?:([a-zA-Z][a-zA-Z0-9+.-]{1,31}:[^\s<>]*)|([a-zA-Z0-9.!#$%&'*+/=?^_`{|}~-]+@[a-zA-Z0-9](?:[a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?(?:\.[a-zA-Z0-9](?:[a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?)*))>"
+);
+make_regex!(
+    EMAIL_RE,
+    r"[a-zA-Z0-9._+-]+@[a-zA-Z0-9_-]+(?:\.[a-zA-Z0-9_-]+)+"
+);
+make_regex!(IMAGE_SIZE_RE, r"^(.*?)\s+=(\d*)x(\d*)$");
+make_regex!(WIKI_LINK_RE, r"\[\[([^\[\]|\n]+)(?:\|([^\[\]\n]+))?\]\]");
+
+/// A byte range in the source text.
+pub(crate) type Span = (usize, usize);
 
-#[pyclass]
+/// The text of a definition in a definition list, and its metadata.
+pub(crate) type Definition = (String, Vec<Metadata>);
+
+/// A block-level token.
+///
+/// Every token records the byte range of the source it was parsed from as
+/// its `span`, and the source text itself as `raw` when parsed with
+/// `ParseOptions(keep_raw=True)`.
+///
+/// `id` numbers the tokens of a parse from 0 in document order, the tokens
+/// inside a container right after it, and `parent_id` is the id of the
+/// container a token is in, or `None` at the top level. Transforms keep the
+/// ids of the tokens they pass on. A token built by hand has id 0 and no
+/// parent unless given them.
+#[pyclass(frozen)]
 #[derive(Clone)]
 pub(crate) enum Token {
-    Heading { level: u8, content: String },
-    Paragraph(String, Vec<Metadata>),
-    HorizontalRule(),
-    Code { language: String, content: String },
+    /// A heading.
+    ///
+    /// `number` is the section number assigned by `number_headings`, if any.
+    #[pyo3(constructor = (level, content, span = (0, 0), raw = None, number = None, id = 0, parent_id = None))]
+    Heading {
+        level: u8,
+        content: String,
+        span: Span,
+        raw: Option<String>,
+        number: Option<String>,
+        id: usize,
+        parent_id: Option<usize>,
+    },
+    /// A paragraph: its text, metadata, span, raw source, the offsets in the
+    /// text of the spaces that joined its lines, and its id and parent id.
+    ///
+    /// `parse` gives the metadata sorted by where it starts in the text, as
+    /// [`sort_metadata`] does; so are the metadata of each definition.
+    #[pyo3(constructor = (_0, _1, _2 = (0, 0), _3 = None, _4 = Vec::new(), _5 = 0, _6 = None))]
+    Paragraph(
+        String,
+        Vec<Metadata>,
+        Span,
+        Option<String>,
+        Vec<usize>,
+        usize,
+        Option<usize>,
+    ),
+    /// A thematic break. `marker` is the character it was written with (`-`,
+    /// `*` or `_`) and `length` how many of them there were.
+    #[pyo3(constructor = (span = (0, 0), raw = None, marker = '-', length = 3, id = 0, parent_id = None))]
+    HorizontalRule {
+        span: Span,
+        raw: Option<String>,
+        marker: char,
+        length: usize,
+        id: usize,
+        parent_id: Option<usize>,
+    },
+    /// A definition list, parsed with `ParseOptions(definition_lists=True)`.
+    ///
+    /// Every item is a term and its definitions, each definition with the
+    /// links and images found in it. The paragraphs of a definition that has
+    /// several are separated by a blank line.
+    #[pyo3(constructor = (items, span = (0, 0), raw = None, id = 0, parent_id = None))]
+    DefinitionList {
+        items: Vec<(String, Vec<Definition>)>,
+        span: Span,
+        raw: Option<String>,
+        id: usize,
+        parent_id: Option<usize>,
+    },
+    /// An abbreviation definition (`*[HTML]: HyperText Markup Language`),
+    /// parsed with `ParseOptions(abbreviations=True)`.
+    ///
+    /// It renders to nothing itself; instead every whole-word use of `abbr` in
+    /// the rendered document is wrapped in `<abbr>`.
+    #[pyo3(constructor = (abbr, expansion, span = (0, 0), raw = None, id = 0, parent_id = None))]
+    Abbreviation {
+        abbr: String,
+        expansion: String,
+        span: Span,
+        raw: Option<String>,
+        id: usize,
+        parent_id: Option<usize>,
+    },
+    /// A code block.
+    ///
+    /// `fence_char`, `fence_length` and `indent` describe the opening fence as
+    /// written, so the block can be re-emitted faithfully. Indented code blocks
+    /// have no fence: `fence_char` is `None`, `fence_length` is `0` and `indent`
+    /// is `4`. The lines of an indented fence lose the indentation they all
+    /// share, blank lines aside. A block built without a fence is written
+    /// back to Markdown with backticks.
+    ///
+    /// `info` is everything after the opening fence, trimmed. It is split on
+    /// spaces and commas, outside quotes, into `language` (the first word),
+    /// `flags` (`no_run` in ```` ```rust,no_run ````) and `attrs`
+    /// (`exec="true"`, with the quotes removed).
+    #[pyo3(constructor = (language, content, fence_char = None, fence_length = 0, indent = 0, span = (0, 0), raw = None, info = String::new(), flags = Vec::new(), attrs = BTreeMap::new(), id = 0, parent_id = None))]
+    Code {
+        language: String,
+        content: String,
+        fence_char: Option<char>,
+        fence_length: usize,
+        indent: usize,
+        span: Span,
+        raw: Option<String>,
+        info: String,
+        flags: Vec<String>,
+        attrs: BTreeMap<String, String>,
+        id: usize,
+        parent_id: Option<usize>,
+    },
+    /// A custom container (`:::note` ... `:::`), parsed with
+    /// `ParseOptions(containers=True)`.
+    ///
+    /// `attrs` is whatever follows the name on the opening line, trimmed, and
+    /// `content` the tokens of the lines up to the closing `:::`. Containers
+    /// nest, and one that is never closed runs to the end of the input.
+    #[pyo3(constructor = (name, attrs, content, span = (0, 0), raw = None, id = 0, parent_id = None))]
+    Container {
+        name: String,
+        attrs: String,
+        content: Tokens,
+        span: Span,
+        raw: Option<String>,
+        id: usize,
+        parent_id: Option<usize>,
+    },
+    /// A block of display math (`$$` ... `$$`), parsed with
+    /// `ParseOptions(math=True)`. `content` is the TeX between the `$$`s,
+    /// trimmed. One that is never closed runs to the end of the input.
+    #[pyo3(constructor = (content, span = (0, 0), raw = None, id = 0, parent_id = None))]
+    MathBlock {
+        content: String,
+        span: Span,
+        raw: Option<String>,
+        id: usize,
+        parent_id: Option<usize>,
+    },
 }
 
 #[pymethods]
@@ -36,26 +220,335 @@ impl Token {
     #[pyo3(name = "__repr__")]
     pub fn py_repr(&self) -> String {
         match self {
-            Self::Heading { level, content } => format!("Heading({level}, {content:?})"),
-            Self::Paragraph(content, meta) => format!("Paragraph({content:?}, {meta:?})"),
-            Self::HorizontalRule() => "HorizontalRule".to_string(),
-            Self::Code { language, content } => format!("Code({language:?}, {content:?})"),
+            Self::Heading { level, content, .. } => format!("Heading({level}, {content:?})"),
+            Self::Paragraph(content, meta, ..) => format!("Paragraph({content:?}, {meta:?})"),
+            Self::HorizontalRule { .. } => "HorizontalRule".to_string(),
+            Self::DefinitionList { items, .. } => {
+                let items: Vec<_> = items
+                    .iter()
+                    .map(|(term, definitions)| {
+                        (
+                            term,
+                            definitions.iter().map(|(text, _)| text).collect::<Vec<_>>(),
+                        )
+                    })
+                    .collect();
+
+                format!("DefinitionList({items:?})")
+            }
+            Self::Abbreviation {
+                abbr, expansion, ..
+            } => format!("Abbreviation({abbr:?}, {expansion:?})"),
+            Self::Code {
+                language, content, ..
+            } => format!("Code({language:?}, {content:?})"),
+            Self::Container { name, content, .. } => {
+                let content: Vec<_> = content.iter().map(Token::py_repr).collect();
+
+                format!("Container({name:?}, [{}])", content.join(", "))
+            }
+            Self::MathBlock { content, .. } => format!("MathBlock({content:?})"),
+        }
+    }
+
+    /// The length in characters of the text of this token: the content of a
+    /// heading, code block or math block, the text of a paragraph, the terms and
+    /// definitions of a definition list, the abbreviation and its expansion,
+    /// or the lengths of the tokens in a container added up. A horizontal
+    /// rule has no text, and a length of 0.
+    fn __len__(&self) -> usize {
+        match self {
+            Self::Heading { content, .. }
+            | Self::Code { content, .. }
+            | Self::MathBlock { content, .. } => content.chars().count(),
+            Self::Paragraph(text, ..) => text.chars().count(),
+            Self::HorizontalRule { .. } => 0,
+            Self::DefinitionList { items, .. } => items
+                .iter()
+                .map(|(term, definitions)| {
+                    term.chars().count()
+                        + definitions
+                            .iter()
+                            .map(|(text, _)| text.chars().count())
+                            .sum::<usize>()
+                })
+                .sum(),
+            Self::Abbreviation {
+                abbr, expansion, ..
+            } => abbr.chars().count() + expansion.chars().count(),
+            Self::Container { content, .. } => content.iter().map(Token::__len__).sum(),
+        }
+    }
+
+    #[getter(span)]
+    fn py_span(&self) -> Span {
+        self.span()
+    }
+
+    #[getter(raw)]
+    fn py_raw(&self) -> Option<String> {
+        self.raw().map(str::to_string)
+    }
+
+    #[getter(kind)]
+    fn py_kind(&self) -> &'static str {
+        self.kind()
+    }
+
+    #[getter(id)]
+    fn py_id(&self) -> usize {
+        self.id()
+    }
+
+    #[getter(parent_id)]
+    fn py_parent_id(&self) -> Option<usize> {
+        self.parent_id()
+    }
+
+    /// The metadata of a paragraph. Other tokens have none.
+    #[getter(metadata)]
+    fn py_metadata(&self) -> PyResult<Vec<Metadata>> {
+        match self {
+            Self::Paragraph(_, metadata, ..) => Ok(metadata.clone()),
+            _ => Err(PyAttributeError::new_err(format!(
+                "{} tokens have no metadata",
+                self.kind()
+            ))),
+        }
+    }
+}
+
+/// Every token of `tokens` in document order, with the tokens inside a
+/// container right after it.
+pub(crate) fn walk(tokens: &[Token]) -> Vec<&Token> {
+    let mut walked = Vec::with_capacity(tokens.len());
+
+    for token in tokens {
+        walked.push(token);
+        if let Token::Container { content, .. } = token {
+            walked.extend(walk(content));
+        }
+    }
+
+    walked
+}
+
+/// Whether `line` opens a container, as it would outside of any other.
+pub(crate) fn opens_container(line: &str, options: &ParseOptions) -> bool {
+    let line = expand_indent(line.trim_start_matches(is_zero_width), options.tab_width);
+
+    container(&line, options).is_some()
+}
+
+/// Gives `tokens`, and the tokens in them, ids in document order from `next`
+/// on, as [`walk`] visits them, with `parent` as the parent of `tokens`.
+pub(crate) fn number_tokens(tokens: &mut [Token], next: &mut usize, parent: Option<usize>) {
+    for token in tokens {
+        let id = *next;
+        *next += 1;
+        token.set_ids(id, parent);
+
+        if let Token::Container { content, .. } = token {
+            number_tokens(content, next, Some(id));
+        }
+    }
+}
+
+/// Every value of [`Token::kind`].
+pub(crate) const KINDS: &[&str] = &[
+    "heading",
+    "paragraph",
+    "horizontal_rule",
+    "definition_list",
+    "abbreviation",
+    "code",
+    "container",
+    "math_block",
+];
+
+impl Token {
+    /// The name of the variant, in snake case.
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Self::Heading { .. } => "heading",
+            Self::Paragraph(..) => "paragraph",
+            Self::HorizontalRule { .. } => "horizontal_rule",
+            Self::DefinitionList { .. } => "definition_list",
+            Self::Abbreviation { .. } => "abbreviation",
+            Self::Code { .. } => "code",
+            Self::Container { .. } => "container",
+            Self::MathBlock { .. } => "math_block",
+        }
+    }
+
+    /// The byte range of the source this token was parsed from.
+    pub(crate) fn span(&self) -> Span {
+        match self {
+            Self::Heading { span, .. }
+            | Self::Paragraph(_, _, span, ..)
+            | Self::HorizontalRule { span, .. }
+            | Self::DefinitionList { span, .. }
+            | Self::Abbreviation { span, .. }
+            | Self::Code { span, .. }
+            | Self::Container { span, .. }
+            | Self::MathBlock { span, .. } => *span,
+        }
+    }
+
+    fn span_mut(&mut self) -> &mut Span {
+        match self {
+            Self::Heading { span, .. }
+            | Self::Paragraph(_, _, span, ..)
+            | Self::HorizontalRule { span, .. }
+            | Self::DefinitionList { span, .. }
+            | Self::Abbreviation { span, .. }
+            | Self::Code { span, .. }
+            | Self::Container { span, .. }
+            | Self::MathBlock { span, .. } => span,
+        }
+    }
+
+    /// The id of this token in the parse it came from.
+    pub(crate) fn id(&self) -> usize {
+        match self {
+            Self::Heading { id, .. }
+            | Self::Paragraph(_, _, _, _, _, id, _)
+            | Self::HorizontalRule { id, .. }
+            | Self::DefinitionList { id, .. }
+            | Self::Abbreviation { id, .. }
+            | Self::Code { id, .. }
+            | Self::Container { id, .. }
+            | Self::MathBlock { id, .. } => *id,
+        }
+    }
+
+    /// The id of the container this token is in, if it is in one.
+    pub(crate) fn parent_id(&self) -> Option<usize> {
+        match self {
+            Self::Heading { parent_id, .. }
+            | Self::Paragraph(_, _, _, _, _, _, parent_id)
+            | Self::HorizontalRule { parent_id, .. }
+            | Self::DefinitionList { parent_id, .. }
+            | Self::Abbreviation { parent_id, .. }
+            | Self::Code { parent_id, .. }
+            | Self::Container { parent_id, .. }
+            | Self::MathBlock { parent_id, .. } => *parent_id,
+        }
+    }
+
+    fn set_ids(&mut self, to: usize, parent: Option<usize>) {
+        let (id, parent_id) = match self {
+            Self::Heading { id, parent_id, .. }
+            | Self::Paragraph(_, _, _, _, _, id, parent_id)
+            | Self::HorizontalRule { id, parent_id, .. }
+            | Self::DefinitionList { id, parent_id, .. }
+            | Self::Abbreviation { id, parent_id, .. }
+            | Self::Code { id, parent_id, .. }
+            | Self::Container { id, parent_id, .. }
+            | Self::MathBlock { id, parent_id, .. } => (id, parent_id),
+        };
+
+        *id = to;
+        *parent_id = parent;
+    }
+
+    /// The source text of this token, if it was kept while parsing.
+    pub(crate) fn raw(&self) -> Option<&str> {
+        match self {
+            Self::Heading { raw, .. }
+            | Self::Paragraph(_, _, _, raw, ..)
+            | Self::HorizontalRule { raw, .. }
+            | Self::DefinitionList { raw, .. }
+            | Self::Abbreviation { raw, .. }
+            | Self::Code { raw, .. }
+            | Self::Container { raw, .. }
+            | Self::MathBlock { raw, .. } => raw.as_deref(),
+        }
+    }
+
+    /// Moves the span of this token, and those of the tokens and links inside
+    /// it, to `map(offset)`.
+    pub(crate) fn map_spans(&mut self, map: &impl Fn(usize) -> usize) {
+        let span = self.span_mut();
+        *span = (map(span.0), map(span.1));
+
+        match self {
+            Self::Paragraph(_, metadata, ..) => metadata
+                .iter_mut()
+                .for_each(|metadata| metadata.map_span(map)),
+            Self::DefinitionList { items, .. } => items
+                .iter_mut()
+                .flat_map(|(_, definitions)| definitions)
+                .flat_map(|(_, metadata)| metadata)
+                .for_each(|metadata| metadata.map_span(map)),
+            Self::Container { content, .. } => {
+                content.iter_mut().for_each(|token| token.map_spans(map))
+            }
+            _ => {}
         }
     }
 }
 
-#[pyclass]
+#[pyclass(frozen)]
 #[derive(Clone)]
 pub(crate) enum Metadata {
+    /// A link. `span` is where it is in the source, with
+    /// `ParseOptions(source_spans=True)`.
+    #[pyo3(constructor = (location, label, url, span = None))]
     Link {
         location: (usize, usize),
         label: String,
         url: String,
+        span: Option<Span>,
     },
+    /// An image. `width` and `height` come from a `=WxH` suffix on the
+    /// destination (`![alt](img.png =640x480)`), either of which may be left
+    /// out, and are not part of `url`. `span` is as for a link.
+    #[pyo3(constructor = (location, label, url, width = None, height = None, span = None))]
     Image {
         location: (usize, usize),
         label: String,
         url: String,
+        width: Option<u32>,
+        height: Option<u32>,
+        span: Option<Span>,
+    },
+    /// A use of an abbreviation defined in the document, found when parsing
+    /// with `ParseOptions(abbreviations=True)`.
+    Abbreviation {
+        location: (usize, usize),
+        short: String,
+        title: String,
+    },
+    /// Text between a pair of `ParseOptions.preserve_delimiters`, delimiters
+    /// included, which is rendered as written. `closed` is false for one that
+    /// runs to the end of the paragraph without its closing delimiter, which
+    /// is escaped like any other text.
+    #[pyo3(constructor = (location, closed = true))]
+    Raw {
+        location: (usize, usize),
+        closed: bool,
+    },
+    /// A hard line break, from a line ending in a backslash. `location` is the
+    /// space that joined that line to the next; the backslash is not part of
+    /// the text.
+    #[pyo3(constructor = (location))]
+    LineBreak { location: (usize, usize) },
+    /// Inline math (`$x^2$`), found with `ParseOptions(math=True)`. `content`
+    /// is the TeX between the dollar signs. Nothing else is found inside it.
+    InlineMath {
+        location: (usize, usize),
+        content: String,
+    },
+    /// An Obsidian wiki link (`[[Page Name]]`, `[[Page Name|text]]`), found
+    /// with `ParseOptions(wiki_links=True)`. `target` is the page, with a
+    /// `#heading` if it has one, and `alias` the text after the `|`, both
+    /// trimmed.
+    #[pyo3(constructor = (location, target, alias = None))]
+    WikiLink {
+        location: (usize, usize),
+        target: String,
+        alias: Option<String>,
     },
 }
 
@@ -68,12 +561,56 @@ impl Metadata {
                 location,
                 label,
                 url,
+                ..
             } => format!("Link({location:?}, {label:?}, {url:?})"),
             Self::Image {
                 location,
                 label,
                 url,
+                width: None,
+                height: None,
+                ..
             } => format!("Image({location:?}, {label:?}, {url:?})"),
+            Self::Image {
+                location,
+                label,
+                url,
+                width,
+                height,
+                ..
+            } => {
+                let size = |size: &Option<u32>| size.map_or("None".to_string(), |n| n.to_string());
+
+                format!(
+                    "Image({location:?}, {label:?}, {url:?}, {}, {})",
+                    size(width),
+                    size(height)
+                )
+            }
+            Self::Abbreviation {
+                location,
+                short,
+                title,
+            } => format!("Abbreviation({location:?}, {short:?}, {title:?})"),
+            Self::Raw { location, closed } => {
+                let closed = if *closed { "True" } else { "False" };
+
+                format!("Raw({location:?}, {closed})")
+            }
+            Self::LineBreak { location } => format!("LineBreak({location:?})"),
+            Self::InlineMath { location, content } => {
+                format!("InlineMath({location:?}, {content:?})")
+            }
+            Self::WikiLink {
+                location,
+                target,
+                alias: None,
+            } => format!("WikiLink({location:?}, {target:?})"),
+            Self::WikiLink {
+                location,
+                target,
+                alias: Some(alias),
+            } => format!("WikiLink({location:?}, {target:?}, {alias:?})"),
         }
     }
 }
@@ -85,8 +622,183 @@ impl std::fmt::Debug for Metadata {
 }
 
 impl Metadata {
+    /// Parses and returns the autolinks (`<https://...>`, `<me@example.com>`),
+    /// if any. An email address links to `mailto:` it.
+    pub(crate) fn autolinks(paragraph: &str) -> Vec<Self> {
+        AUTOLINK_RE
+            .captures_iter(paragraph)
+            .map(|c| {
+                let range = c.get(0).unwrap().range();
+
+                let (label, url) = match c.get(1) {
+                    Some(uri) => (uri.as_str().to_string(), uri.as_str().to_string()),
+                    None => (c[2].to_string(), format!("mailto:{}", &c[2])),
+                };
+
+                Metadata::Link {
+                    location: (range.start, range.end),
+                    label,
+                    url,
+                    span: None,
+                }
+            })
+            .collect()
+    }
+
+    /// Parses and returns the inline math, `$` to `$`, outside code spans.
+    ///
+    /// A `$` that is escaped or next to another `$` delimits nothing. One
+    /// opens math if it is followed by something other than whitespace, and
+    /// closes it if it comes after something other than whitespace and is
+    /// not followed by a digit, which keeps `$5 or $10` text.
+    pub(crate) fn math(paragraph: &str) -> Vec<Self> {
+        let bytes = paragraph.as_bytes();
+        let code = markdown::code_spans(paragraph);
+        let dollar = |i: usize| {
+            bytes[i] == b'$'
+                && bytes[..i].iter().rev().take_while(|&&b| b == b'\\').count() % 2 == 0
+                && (i == 0 || bytes[i - 1] != b'$')
+                && bytes.get(i + 1) != Some(&b'$')
+                && !code.iter().any(|&(start, end)| start <= i && i < end)
+        };
+
+        let mut math = Vec::new();
+        let mut i = 0;
+
+        while i < bytes.len() {
+            if !dollar(i) || !paragraph[i + 1..].starts_with(|c: char| !c.is_whitespace()) {
+                i += 1;
+                continue;
+            }
+
+            // Whether a `$` closes does not depend on where the math opened,
+            // so without a close here there is none further on either
+            let Some(close) = (i + 2..bytes.len()).find(|&j| {
+                dollar(j)
+                    && !paragraph[..j].ends_with(char::is_whitespace)
+                    && !bytes.get(j + 1).is_some_and(u8::is_ascii_digit)
+            }) else {
+                break;
+            };
+
+            math.push(Metadata::InlineMath {
+                location: (i, close + 1),
+                content: paragraph[i + 1..close].to_string(),
+            });
+            i = close + 1;
+        }
+
+        math
+    }
+
+    /// Parses and returns the wiki links, outside code spans. An embed,
+    /// `![[image.png]]`, is left as text, and so is a link to nothing.
+    pub(crate) fn wiki_links(paragraph: &str) -> Vec<Self> {
+        let code = markdown::code_spans(paragraph);
+
+        WIKI_LINK_RE
+            .captures_iter(paragraph)
+            .filter_map(|c| {
+                let range = c.get(0).unwrap().range();
+                let target = c[1].trim();
+
+                if target.is_empty()
+                    || paragraph[..range.start].ends_with('!')
+                    || code
+                        .iter()
+                        .any(|&(s, e)| s <= range.start && range.start < e)
+                {
+                    return None;
+                }
+
+                Some(Metadata::WikiLink {
+                    location: (range.start, range.end),
+                    target: target.to_string(),
+                    alias: c
+                        .get(2)
+                        .map(|alias| alias.as_str().trim())
+                        .filter(|alias| !alias.is_empty())
+                        .map(str::to_string),
+                })
+            })
+            .collect()
+    }
+
+    /// Parses and returns the bare email addresses, as GFM autolinks them.
+    pub(crate) fn emails(paragraph: &str) -> Vec<Self> {
+        EMAIL_RE
+            .find_iter(paragraph)
+            .filter(|m| {
+                let before = paragraph[..m.start()].chars().next_back();
+                let after = paragraph[m.end()..].chars().next();
+
+                !before.is_some_and(|c| c.is_alphanumeric() || c == '/' || c == ':')
+                    && after != Some('@')
+                    && !m.as_str().ends_with(['-', '_'])
+            })
+            .map(|m| Metadata::Link {
+                location: (m.start(), m.end()),
+                label: m.as_str().to_string(),
+                url: format!("mailto:{}", m.as_str()),
+                span: None,
+            })
+            .collect()
+    }
+
+    /// The name of the variant, in snake case.
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Self::Link { .. } => "link",
+            Self::Image { .. } => "image",
+            Self::Abbreviation { .. } => "abbreviation",
+            Self::Raw { .. } => "raw",
+            Self::LineBreak { .. } => "line_break",
+            Self::InlineMath { .. } => "inline_math",
+            Self::WikiLink { .. } => "wiki_link",
+        }
+    }
+
+    /// The range of the paragraph this metadata was parsed from.
+    pub(crate) fn location(&self) -> (usize, usize) {
+        match self {
+            Self::Link { location, .. }
+            | Self::Image { location, .. }
+            | Self::Abbreviation { location, .. }
+            | Self::Raw { location, .. }
+            | Self::LineBreak { location }
+            | Self::InlineMath { location, .. }
+            | Self::WikiLink { location, .. } => *location,
+        }
+    }
+
+    /// Moves the source span of a link or image, if it has one, to
+    /// `map(offset)`.
+    fn map_span(&mut self, map: &impl Fn(usize) -> usize) {
+        if let Self::Link {
+            span: Some(span), ..
+        }
+        | Self::Image {
+            span: Some(span), ..
+        } = self
+        {
+            *span = (map(span.0), map(span.1));
+        }
+    }
+
+    pub(crate) fn location_mut(&mut self) -> &mut (usize, usize) {
+        match self {
+            Self::Link { location, .. }
+            | Self::Image { location, .. }
+            | Self::Abbreviation { location, .. }
+            | Self::Raw { location, .. }
+            | Self::LineBreak { location }
+            | Self::InlineMath { location, .. }
+            | Self::WikiLink { location, .. } => location,
+        }
+    }
+
     /// Parses and returns the link metadata(s), if any.
-    pub(crate) fn links(paragraph: &String) -> Vec<Self> {
+    pub(crate) fn links(paragraph: &str) -> Vec<Self> {
         let lre = LINK_RE.captures_iter(paragraph);
 
         lre.map(|c| {
@@ -96,17 +808,20 @@ impl Metadata {
                 (range.start, range.end)
             };
 
+            let url = c.get(2).or(c.get(3)).unwrap().as_str();
+
             Metadata::Link {
                 location: (start, end),
                 label: c[1].to_string(),
-                url: c[2].to_string(),
+                url: url.to_string(),
+                span: None,
             }
         })
         .collect::<Vec<_>>()
     }
 
     /// Parses and returns the image metadata(s), if any.
-    pub(crate) fn images(paragraph: &String) -> Vec<Self> {
+    pub(crate) fn images(paragraph: &str) -> Vec<Self> {
         let ire = IMAGE_RE.captures_iter(paragraph);
 
         ire.map(|c| {
@@ -116,10 +831,25 @@ impl Metadata {
                 (range.start, range.end)
             };
 
+            let (url, width, height) = match c.get(2) {
+                Some(url) => (url.as_str().to_string(), None, None),
+                None => match IMAGE_SIZE_RE.captures(&c[3]) {
+                    Some(size) if !size[2].is_empty() || !size[3].is_empty() => (
+                        size[1].to_string(),
+                        size[2].parse().ok(),
+                        size[3].parse().ok(),
+                    ),
+                    _ => (c[3].to_string(), None, None),
+                },
+            };
+
             Metadata::Image {
                 location: (start, end),
                 label: c[1].to_string(),
-                url: c[2].to_string(),
+                url,
+                width,
+                height,
+                span: None,
             }
         })
         .collect::<Vec<_>>()
@@ -128,6 +858,38 @@ impl Metadata {
 
 pub(crate) type Tokens = Vec<Token>;
 
+/// Splits the input on `\n`, the same way `str::split` would.
+fn split_lines(input: &str) -> Vec<&str> {
+    let mut lines = Vec::new();
+    let mut start = 0_usize;
+
+    for end in memchr::memchr_iter(b'\n', input.as_bytes()) {
+        lines.push(&input[start..end]);
+        start = end + 1;
+    }
+    lines.push(&input[start..]);
+
+    lines
+}
+
+/// Returns the byte offset at which each line of the input starts.
+///
+/// Lines are split on `\n` exactly as in [`parse`], so a `\r\n` ending keeps
+/// its `\r` at the end of the line and offsets stay in step with the tokens.
+pub(crate) fn line_offsets(input: &str) -> Vec<usize> {
+    std::iter::once(0)
+        .chain(memchr::memchr_iter(b'\n', input.as_bytes()).map(|end| end + 1))
+        .collect()
+}
+
+/// Returns the first byte of the line that is not a space.
+///
+/// Block constructs are recognized by this byte, so lines that cannot start
+/// one are never handed to the regexes.
+fn lead_byte(line: &str) -> Option<u8> {
+    line.bytes().find(|b| *b != b' ')
+}
+
 /// Parses a Markdown string into a series of tokens.
 ///
 /// # Example
@@ -141,150 +903,1881 @@ pub(crate) type Tokens = Vec<Token>;
 /// # Returns
 ///
 /// A vector of tokens
-pub(crate) fn parse(input: String) -> PyResult<Tokens> {
-    let lines = input.split('\n').map(|s| s.to_string()).collect::<Vec<_>>();
+pub(crate) fn parse(input: String, options: &ParseOptions) -> PyResult<Tokens> {
+    parse_timed(input, options, &mut Phases::off())
+}
 
+/// [`parse`], timing its phases in `phases`.
+pub(crate) fn parse_timed(
+    input: String,
+    options: &ParseOptions,
+    phases: &mut Phases,
+) -> PyResult<Tokens> {
     let mut tokens: Tokens = Vec::new();
-    let mut i = 0_usize;
+    phases.time("blocks", || {
+        parse_blocks(&input, 0, options, &mut tokens, |_, _| true)
+    });
+
+    if options.strict {
+        strict::check(&input, 0, &tokens, options)?;
+    }
+
+    number_tokens(&mut tokens, &mut 0, None);
+
+    if options.abbreviations {
+        phases.time("abbreviations", || {
+            let abbreviator = Abbreviator::new(&tokens);
+            tokens
+                .iter_mut()
+                .for_each(|token| abbreviator.annotate(token));
+        });
+    }
+
+    Ok(tokens)
+}
+
+/// Runs the block loop over the lines of `source`, starting at line `start`.
+///
+/// `block` is called with the current line and token count every time the
+/// loop starts a new block; returning `false` stops the loop there. No state
+/// is carried from one block to the next, so the tokens produced from a
+/// given block start depend only on the lines from that point on.
+///
+/// Blank lines are skipped rather than reported as block starts, except at
+/// `start`. Where a block ends may depend on the line after the next block
+/// start (a paragraph stops above the term of a definition list), but never
+/// on anything further.
+pub(crate) fn parse_blocks(
+    source: &str,
+    start: usize,
+    options: &ParseOptions,
+    tokens: &mut Tokens,
+    block: impl FnMut(usize, usize) -> bool,
+) {
+    nested_blocks(source, start, options, 0, tokens, block)
+}
+
+/// [`parse_blocks`] inside `depth` containers. A container that would be
+/// nested deeper than `options.max_depth` is not one, and its lines are
+/// parsed as paragraph text.
+fn nested_blocks(
+    source: &str,
+    start: usize,
+    options: &ParseOptions,
+    depth: usize,
+    tokens: &mut Tokens,
+    mut block: impl FnMut(usize, usize) -> bool,
+) {
+    let parsed = tokens.len();
+    let containers = depth < options.max_depth;
+    let mut lines = split_lines(source);
+    let mut offsets = line_offsets(source);
+
+    // A line break at the end of the input ends the last line rather than
+    // starting an empty one, which an unclosed fence would take as content
+    if source.ends_with('\n') {
+        lines.pop();
+        offsets.pop();
+    }
+
+    // Zero-width characters at the start of a line (a byte order mark, or
+    // what a word processor leaves behind) hide the marker of the block
+    // after them. They are not part of the line, but spans still count them
+    // so they index into `source`
+    for (line, offset) in lines.iter_mut().zip(&mut offsets) {
+        let text = line.trim_start_matches(is_zero_width);
+        *offset += line.len() - text.len();
+        *line = text;
+    }
+
+    // Block structure is decided on lines with the tabs of their indentation
+    // expanded to the next tab stop. Spans and the content of code blocks
+    // use the lines as written
+    let written = lines;
+    let expanded: Vec<Cow<str>> = written
+        .iter()
+        .map(|line| expand_indent(line, options.tab_width))
+        .collect();
+    let lines: Vec<&str> = expanded.iter().map(|line| line.as_ref()).collect();
+
+    // The source covered by lines `first..last`
+    let span = |first: usize, last: usize| -> Span {
+        (offsets[first], offsets[last - 1] + written[last - 1].len())
+    };
+    let raw = |(start, end): Span| options.keep_raw.then(|| source[start..end].to_string());
+    // Where line `i` ends in the source, which paragraph lines are joined with
+    let end = |i: usize| offsets[i] + written[i].len();
+
+    let mut i = start;
 
     'consumer: while i < lines.len() {
-        let line = &lines[i];
+        let line = lines[i];
+
+        if (i == start || !is_blank(line, options)) && !block(i, tokens.len()) {
+            break 'consumer;
+        }
 
-        if line.is_empty() {
+        // Blank line
+        if is_blank(line, options) {
             i += 1;
             continue 'consumer;
         }
 
-        let hre = HEADING_RE.captures(&line);
-
         // Heading
-        if let Some(c) = hre {
+        if let Some((level, text, _)) = heading(line, options) {
+            let span = span(i, i + 1);
+
             tokens.push(Token::Heading {
-                level: c[1].to_string().len() as u8,
-                content: c[2].to_string(),
+                level,
+                content: heading_text(text, options),
+                span,
+                raw: raw(span),
+                number: None,
+                id: 0,
+                parent_id: None,
             });
-        } else {
-            let mut contents: Vec<String> = vec![];
 
-            'collector: while i < lines.len() {
-                let line = &lines[i];
+            i += 1;
+            continue 'consumer;
+        }
 
-                if line.trim().is_empty() {
-                    break 'collector;
-                }
+        // Horizontal rule
+        if let Some((marker, length)) = rule(line) {
+            let span = span(i, i + 1);
 
-                // "---" handling (horizontal rule or heading)
-                if line.starts_with("---") && line.trim_matches('-').is_empty() {
-                    if contents.is_empty() {
-                        tokens.push(Token::HorizontalRule());
-                    } else {
-                        // If we have something like:
-                        // ```markdown
-                        // Only one new line!
-                        // Hello, guys!
-                        // ---
-                        // ```
-                        // We should ONLY collect "Hello, guys!"
-                        // The `contents`:
-                        // ["Only one new line!", "Hello, guys!"]
-                        // So we should be getting [-1] as the heading, [:-1] as the content (before)
-
-                        let before = &contents[..contents.len() - 1].join(" ");
-                        let heading = &contents[contents.len() - 1];
-
-                        if !before.is_empty() {
-                            tokens.push(Token::Paragraph(before.to_string(), vec![]));
-                        }
-                        tokens.push(Token::Heading {
-                            level: 1,
-                            content: heading.to_string(),
-                        });
-                    }
+            tokens.push(Token::HorizontalRule {
+                span,
+                raw: raw(span),
+                marker,
+                length,
+                id: 0,
+                parent_id: None,
+            });
 
-                    i += 1;
-                    continue 'consumer;
+            i += 1;
+            continue 'consumer;
+        }
+
+        // Code block
+        if let Some(c) = fence(line) {
+            let first = i;
+            let indent = c[1].len();
+            let fence = c.get(2).or(c.get(4)).unwrap().as_str();
+            let info = c.get(3).or(c.get(5)).unwrap().as_str().trim().to_string();
+            let (language, flags, attrs) = fence_info(&info);
+
+            i += 1;
+            'code_collector: while i < lines.len() {
+                if closes(lines[i], fence) {
+                    break 'code_collector;
                 }
 
-                let fre = FENCE_RE.captures(&line);
+                i += 1;
+            }
 
-                if let Some(c) = fre {
-                    tokens.push(Token::Paragraph(contents.join(" "), vec![]));
-                    contents.clear();
+            // The code of an indented fence loses the indentation it has in
+            // common, which it usually only has from where it was pasted
+            let body = &written[first + 1..i];
+            let common = if indent > 0 { common_indent(body) } else { "" };
+            let mut code = String::new();
+            for line in body {
+                code += line
+                    .strip_prefix(common)
+                    .unwrap_or_else(|| line.trim_start_matches([' ', '\t']));
+                code += "\n";
+            }
 
-                    let language = c[1].to_string();
-                    let mut code = String::new();
+            // An unclosed fence runs to the end of the input
+            let span = span(first, (i + 1).min(lines.len()));
 
-                    i += 1;
-                    'code_collector: while i < lines.len() {
-                        let line = &lines[i];
+            tokens.push(Token::Code {
+                language,
+                content: code,
+                fence_char: fence.chars().next(),
+                fence_length: fence.len(),
+                indent,
+                span,
+                raw: raw(span),
+                info,
+                flags,
+                attrs,
+                id: 0,
+                parent_id: None,
+            });
 
-                        if line.trim() == "```" {
-                            break 'code_collector;
-                        }
+            // Skip the closing fence
+            i += 1;
+            continue 'consumer;
+        }
 
-                        code += line;
-                        code += "\n";
+        // Math block
+        if let Some(rest) = math_block(line, options) {
+            let first = i;
+            let mut content = String::new();
 
+            // `$$x$$` on one line is a block of its own
+            match math_close(rest) {
+                Some(inner) => content += inner,
+                None => {
+                    content += rest.trim_end();
+                    i += 1;
+                    while i < lines.len() {
+                        content += "\n";
+                        if let Some(inner) = math_close(lines[i]) {
+                            content += inner;
+                            break;
+                        }
+                        content += written[i].trim_end();
                         i += 1;
                     }
+                }
+            }
 
-                    tokens.push(Token::Code {
-                        language,
-                        content: code,
-                    });
+            // An unclosed block runs to the end of the input
+            let span = span(first, (i + 1).min(lines.len()));
 
-                    i += 1;
-                    continue 'consumer;
+            tokens.push(Token::MathBlock {
+                content: content.trim().to_string(),
+                span,
+                raw: raw(span),
+                id: 0,
+                parent_id: None,
+            });
+
+            // Skip the closing line
+            i += 1;
+            continue 'consumer;
+        }
+
+        // Custom container
+        if let Some(c) = containers.then(|| container(line, options)).flatten() {
+            let first = i;
+            let marker = c.get(1).unwrap().as_str();
+            let name = c[2].to_string();
+            let attrs = c[3].to_string();
+
+            // Containers inside this one are closed first, and neither they
+            // nor a `:::` in a code or math block close it
+            let mut nested = 0;
+            let mut code: Option<&str> = None;
+            let mut math = false;
+
+            i += 1;
+            while i < lines.len() {
+                let line = lines[i];
+
+                match code {
+                    Some(fence) => {
+                        if closes(line, fence) {
+                            code = None;
+                        }
+                    }
+                    None if math => {
+                        if math_close(line).is_some() {
+                            math = false;
+                        }
+                    }
+                    None => {
+                        if let Some(c) = fence(line) {
+                            code = c.get(2).or(c.get(4)).map(|fence| fence.as_str());
+                        } else if let Some(rest) = math_block(line, options) {
+                            math = math_close(rest).is_none();
+                        } else if container(line, options).is_some() {
+                            nested += 1;
+                        } else if closes(line, ":::") && nested > 0 {
+                            nested -= 1;
+                        } else if closes(line, marker) {
+                            break;
+                        }
+                    }
                 }
 
-                contents.push(line.to_owned());
                 i += 1;
             }
 
-            let paragraph = contents.join(" ");
-            let mut metadatas: Vec<Metadata> = vec![];
-
-            // Process links
-            for item in Metadata::links(&paragraph) {
-                metadatas.push(item);
+            // The lines in between are parsed on their own, so a block in
+            // them cannot run past the closing marker
+            let mut content = Vec::new();
+            if i > first + 1 {
+                let (start, end) = span(first + 1, i);
+                // A function pointer, as a closure would be a new type of
+                // `block` at every level of nesting
+                let every: fn(usize, usize) -> bool = |_, _| true;
+                let source = &source[start..end];
+                nested_blocks(source, 0, options, depth + 1, &mut content, every);
+                content
+                    .iter_mut()
+                    .for_each(|token| token.map_spans(&|offset| offset + start));
             }
 
-            // Process images
-            for item in Metadata::images(&paragraph) {
-                metadatas.push(item);
-            }
+            // An unclosed container runs to the end of the input
+            let span = span(first, (i + 1).min(lines.len()));
 
-            tokens.push(Token::Paragraph(paragraph, metadatas));
-            continue;
+            tokens.push(Token::Container {
+                name,
+                attrs,
+                content,
+                span,
+                raw: raw(span),
+                id: 0,
+                parent_id: None,
+            });
+
+            // Skip the closing marker
+            i += 1;
+            continue 'consumer;
         }
 
-        i += 1;
-    }
+        // Abbreviation definition
+        if let Some(c) = abbreviation(line, options) {
+            let span = span(i, i + 1);
 
-    Ok(tokens)
-}
+            tokens.push(Token::Abbreviation {
+                abbr: c[1].to_string(),
+                expansion: c[2].to_string(),
+                span,
+                raw: raw(span),
+                id: 0,
+                parent_id: None,
+            });
 
-pub(crate) fn tokens_to_html(tokens: Tokens) -> PyResult<String> {
-    let mut contents: Vec<String> = vec![];
+            i += 1;
+            continue 'consumer;
+        }
 
-    for item in tokens {
-        match item {
-            Token::Paragraph(s, ..) => {
-                contents.push(format!("<p>{}</p>", html_escape::encode_text(&s)))
+        // Definition list
+        if options.definition_lists && is_term(&lines, i, options) {
+            let first = i;
+            let mut items = Vec::new();
+
+            loop {
+                let term = heading_text(lines[i], options);
+                let mut definitions = Vec::new();
+                i += 1;
+
+                while let Some(text) = lines.get(i).and_then(|line| definition(line)) {
+                    let mut paragraphs = vec![vec![(text, end(i))]];
+                    i += 1;
+
+                    // Indented lines continue the definition, and after blank
+                    // lines they start another paragraph of it
+                    loop {
+                        let next = (i..lines.len()).find(|&j| !is_blank(lines[j], options));
+
+                        match next {
+                            Some(j) if is_indented(lines[j]) && definition(lines[j]).is_none() => {
+                                if j > i {
+                                    paragraphs.push(Vec::new());
+                                }
+                                paragraphs.last_mut().unwrap().push((lines[j], end(j)));
+                                i = j + 1;
+                            }
+                            _ => break,
+                        }
+                    }
+
+                    definitions.push(definition_text(&paragraphs, options));
+                }
+
+                items.push((term, definitions));
+
+                // Items may be separated by blank lines
+                let next = (i..lines.len()).find(|&j| !is_blank(lines[j], options));
+                match next {
+                    Some(j) if is_term(&lines, j, options) => i = j,
+                    _ => break,
+                }
+            }
+
+            let span = span(first, i);
+            tokens.push(Token::DefinitionList {
+                items,
+                span,
+                raw: raw(span),
+                id: 0,
+                parent_id: None,
+            });
+
+            continue 'consumer;
+        }
+
+        // Paragraph, running until a blank line or another block interrupts it
+        let first = i;
+        let mut contents: Vec<(&str, usize)> = vec![(line, end(i))];
+        i += 1;
+
+        'collector: while i < lines.len() {
+            let line = lines[i];
+
+            if interrupts(line, options, containers) {
+                break 'collector;
+            }
+
+            // A line followed by a definition is the term of a definition list
+            if options.definition_lists && is_term(&lines, i, options) {
+                break 'collector;
+            }
+
+            // "---" under a paragraph makes a heading
+            if is_underline(line) {
+                // If we have something like:
+                // ```markdown
+                // Only one new line!
+                // Hello, guys!
+                // ---
+                // ```
+                // We should ONLY collect "Hello, guys!"
+                // The `contents`:
+                // ["Only one new line!", "Hello, guys!"]
+                // So we should be getting [-1] as the heading, [:-1] as the content (before)
+
+                let (heading, _) = contents[contents.len() - 1];
+                let before = &contents[..contents.len() - 1];
+
+                if !before.is_empty() {
+                    let span = span(first, i - 1);
+                    tokens.push(paragraph(before, span, raw(span), options));
+                }
+
+                let span = span(i - 1, i + 1);
+                tokens.push(Token::Heading {
+                    level: 1,
+                    content: heading_text(heading, options),
+                    span,
+                    raw: raw(span),
+                    number: None,
+                    id: 0,
+                    parent_id: None,
+                });
+
+                i += 1;
+                continue 'consumer;
+            }
+
+            if rule(line).is_some() {
+                break 'collector;
+            }
+
+            contents.push((line, end(i)));
+            i += 1;
+        }
+
+        let span = span(first, i);
+        tokens.push(paragraph(&contents, span, raw(span), options));
+    }
+
+    // Containers were sanitized by the parse of their content
+    if options.sanitize_control_chars {
+        tokens[parsed..].iter_mut().for_each(sanitize::token);
+    }
+}
+
+/// Whether the line ends a paragraph above it by itself: it is blank, or
+/// starts a block that may interrupt one. Terms, underlines and rules are
+/// left to the paragraph collector, which treats them each its own way.
+fn interrupts(line: &str, options: &ParseOptions, containers: bool) -> bool {
+    is_blank(line, options)
+        || fence(line).is_some()
+        || heading(line, options).is_some_and(|(_, _, interrupts)| interrupts)
+        || abbreviation(line, options).is_some()
+        || (containers && container(line, options).is_some())
+        || math_block(line, options).is_some()
+}
+
+/// Whether `line`, written after `block`, is sure to be one more line of it,
+/// where `block` is the last block parsed from its input, still open and
+/// running to the end of it. A `false` only means the line may end `block`,
+/// or start another; [`parse_blocks`] tells which.
+pub(crate) fn continues(block: &Token, line: &str, options: &ParseOptions) -> bool {
+    let line = expand_indent(line.trim_start_matches(is_zero_width), options.tab_width);
+    let line = line.as_ref();
+
+    match block {
+        Token::Code {
+            fence_char: Some(marker),
+            fence_length,
+            ..
+        } => !closes(line, &marker.to_string().repeat(*fence_length)),
+        Token::MathBlock { .. } => math_close(line).is_none(),
+        // A `:::` line may close the container, or open one inside it, and
+        // only the fences around it tell which
+        Token::Container { .. } => lead_byte(line) != Some(b':'),
+        Token::Paragraph(..) => {
+            !(interrupts(line, options, options.max_depth > 0)
+                || is_underline(line)
+                || rule(line).is_some()
+                || (options.definition_lists && definition(line).is_some()))
+        }
+        _ => false,
+    }
+}
+
+/// Matches a heading line: an ATX heading, or with `options.lenient_headings`
+/// one of the forms other tools write. Returns the level, the text, and
+/// whether the heading may interrupt a paragraph.
+fn heading<'a>(line: &'a str, options: &ParseOptions) -> Option<(u8, &'a str, bool)> {
+    let atx = |c: Captures<'a>| {
+        let level = c[1].chars().count() as u8;
+        (level, c.get(2).unwrap().as_str())
+    };
+
+    if line.starts_with('#') {
+        // Hashes alone are an empty heading
+        let hashes = line.trim_end_matches(|c| is_space(c, options));
+        if hashes.len() <= 6 && hashes.bytes().all(|b| b == b'#') {
+            return Some((hashes.len() as u8, "", true));
+        }
+        if let Some(c) = HEADING_RE.captures(line) {
+            let (level, text) = atx(c);
+            return Some((level, text, true));
+        }
+    }
+    if !options.lenient_headings {
+        return None;
+    }
+
+    // Full-width hashes, as translated documents have them, count as `#`.
+    // Without the space, only CJK text after the hashes reads as a heading
+    if let Some(c) = WIDE_HEADING_RE.captures(line) {
+        let (level, text) = atx(c);
+        return Some((level, text, true));
+    }
+    if let Some(c) = CJK_HEADING_RE.captures(line) {
+        let (level, text) = atx(c);
+        return Some((level, text, false));
+    }
+
+    let c = ASCIIDOC_HEADING_RE.captures(line)?;
+    let text = c.get(2).unwrap().as_str();
+    (!text.trim_matches('=').trim().is_empty()).then(|| (c[1].len() as u8, text, false))
+}
+
+/// Matches an opening code fence.
+fn fence(line: &str) -> Option<Captures<'_>> {
+    match lead_byte(line) {
+        Some(b'`' | b'~') => FENCE_RE.captures(line),
+        _ => None,
+    }
+}
+
+/// Matches the opening `$$` of a math block, if math is parsed, and returns
+/// the rest of the line.
+fn math_block<'a>(line: &'a str, options: &ParseOptions) -> Option<&'a str> {
+    match lead_byte(line) {
+        Some(b'$') if options.math => line.trim_start_matches(' ').strip_prefix("$$"),
+        _ => None,
+    }
+}
+
+/// Matches the line that closes a math block: what comes before its `$$`.
+/// Any more `$$`s it ends in close it too, since content ending in one would
+/// close the block early when written back.
+fn math_close(line: &str) -> Option<&str> {
+    line.trim_end()
+        .strip_suffix("$$")
+        .map(|inner| inner.trim_end_matches("$$"))
+}
+
+/// Matches the opening line of a custom container, if they are parsed: its
+/// colons, name and the attributes after the name.
+fn container<'a>(line: &'a str, options: &ParseOptions) -> Option<Captures<'a>> {
+    match lead_byte(line) {
+        Some(b':') if options.containers => CONTAINER_RE.captures(line),
+        _ => None,
+    }
+}
+
+/// Splits the info string of a fence into its language, flags and
+/// `key=value` attributes.
+///
+/// Words are separated by spaces or commas, except inside `"..."` or
+/// `'...'`, and the quotes themselves are dropped. The first word is the
+/// language unless it is an attribute.
+fn fence_info(info: &str) -> (String, Vec<String>, BTreeMap<String, String>) {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quote = None;
+    let mut quoted = false;
+
+    for c in info.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => word.push(c),
+            None if c == '"' || c == '\'' => {
+                quote = Some(c);
+                quoted = true;
+            }
+            None if c.is_whitespace() || c == ',' => {
+                if !word.is_empty() || quoted {
+                    words.push(std::mem::take(&mut word));
+                }
+                quoted = false;
+            }
+            None => word.push(c),
+        }
+    }
+    if !word.is_empty() || quoted {
+        words.push(word);
+    }
+
+    let mut language = String::new();
+    let mut flags = Vec::new();
+    let mut attrs = BTreeMap::new();
+
+    for (i, word) in words.into_iter().enumerate() {
+        match word.split_once('=') {
+            Some((key, value)) if !key.is_empty() => {
+                attrs.insert(key.to_string(), value.to_string());
+            }
+            _ if i == 0 => language = word,
+            _ => flags.push(word),
+        }
+    }
+
+    (language, flags, attrs)
+}
+
+/// Whether the line closes a code block opened by `fence`: a run of the same
+/// character, at least as long, and nothing else. Like an opening fence, it
+/// may be indented by three spaces at most, so a fence shown in indented text
+/// inside the block is content.
+pub(crate) fn closes(line: &str, fence: &str) -> bool {
+    let trimmed = line.trim_start_matches(' ');
+    if line.len() - trimmed.len() > 3 {
+        return false;
+    }
+    let line = trimmed.trim();
+    let marker = fence.as_bytes()[0];
+
+    line.len() >= fence.len() && line.bytes().all(|b| b == marker)
+}
+
+/// Whether the line is a `---` setext underline.
+fn is_underline(line: &str) -> bool {
+    line.starts_with("---") && line.trim_matches('-').is_empty()
+}
+
+/// Matches a thematic break: three or more `-`, `*` or `_`, all the same and
+/// optionally spaced out, indented by at most three spaces. Returns the
+/// marker and how many times it appears.
+fn rule(line: &str) -> Option<(char, usize)> {
+    let marker = match lead_byte(line) {
+        Some(b @ (b'-' | b'*' | b'_')) => b as char,
+        _ => return None,
+    };
+
+    let body = line.trim_start_matches(' ');
+    if line.len() - body.len() > 3 {
+        return None;
+    }
+
+    let mut length = 0;
+    for c in body.chars() {
+        match c {
+            ' ' | '\t' | '\r' => {}
+            c if c == marker => length += 1,
+            _ => return None,
+        }
+    }
+
+    (length >= 3).then_some((marker, length))
+}
+
+/// Matches an abbreviation definition line, if they are enabled.
+fn abbreviation<'a>(line: &'a str, options: &ParseOptions) -> Option<Captures<'a>> {
+    match lead_byte(line) {
+        Some(b'*') if options.abbreviations => ABBREVIATION_RE.captures(line),
+        _ => None,
+    }
+}
+
+/// Returns the text of a `: definition` line.
+fn definition(line: &str) -> Option<&str> {
+    let rest = line.trim_start_matches(' ');
+
+    if line.len() - rest.len() > 3 {
+        return None;
+    }
+
+    let text = rest.strip_prefix(':')?;
+    if !text.starts_with([' ', '\t']) || text.trim().is_empty() {
+        return None;
+    }
+
+    Some(text.trim_start())
+}
+
+/// Whether line `i` is the term of a definition list: a line of text directly
+/// followed by a definition.
+fn is_term(lines: &[&str], i: usize, options: &ParseOptions) -> bool {
+    i + 1 < lines.len()
+        && !is_blank(lines[i], options)
+        && definition(lines[i]).is_none()
+        && definition(lines[i + 1]).is_some()
+}
+
+/// Whether the character is whitespace, counting non-breaking spaces only if
+/// `options` do.
+fn is_space(c: char, options: &ParseOptions) -> bool {
+    c.is_whitespace()
+        && (options.nbsp_is_whitespace || !matches!(c, '\u{a0}' | '\u{2007}' | '\u{202f}'))
+}
+
+/// Zero-width spaces, joiners and the byte order mark, which take up no room
+/// and are not whitespace to `char::is_whitespace`.
+fn is_zero_width(c: char) -> bool {
+    matches!(c, '\u{200b}'..='\u{200d}' | '\u{2060}' | '\u{feff}')
+}
+
+/// Whether the line has nothing but whitespace and zero-width characters.
+fn is_blank(line: &str, options: &ParseOptions) -> bool {
+    line.chars()
+        .all(|c| is_space(c, options) || is_zero_width(c))
+}
+
+/// Expands the tabs in the indentation of a line to spaces, each up to the
+/// next multiple of `tab_width` columns. The rest of the line is left alone.
+fn expand_indent(line: &str, tab_width: usize) -> Cow<'_, str> {
+    let indent = line.len() - line.trim_start_matches([' ', '\t']).len();
+    if !line[..indent].contains('\t') {
+        return Cow::Borrowed(line);
+    }
+
+    let mut expanded = String::with_capacity(line.len() + tab_width);
+    for c in line[..indent].chars() {
+        let width = match c {
+            '\t' => tab_width - expanded.len() % tab_width,
+            _ => 1,
+        };
+        expanded.extend(std::iter::repeat_n(' ', width));
+    }
+    expanded += &line[indent..];
+
+    Cow::Owned(expanded)
+}
+
+/// The longest run of leading spaces and tabs shared by every line that is
+/// not made of them alone.
+fn common_indent<'a>(lines: &[&'a str]) -> &'a str {
+    let mut indents = lines.iter().filter_map(|line| {
+        let rest = line.trim_start_matches([' ', '\t']);
+        (!rest.is_empty()).then(|| &line[..line.len() - rest.len()])
+    });
+    let Some(first) = indents.next() else {
+        return "";
+    };
+
+    indents.fold(first, |common, indent| {
+        let shared = common
+            .bytes()
+            .zip(indent.bytes())
+            .take_while(|(a, b)| a == b)
+            .count();
+        &common[..shared]
+    })
+}
+
+fn is_indented(line: &str) -> bool {
+    line.starts_with([' ', '\t'])
+}
+
+/// Where each byte of the text of a paragraph is in the source, built while
+/// its lines are joined with `ParseOptions(source_spans=True)`.
+type Offsets = Vec<usize>;
+
+/// Collapses every run of spaces and tabs into one space. A run that takes in
+/// line breaks becomes a single line break instead.
+///
+/// `offsets`, the table of `text`, is made the table of the result. A run
+/// that was collapsed is where its first space or line break was.
+fn collapse_whitespace(text: &str, offsets: Option<&mut Offsets>) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut kept = Vec::new();
+    let mut run: Option<(char, usize)> = None;
+
+    for (i, c) in text.char_indices() {
+        match c {
+            '\n' => run = Some(run.map_or(('\n', i), |(_, at)| ('\n', at))),
+            ' ' | '\t' => run = run.or(Some((' ', i))),
+            _ => {
+                if let Some((space, at)) = run.take() {
+                    collapsed.push(space);
+                    kept.push(at);
+                }
+                collapsed.push(c);
+                kept.extend(i..i + c.len_utf8());
+            }
+        }
+    }
+    if let Some((space, at)) = run {
+        collapsed.push(space);
+        kept.push(at);
+    }
+
+    if let Some(offsets) = offsets {
+        *offsets = kept.into_iter().map(|i| offsets[i]).collect();
+    }
+
+    collapsed
+}
+
+/// Trims the text of a heading or term, and collapses the runs of spaces and
+/// tabs inside it whether or not `collapse_whitespace` is set.
+fn heading_text(content: &str, options: &ParseOptions) -> String {
+    collapse_whitespace(content.trim_matches(|c| is_space(c, options)), None)
+}
+
+/// Joins the collected lines into a paragraph and extracts its metadata.
+///
+/// Whitespace is cleaned up first, so metadata offsets point into the
+/// string that is actually stored on the token.
+fn paragraph(
+    contents: &[(&str, usize)],
+    span: Span,
+    raw: Option<String>,
+    options: &ParseOptions,
+) -> Token {
+    let (paragraph, breaks, hard_breaks, offsets) = join_lines(contents, options);
+    let mut metadatas = inline_metadata(&paragraph, options);
+    if let Some(offsets) = offsets {
+        source_spans(&mut metadatas, &offsets);
+    }
+    line_breaks(&mut metadatas, &hard_breaks);
+
+    Token::Paragraph(paragraph, metadatas, span, raw, breaks, 0, None)
+}
+
+/// Joins the paragraphs of a definition with blank lines and extracts its
+/// metadata from the result.
+fn definition_text(paragraphs: &[Vec<(&str, usize)>], options: &ParseOptions) -> Definition {
+    let mut text = String::new();
+    let mut offsets = options.source_spans.then(Vec::new);
+    let mut hard_breaks = Vec::new();
+
+    for (i, lines) in paragraphs.iter().enumerate() {
+        let (paragraph, _, paragraph_hard_breaks, paragraph_offsets) = join_lines(lines, options);
+
+        if i > 0 {
+            text += "\n\n";
+        }
+        hard_breaks.extend(paragraph_hard_breaks.into_iter().map(|at| at + text.len()));
+        text += &paragraph;
+
+        if let (Some(offsets), Some(paragraph_offsets)) = (&mut offsets, paragraph_offsets) {
+            if i > 0 {
+                let (_, end) = paragraphs[i - 1][paragraphs[i - 1].len() - 1];
+                offsets.extend([end, end]);
+            }
+            offsets.extend(paragraph_offsets);
+        }
+    }
+
+    let mut metadatas = inline_metadata(&text, options);
+    if let Some(offsets) = offsets {
+        source_spans(&mut metadatas, &offsets);
+    }
+    line_breaks(&mut metadatas, &hard_breaks);
+
+    (text, metadatas)
+}
+
+/// Joins the lines of a paragraph into one, trimming and collapsing
+/// whitespace as `options` ask. Each line comes with the offset in the
+/// source where it ends.
+///
+/// Lines are joined with a space, and the offset of every joining space is
+/// returned alongside the text, then the offsets of those after a line that
+/// ended in a backslash, which is dropped from the text. With
+/// `options.source_spans`, so is the [`Offsets`] table of the text.
+fn join_lines(
+    contents: &[(&str, usize)],
+    options: &ParseOptions,
+) -> (String, Vec<usize>, Vec<usize>, Option<Offsets>) {
+    // Join on newlines first, which trimming and collapsing leave alone, so
+    // the breaks can still be found afterwards
+    let mut paragraph = String::new();
+    let mut offsets = options.source_spans.then(Vec::new);
+    let mut hard = Vec::new();
+
+    for (i, &(line, end)) in contents.iter().enumerate() {
+        let (start, mut text) = if options.trim_lines {
+            let text = line.trim_start_matches(|c| is_space(c, options));
+            (
+                line.len() - text.len(),
+                text.trim_end_matches(|c| is_space(c, options)),
+            )
+        } else {
+            (0, line)
+        };
+
+        // A backslash right before the end of any line but the last is a
+        // hard break, unless it is itself escaped
+        if i + 1 < contents.len() {
+            let trailing = line.trim_end_matches('\r');
+            let backslashes = trailing.len() - trailing.trim_end_matches('\\').len();
+            let is_hard = backslashes % 2 == 1 && text.ends_with('\\');
+            if is_hard {
+                text = &text[..text.len() - 1];
+            }
+            hard.push(is_hard);
+        }
+
+        if i > 0 {
+            paragraph.push('\n');
+        }
+        paragraph += text;
+
+        if let Some(offsets) = &mut offsets {
+            // A line break is where the line before it ended. Expanding tabs
+            // only lengthens the indentation of a line, so past that its
+            // bytes are as far from the end as in the source
+            if i > 0 {
+                offsets.push(contents[i - 1].1);
+            }
+            offsets.extend((0..text.len()).map(|j| (end + start + j).saturating_sub(line.len())));
+        }
+    }
+
+    let paragraph = if options.collapse_whitespace {
+        collapse_whitespace(&paragraph, offsets.as_mut())
+    } else {
+        paragraph
+    };
+    let breaks: Vec<usize> = memchr::memchr_iter(b'\n', paragraph.as_bytes()).collect();
+    let hard_breaks = breaks
+        .iter()
+        .zip(hard)
+        .filter_map(|(&at, hard)| hard.then_some(at))
+        .collect();
+
+    (paragraph.replace('\n', " "), breaks, hard_breaks, offsets)
+}
+
+/// Adds a [`Metadata::LineBreak`] at each of `hard_breaks`, unless something
+/// found in the text already covers it, like a raw span.
+fn line_breaks(metadatas: &mut Vec<Metadata>, hard_breaks: &[usize]) {
+    if hard_breaks.is_empty() {
+        return;
+    }
+
+    for &at in hard_breaks {
+        let covered = metadatas.iter().any(|metadata| {
+            let (start, end) = metadata.location();
+            start <= at && at < end
+        });
+
+        if !covered {
+            metadatas.push(Metadata::LineBreak {
+                location: (at, at + 1),
+            });
+        }
+    }
+    sort_metadata(metadatas);
+}
+
+/// Sets the `span` of the links and images from the [`Offsets`] of the text
+/// they were found in.
+fn source_spans(metadatas: &mut [Metadata], offsets: &Offsets) {
+    for metadata in metadatas {
+        if let Metadata::Link { location, span, .. } | Metadata::Image { location, span, .. } =
+            metadata
+        {
+            *span = Some((offsets[location.0], offsets[location.1 - 1] + 1));
+        }
+    }
+}
+
+/// Finds the links and images in a paragraph, in the order of
+/// [`sort_metadata`].
+pub(crate) fn inline_metadata(paragraph: &str, options: &ParseOptions) -> Vec<Metadata> {
+    let mut metadatas: Vec<Metadata> = vec![];
+
+    // Process links
+    if memchr::memchr(b'[', paragraph.as_bytes()).is_some() {
+        for item in Metadata::links(paragraph) {
+            metadatas.push(item);
+        }
+    }
+
+    // Process images
+    if memchr::memchr(b'!', paragraph.as_bytes()).is_some() {
+        for item in Metadata::images(paragraph) {
+            metadatas.push(item);
+        }
+    }
+
+    // Process autolinks
+    if memchr::memchr(b'<', paragraph.as_bytes()).is_some() {
+        for item in Metadata::autolinks(paragraph) {
+            metadatas.push(item);
+        }
+    }
+
+    // Process bare emails, unless they are already part of a link
+    if options.autolink_emails && memchr::memchr(b'@', paragraph.as_bytes()).is_some() {
+        for item in Metadata::emails(paragraph) {
+            let (start, end) = item.location();
+            let linked = metadatas.iter().any(|metadata| {
+                let (s, e) = metadata.location();
+                start < e && s < end
+            });
+
+            if !linked {
+                metadatas.push(item);
+            }
+        }
+    }
+
+    // A wiki link is not a link with a label of `[Page]`
+    if options.wiki_links && memchr::memmem::find(paragraph.as_bytes(), b"[[").is_some() {
+        let wiki_links = Metadata::wiki_links(paragraph);
+        metadatas.retain(|metadata| {
+            let (start, end) = metadata.location();
+            !wiki_links.iter().any(|link| {
+                let (s, e) = link.location();
+                start < e && s < end
+            })
+        });
+        metadatas.extend(wiki_links);
+    }
+
+    // Nothing is found in math
+    if options.math && memchr::memchr(b'$', paragraph.as_bytes()).is_some() {
+        let math = Metadata::math(paragraph);
+        metadatas.retain(|metadata| {
+            let (start, end) = metadata.location();
+            !math.iter().any(|math| {
+                let (s, e) = math.location();
+                start < e && s < end
+            })
+        });
+        metadatas.extend(math);
+    }
+
+    // Nothing is found in text kept as written
+    let raw = raw_spans(paragraph, &options.preserve_delimiters);
+    if !raw.is_empty() {
+        metadatas.retain(|metadata| {
+            let (start, end) = metadata.location();
+            !raw.iter().any(|raw| {
+                let (s, e) = raw.location();
+                start < e && s < end
+            })
+        });
+        metadatas.extend(raw);
+    }
+
+    sort_metadata(&mut metadatas);
+    metadatas
+}
+
+/// Finds the spans between `delimiters` in a paragraph, left to right. Where
+/// two open at the same place the longer opening delimiter wins, and a span
+/// without its closing delimiter runs to the end.
+fn raw_spans(paragraph: &str, delimiters: &[(String, String)]) -> Vec<Metadata> {
+    let mut spans = Vec::new();
+    let mut cursor = 0;
+
+    loop {
+        let next = delimiters
+            .iter()
+            .filter_map(|(open, close)| {
+                let start = cursor + paragraph[cursor..].find(open.as_str())?;
+                Some((start, std::cmp::Reverse(open.len()), close))
+            })
+            .min();
+        let Some((start, std::cmp::Reverse(open), close)) = next else {
+            break;
+        };
+
+        let after = start + open;
+        let Some(end) = paragraph[after..].find(close.as_str()) else {
+            spans.push(Metadata::Raw {
+                location: (start, paragraph.len()),
+                closed: false,
+            });
+            break;
+        };
+
+        cursor = after + end + close.len();
+        spans.push(Metadata::Raw {
+            location: (start, cursor),
+            closed: true,
+        });
+    }
+
+    spans
+}
+
+/// Puts metadata in the order it appears in the text: by where it starts,
+/// the longer first where two start together. The sort is stable, so
+/// anything still tied keeps the order it was found in.
+///
+/// An image starts one byte before the link inside it, so it always comes
+/// right before that link.
+pub(crate) fn sort_metadata(metadata: &mut [Metadata]) {
+    metadata.sort_by_key(|metadata| {
+        let (start, end) = metadata.location();
+        (start, std::cmp::Reverse(end))
+    });
+}
+
+/// Drops the backslash from backslash escapes (`\#`, `\-`, ...), and with
+/// `references` decodes entity and numeric character references (`&amp;`,
+/// `&#35;`) to the text they stand for.
+///
+/// Any ASCII punctuation character can be escaped, which is also what keeps an
+/// escaped block marker at the start of a line from starting a block, and an
+/// escaped `\&` from starting a reference.
+pub(crate) fn unescape(text: &str, references: bool) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        if c == '\\' {
+            if let Some(next) = rest[1..].chars().next() {
+                if next.is_ascii_punctuation() {
+                    unescaped.push(next);
+                    rest = &rest[2..];
+                    continue;
+                }
+            }
+        }
+
+        if c == '&' && references {
+            if let Some((decoded, length)) = decode_reference(rest) {
+                unescaped += &decoded;
+                rest = &rest[length..];
+                continue;
             }
-            Token::Code { content, .. } => contents.push(format!(
-                "<pre><code>{}</code></pre>",
-                html_escape::encode_text::<String>(&content).to_string()
+        }
+
+        unescaped.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+
+    unescaped
+}
+
+/// Common code block language aliases, and the name each one maps to.
+const LANGUAGE_ALIASES: &[(&str, &str)] = &[
+    ("c++", "cpp"),
+    ("cs", "csharp"),
+    ("c#", "csharp"),
+    ("golang", "go"),
+    ("htm", "html"),
+    ("js", "javascript"),
+    ("kt", "kotlin"),
+    ("md", "markdown"),
+    ("py", "python"),
+    ("py3", "python"),
+    ("rb", "ruby"),
+    ("rs", "rust"),
+    ("sh", "bash"),
+    ("shell", "bash"),
+    ("ts", "typescript"),
+    ("yml", "yaml"),
+];
+
+/// Lowercases a code block language and resolves its alias, if it has one.
+pub(crate) fn normalize_language(language: &str) -> String {
+    let language = language.to_lowercase();
+
+    match LANGUAGE_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == language)
+    {
+        Some((_, name)) => name.to_string(),
+        None => language,
+    }
+}
+
+/// Writes every character as a numeric character reference, which hides an
+/// email address from naive scrapers but not from browsers.
+fn encode_entities(text: &str) -> String {
+    text.chars().map(|c| format!("&#{};", c as u32)).collect()
+}
+
+/// Percent-encodes the bytes of a URL that may not appear in an `href` as
+/// they are: spaces, non-ASCII characters and other unsafe ASCII. Reserved
+/// characters such as `/`, `?` and `#` are kept, and so is a `%` that already
+/// starts an escape.
+fn encode_url(url: &str) -> String {
+    let bytes = url.as_bytes();
+    let mut encoded = String::with_capacity(url.len());
+
+    for (i, &b) in bytes.iter().enumerate() {
+        let escape = b == b'%'
+            && bytes
+                .get(i + 1..i + 3)
+                .is_some_and(|hex| hex.iter().all(u8::is_ascii_hexdigit));
+
+        if b.is_ascii_alphanumeric() || b";/?:@&=+$,-_.!~*'()#".contains(&b) || escape {
+            encoded.push(b as char);
+        } else {
+            encoded += &format!("%{b:02X}");
+        }
+    }
+
+    encoded
+}
+
+/// How many characters of a URL `HtmlOptions(shorten_urls=True)` shows.
+const SHORT_URL_LENGTH: usize = 32;
+
+/// The text `shorten_urls` shows for a URL: without its scheme or a final
+/// `/`, and if that is longer than [`SHORT_URL_LENGTH`], cut to that many
+/// characters, the last a `…`, but never inside the host. `None` for a URL
+/// with no `scheme://`.
+fn shorten_url(url: &str) -> Option<String> {
+    let (_, rest) = url.split_once("://")?;
+    let rest = rest.strip_suffix('/').unwrap_or(rest);
+
+    match rest.char_indices().nth(SHORT_URL_LENGTH) {
+        None => Some(rest.to_string()),
+        Some(_) => {
+            let host = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+            let cut = rest
+                .char_indices()
+                .nth(SHORT_URL_LENGTH - 1)
+                .map_or(rest.len(), |(i, _)| i)
+                .max(host);
+
+            Some(format!("{}…", &rest[..cut]))
+        }
+    }
+}
+
+/// Turns the spaces that joined the lines of a paragraph back into newlines.
+fn soft_breaks(text: String, breaks: &[usize]) -> String {
+    let mut bytes = text.into_bytes();
+
+    for &i in breaks {
+        if let Some(b @ b' ') = bytes.get_mut(i) {
+            *b = b'\n';
+        }
+    }
+
+    // Only ASCII spaces were replaced
+    String::from_utf8(bytes).unwrap()
+}
+
+/// Renders the text of a paragraph, with its links, images and abbreviations
+/// in place.
+///
+/// A metadata that starts inside an earlier one is skipped, like the link
+/// half of an image, and so is one whose location does not fit the text.
+fn inline_html(
+    text: &str,
+    metadata: &[Metadata],
+    options: &HtmlOptions,
+    abbreviator: &Abbreviator,
+) -> Vec<Node> {
+    let mut metadata: Vec<&Metadata> = metadata.iter().collect();
+    metadata.sort_by_key(|metadata| metadata.location().0);
+
+    let mut nodes = Vec::new();
+    let mut cursor = 0;
+
+    for metadata in metadata {
+        let (start, end) = metadata.location();
+        if start < cursor || text.get(start..end).is_none() {
+            continue;
+        }
+
+        nodes.push(Node::Html(escape_prose(
+            &unescape(&text[cursor..start], options.escape),
+            options,
+        )));
+
+        match metadata {
+            // A link whose scheme is not safe is only its label
+            Metadata::Link { label, url, .. } if !safe_url(url) => {
+                nodes.extend(abbreviator.html(&unescape(label, options.escape), options))
+            }
+            Metadata::Link { label, url, .. } => {
+                let short = (options.shorten_urls && label == url)
+                    .then(|| shorten_url(url))
+                    .flatten();
+                let url = &if options.encode_urls {
+                    encode_url(url)
+                } else {
+                    url.clone()
+                };
+                let email = url
+                    .get(..7)
+                    .is_some_and(|scheme| scheme.eq_ignore_ascii_case("mailto:"))
+                    .then(|| &url[7..]);
+
+                let link = match email {
+                    Some(email) if options.obfuscate_emails => {
+                        let label = if label == email {
+                            vec![Node::Html(encode_entities(label))]
+                        } else {
+                            abbreviator.html(&unescape(label, options.escape), options)
+                        };
+
+                        Node::element("a", vec![("href", encode_entities(url))], label)
+                    }
+                    _ => Node::element(
+                        "a",
+                        vec![("href", escape_attr(url))],
+                        match short {
+                            Some(short) => vec![Node::Html(escape_prose(&short, options))],
+                            None => abbreviator.html(&unescape(label, options.escape), options),
+                        },
+                    ),
+                };
+                nodes.push(link);
+            }
+            Metadata::Image { .. } => nodes.push(image_html(metadata, options)),
+            Metadata::Abbreviation { short, title, .. } => {
+                nodes.push(abbr::abbr_html(short, title))
+            }
+            Metadata::Raw { closed: true, .. } => {
+                nodes.push(Node::Html(text[start..end].to_string()))
+            }
+            Metadata::Raw { closed: false, .. } => nodes.push(Node::Html(escape_prose(
+                &unescape(&text[start..end], options.escape),
+                options,
+            ))),
+            Metadata::InlineMath { content, .. } => nodes.push(Node::element(
+                "span",
+                vec![("class", "math inline".to_string())],
+                vec![Node::Html(format!("\\({}\\)", escape_text(content)))],
             )),
-            Token::Heading { level, content } => contents.push(format!(
-                "<h{}>{}</h{}>",
-                level,
-                html_escape::encode_text(&content),
-                level
+            Metadata::LineBreak { .. } => {
+                nodes.push(Node::element("br", Vec::new(), Vec::new()));
+                nodes.push(Node::Html("\n".to_string()));
+            }
+            Metadata::WikiLink { target, alias, .. } => nodes.push(Node::element(
+                "a",
+                vec![("href", escape_attr(&wiki_href(target, options)))],
+                abbreviator.html(
+                    &unescape(alias.as_ref().unwrap_or(target), options.escape),
+                    options,
+                ),
             )),
-            Token::HorizontalRule() => contents.push("<hr />".to_string()),
         }
+
+        cursor = end;
+    }
+
+    nodes.push(Node::Html(escape_prose(
+        &unescape(&text[cursor..], options.escape),
+        options,
+    )));
+    nodes
+}
+
+/// Where a wiki link to `target` goes: the slug of the page, and of the
+/// heading after a `#` if there is one. A link to a heading alone stays on
+/// the page.
+fn wiki_href(target: &str, options: &HtmlOptions) -> String {
+    let slug = |text: &str| match options.slug_style.slugify(text.trim()) {
+        slug if slug.is_empty() => EMPTY_SLUG.to_string(),
+        slug => slug,
+    };
+
+    match target.split_once('#') {
+        Some((page, heading)) if page.trim().is_empty() => format!("#{}", slug(heading)),
+        Some((page, heading)) => format!("{}#{}", slug(page), slug(heading)),
+        None => slug(target),
+    }
+}
+
+/// Renders a piece of inline Markdown, like the text of a paragraph, with the
+/// links and images `inline_metadata` finds in it.
+pub(crate) fn inline_to_html(text: &str, options: &HtmlOptions) -> String {
+    let sanitized;
+    let text = if options.sanitize_control_chars {
+        sanitized = sanitize::string(text);
+        &sanitized
+    } else {
+        text
+    };
+    let metadata = inline_metadata(text, &ParseOptions::default());
+    let abbreviator = Abbreviator::from(BTreeMap::new());
+
+    inline_html(text, &metadata, options, &abbreviator)
+        .iter()
+        .map(|node| node.to_html(options.output_style))
+        .collect()
+}
+
+/// Inline Markdown as it reads: links and images replaced by their labels,
+/// and backslash escapes and character references undone.
+pub(crate) fn plain_text(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut cursor = 0;
+
+    for metadata in inline_metadata(text, &ParseOptions::default()) {
+        let (start, end) = metadata.location();
+        if start < cursor || text.get(start..end).is_none() {
+            continue;
+        }
+
+        plain += &unescape(&text[cursor..start], true);
+        match &metadata {
+            Metadata::Link { label, .. } | Metadata::Image { label, .. } => {
+                plain += &unescape(label, true)
+            }
+            Metadata::WikiLink { target, alias, .. } => plain += alias.as_ref().unwrap_or(target),
+            _ => {}
+        }
+        cursor = end;
+    }
+
+    plain + &unescape(&text[cursor..], true)
+}
+
+/// Renders an image metadata as an `<img>`.
+fn image_html(image: &Metadata, options: &HtmlOptions) -> Node {
+    let Metadata::Image {
+        label,
+        url,
+        width,
+        height,
+        ..
+    } = image
+    else {
+        return Node::Html(String::new());
+    };
+    let safe = safe_url(url);
+    let url = &if options.encode_urls {
+        encode_url(url)
+    } else {
+        url.clone()
+    };
+
+    // An image whose scheme is not safe keeps its alt text, but no source
+    let mut attrs = Vec::new();
+    if safe {
+        attrs.push(("src", escape_attr(url)));
+    }
+    attrs.push(("alt", escape_attr(&unescape(label, true))));
+    if let Some(width) = width {
+        attrs.push(("width", width.to_string()));
+    }
+    if let Some(height) = height {
+        attrs.push(("height", height.to_string()));
+    }
+    if options.lazy_images {
+        attrs.push(("loading", "lazy".to_string()));
+    }
+    if options.decorative_role && label.is_empty() {
+        attrs.push(("role", "presentation".to_string()));
     }
 
-    Ok(contents.join("\n"))
+    Node::element("img", attrs, Vec::new())
+}
+
+/// Finds the image a paragraph starts with, if nothing but whitespace comes
+/// before it. Returns the image and where it ends in the text.
+fn leading_image<'a>(text: &str, metadata: &'a [Metadata]) -> Option<(&'a Metadata, usize)> {
+    metadata.iter().find_map(|metadata| match metadata {
+        Metadata::Image {
+            location: (start, end),
+            ..
+        } if text.get(..*start)?.trim().is_empty() => Some((metadata, *end)),
+        _ => None,
+    })
+}
+
+/// The image a paragraph consists of, if it is nothing else.
+fn lone_image<'a>(text: &str, metadata: &'a [Metadata]) -> Option<&'a Metadata> {
+    let (image, end) = leading_image(text, metadata)?;
+
+    text[end..].trim().is_empty().then_some(image)
+}
+
+/// Renders a paragraph that is an image followed by text as a `<figure>`,
+/// with the text as its caption, if `options.figures` is on.
+fn figure_html(
+    text: &str,
+    metadata: &[Metadata],
+    options: &HtmlOptions,
+    abbreviator: &Abbreviator,
+) -> Option<Node> {
+    if !options.figures {
+        return None;
+    }
+
+    let (image, end) = leading_image(text, metadata)?;
+    let caption = text[end..].trim_start();
+    if caption.trim().is_empty() {
+        return None;
+    }
+
+    // Move the metadata of the caption to where it is in the caption
+    let start = text.len() - caption.len();
+    let metadata: Vec<Metadata> = metadata
+        .iter()
+        .filter(|metadata| metadata.location().0 >= start)
+        .cloned()
+        .map(|mut metadata| {
+            let location = metadata.location_mut();
+            *location = (location.0 - start, location.1 - start);
+            metadata
+        })
+        .collect();
+
+    Some(Node::element(
+        "figure",
+        Vec::new(),
+        vec![
+            image_html(image, options),
+            Node::element(
+                "figcaption",
+                dir_attr(options),
+                inline_html(caption, &metadata, options, abbreviator),
+            ),
+        ],
+    ))
+}
+
+/// Renders a paragraph the way it goes in a gallery: a lone image as the
+/// bare `<img>`, an image with a caption as a figure. Returns `None` for a
+/// token that cannot go in one.
+fn gallery_item(token: &Token, options: &HtmlOptions, abbreviator: &Abbreviator) -> Option<Node> {
+    let Token::Paragraph(text, metadata, _, _, breaks, ..) = token else {
+        return None;
+    };
+    let text = if options.preserve_soft_breaks {
+        soft_breaks(text.clone(), breaks)
+    } else {
+        text.clone()
+    };
+
+    match lone_image(&text, metadata) {
+        Some(image) => Some(image_html(image, options)),
+        None => figure_html(&text, metadata, options, abbreviator),
+    }
+}
+
+/// Wraps the paragraphs collected in `gallery` in a `<div class="gallery">`,
+/// or leaves a single one as it is.
+fn flush_gallery(gallery: &mut Vec<(Node, Node)>, contents: &mut Vec<Node>) {
+    if gallery.len() > 1 {
+        let items = gallery.drain(..).map(|(item, _)| item).collect();
+        contents.push(Node::element(
+            "div",
+            vec![("class", "gallery".to_string())],
+            items,
+        ));
+    } else {
+        contents.extend(gallery.drain(..).map(|(_, html)| html));
+    }
+}
+
+/// The `dir` attribute of headings, paragraphs and captions.
+fn dir_attr(options: &HtmlOptions) -> Vec<(&'static str, String)> {
+    if options.auto_dir {
+        vec![("dir", "auto".to_string())]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Splits the inline HTML of a definition at the blank lines between its
+/// paragraphs.
+fn split_paragraphs(nodes: Vec<Node>) -> Vec<Vec<Node>> {
+    let mut paragraphs = vec![Vec::new()];
+
+    for node in nodes {
+        match node {
+            Node::Html(html) => {
+                let mut pieces = html
+                    .split("\n\n")
+                    .map(|piece| Node::Html(piece.to_string()));
+                paragraphs.last_mut().unwrap().extend(pieces.next());
+                paragraphs.extend(pieces.map(|piece| vec![piece]));
+            }
+            element => paragraphs.last_mut().unwrap().push(element),
+        }
+    }
+
+    paragraphs
+}
+
+pub(crate) fn tokens_to_html(tokens: Tokens, options: &HtmlOptions) -> PyResult<String> {
+    tokens_to_html_timed(tokens, options, &mut Phases::off())
+}
+
+/// [`tokens_to_html`], timing its phases in `phases`.
+pub(crate) fn tokens_to_html_timed(
+    tokens: Tokens,
+    options: &HtmlOptions,
+    phases: &mut Phases,
+) -> PyResult<String> {
+    let nodes = phases.time("nodes", || tokens_to_nodes(tokens, options));
+    let html = phases.time("write", || {
+        let html: Vec<String> = nodes
+            .iter()
+            .map(|node| node.to_html(options.output_style))
+            .collect();
+        html.join("\n")
+    });
+
+    Ok(html)
+}
+
+/// Renders `tokens` as one node per block, the tree `tokens_to_html` writes.
+pub(crate) fn tokens_to_nodes(mut tokens: Tokens, options: &HtmlOptions) -> Vec<Node> {
+    if options.sanitize_control_chars {
+        sanitize::tokens(&mut tokens);
+    }
+    let abbreviator = Abbreviator::new(&tokens);
+    let anchors = anchors::anchor_map(&tokens, options.slug_style);
+    anchors::resolve_cross_references(&mut tokens, &anchors);
+    let mut slugger = Slugger::new(options.slug_style);
+
+    if let Some(i) = options
+        .strip_title
+        .then(|| outline::title_index(&tokens, false))
+        .flatten()
+    {
+        if let Token::Heading { content, .. } = tokens.remove(i) {
+            slugger.slug(&slug_text(content, options));
+        }
+    }
+
+    match options.collapse_level {
+        Some(level) => collapsed_html(tokens, level, options, &abbreviator, &mut slugger),
+        None => blocks_html(tokens, options, &abbreviator, &mut slugger),
+    }
+}
+
+/// Renders `tokens` like [`blocks_html`], but with every section of a
+/// heading of `level` in a `<details>`, the heading as its `<summary>`.
+///
+/// The heading keeps its id inside the summary, and the headings are still
+/// slugged in document order, so the ids are those of an uncollapsed render.
+fn collapsed_html(
+    tokens: Tokens,
+    level: u8,
+    options: &HtmlOptions,
+    abbreviator: &Abbreviator,
+    slugger: &mut Slugger,
+) -> Vec<Node> {
+    let mut contents = Vec::new();
+    let mut rest = tokens;
+    let mut first = true;
+
+    loop {
+        let start = rest
+            .iter()
+            .position(|token| matches!(token, Token::Heading { level: l, .. } if *l == level));
+        let Some(start) = start else {
+            contents.extend(blocks_html(rest, options, abbreviator, slugger));
+            break;
+        };
+        let end = outline::section_end(&rest, start).unwrap_or(start + 1);
+
+        let after = rest.split_off(end);
+        let mut section = rest.split_off(start);
+        contents.extend(blocks_html(rest, options, abbreviator, slugger));
+        rest = after;
+
+        let heading = token_html(section.remove(0), options, abbreviator, slugger);
+        let mut children = vec![
+            Node::element("summary", Vec::new(), heading.into_iter().collect()),
+            Node::Html("\n".to_string()),
+        ];
+        for block in blocks_html(section, options, abbreviator, slugger) {
+            children.push(block);
+            children.push(Node::Html("\n".to_string()));
+        }
+
+        let attrs = if first && options.open_first {
+            vec![("open", String::new())]
+        } else {
+            Vec::new()
+        };
+        contents.push(Node::element("details", attrs, children));
+        first = false;
+    }
+
+    contents
+}
+
+/// The text the slug of a heading is made from: its content, or with
+/// `recompute_slugs` the content as `heading_case` changes it.
+fn slug_text(content: String, options: &HtmlOptions) -> String {
+    if options.recompute_slugs {
+        options.heading_case.apply(&content)
+    } else {
+        content
+    }
+}
+
+/// Renders a run of blocks, at the top of a document or in a container.
+fn blocks_html(
+    tokens: Tokens,
+    options: &HtmlOptions,
+    abbreviator: &Abbreviator,
+    slugger: &mut Slugger,
+) -> Vec<Node> {
+    let mut contents: Vec<Node> = Vec::with_capacity(tokens.len());
+    // The gallery items of the paragraphs since the last other block, and
+    // the HTML of the same paragraphs outside a gallery
+    let mut gallery: Vec<(Node, Node)> = Vec::new();
+
+    for token in tokens {
+        let item = options
+            .group_images
+            .then(|| gallery_item(&token, options, abbreviator))
+            .flatten();
+
+        // Tokens that render to nothing, like abbreviations, do not break up
+        // a gallery
+        let Some(html) = token_html(token, options, abbreviator, slugger) else {
+            continue;
+        };
+
+        match item {
+            Some(item) => gallery.push((item, html)),
+            None => {
+                flush_gallery(&mut gallery, &mut contents);
+                contents.push(html);
+            }
+        }
+    }
+    flush_gallery(&mut gallery, &mut contents);
+
+    contents
+}
+
+/// Renders one token the way `tokens_to_html` renders it on its own.
+pub(crate) fn token_to_html(token: Token, options: &HtmlOptions) -> PyResult<String> {
+    tokens_to_html(vec![token], options)
+}
+
+/// Renders one token of a document, with the abbreviations and heading ids
+/// of that document. Returns `None` for a token that renders to nothing.
+fn token_html(
+    token: Token,
+    options: &HtmlOptions,
+    abbreviator: &Abbreviator,
+    slugger: &mut Slugger,
+) -> Option<Node> {
+    let text = |text: &str| abbreviator.html(&unescape(text, options.escape), options);
+    let inline =
+        |text: &str, metadata: &[Metadata]| inline_html(text, metadata, options, abbreviator);
+
+    let html = match token {
+        Token::Paragraph(s, metadata, _, _, breaks, ..) => {
+            let s = if options.preserve_soft_breaks {
+                soft_breaks(s, &breaks)
+            } else {
+                s
+            };
+
+            match figure_html(&s, &metadata, options, abbreviator) {
+                Some(figure) => figure,
+                None => Node::element("p", dir_attr(options), inline(&s, &metadata)),
+            }
+        }
+        Token::Code {
+            language, content, ..
+        } => {
+            let language = if options.normalize_languages {
+                normalize_language(&language)
+            } else {
+                language
+            };
+            let class = match sanitize_class(&language) {
+                Some(language) => vec![("class", format!("language-{language}"))],
+                None => Vec::new(),
+            };
+
+            let content = if options.trim_code_lines {
+                let lines: Vec<_> = content.split('\n').map(str::trim_end).collect();
+                lines.join("\n")
+            } else {
+                content
+            };
+            let content = if options.escape_code {
+                escape_text(&content)
+            } else {
+                content
+            };
+
+            Node::element(
+                "pre",
+                Vec::new(),
+                vec![Node::element("code", class, vec![Node::Html(content)])],
+            )
+        }
+        Token::Heading { level, content, .. } => {
+            let cased = options.heading_case.apply(&content);
+            let slug = options.heading_ids.then(|| {
+                slugger.slug(if options.recompute_slugs {
+                    &cased
+                } else {
+                    &content
+                })
+            });
+            let mut attrs = match slug.as_deref().and_then(sanitize_id) {
+                Some(id) => vec![("id", escape_attr(id))],
+                None => Vec::new(),
+            };
+            attrs.extend(dir_attr(options));
+
+            Node::element(format!("h{level}"), attrs, text(&cased))
+        }
+        Token::HorizontalRule { .. } => Node::element("hr", Vec::new(), Vec::new()),
+        Token::Abbreviation { .. } => return None,
+        Token::DefinitionList { items, .. } => {
+            let mut children = Vec::new();
+
+            for (term, definitions) in items {
+                children.push(Node::element("dt", dir_attr(options), text(&term)));
+
+                for (definition, metadata) in definitions {
+                    let mut paragraphs: Vec<_> = if metadata.is_empty() {
+                        definition.split("\n\n").map(text).collect()
+                    } else {
+                        split_paragraphs(inline(&definition, &metadata))
+                    };
+
+                    let content = if paragraphs.len() == 1 {
+                        paragraphs.pop().unwrap()
+                    } else {
+                        paragraphs
+                            .into_iter()
+                            .map(|paragraph| Node::element("p", Vec::new(), paragraph))
+                            .collect()
+                    };
+                    children.push(Node::element("dd", dir_attr(options), content));
+                }
+            }
+
+            Node::element("dl", Vec::new(), children)
+        }
+        Token::Container { name, content, .. } => {
+            let class = match sanitize_class(&name) {
+                Some(name) => vec![("class", name.to_string())],
+                None => Vec::new(),
+            };
+
+            // Blocks go on lines of their own, as at the top level
+            let mut children = vec![Node::Html("\n".to_string())];
+            for block in blocks_html(content, options, abbreviator, slugger) {
+                children.push(block);
+                children.push(Node::Html("\n".to_string()));
+            }
+
+            Node::element("div", class, children)
+        }
+        // Typeset by MathJax or KaTeX in the browser, which find it by its
+        // delimiters
+        Token::MathBlock { content, .. } => Node::element(
+            "div",
+            vec![("class", "math display".to_string())],
+            vec![Node::Html(format!("\\[{}\\]", escape_text(&content)))],
+        ),
+    };
+
+    Some(html)
 }
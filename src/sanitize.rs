@@ -0,0 +1,167 @@
+use crate::parser::{Metadata, Token};
+
+/// Whether `c` cannot be written to a document: a control character or
+/// noncharacter, as the HTML spec has them, but tab, line feed and carriage
+/// return. A form feed is disallowed too, since XML has no place for one.
+fn disallowed(c: char) -> bool {
+    match c {
+        '\t' | '\n' | '\r' => false,
+        '\0'..='\u{1F}' | '\u{7F}'..='\u{9F}' | '\u{FDD0}'..='\u{FDEF}' => true,
+        _ => (c as u32) & 0xFFFE == 0xFFFE,
+    }
+}
+
+/// Replaces the disallowed characters of `text` with U+FFFD, and returns
+/// where each of them was and how many bytes it took.
+fn replace(text: &mut String) -> Vec<(usize, usize)> {
+    let replaced: Vec<_> = text
+        .char_indices()
+        .filter(|(_, c)| disallowed(*c))
+        .map(|(i, c)| (i, c.len_utf8()))
+        .collect();
+
+    if !replaced.is_empty() {
+        *text = text.replace(disallowed, "\u{FFFD}");
+    }
+    replaced
+}
+
+/// Where byte `offset` of a text is once the characters in `replaced` are
+/// U+FFFD. An offset right after one moves with it.
+fn moved(replaced: &[(usize, usize)], offset: usize) -> usize {
+    let before = replaced.partition_point(|(at, _)| *at < offset);
+    let removed: usize = replaced[..before].iter().map(|(_, len)| len).sum();
+
+    offset + before * '\u{FFFD}'.len_utf8() - removed
+}
+
+/// [`replace`] on a text the offsets of `metadata` and `breaks` point into.
+fn text(text: &mut String, metadata: &mut [Metadata], breaks: &mut [usize]) {
+    let replaced = replace(text);
+    if replaced.is_empty() {
+        return;
+    }
+
+    for metadata in metadata {
+        let location = metadata.location_mut();
+        *location = (moved(&replaced, location.0), moved(&replaced, location.1));
+    }
+    for at in breaks {
+        *at = moved(&replaced, *at);
+    }
+}
+
+fn metadata(metadata: &mut Metadata) {
+    match metadata {
+        Metadata::Link { label, url, .. } | Metadata::Image { label, url, .. } => {
+            replace(label);
+            replace(url);
+        }
+        Metadata::Abbreviation { short, title, .. } => {
+            replace(short);
+            replace(title);
+        }
+        Metadata::InlineMath { content, .. } => {
+            replace(content);
+        }
+        Metadata::WikiLink { target, alias, .. } => {
+            replace(target);
+            if let Some(alias) = alias {
+                replace(alias);
+            }
+        }
+        Metadata::Raw { .. } | Metadata::LineBreak { .. } => {}
+    }
+}
+
+/// Replaces the disallowed characters in the text of `token` with U+FFFD,
+/// moving the offsets of its metadata to match. Spans still index into the
+/// source, and `raw` is kept as written.
+///
+/// The content of a container is left alone, so that each parse of it
+/// sees to its own tokens; [`tokens`] goes into containers.
+pub(crate) fn token(token: &mut Token) {
+    match token {
+        Token::Heading {
+            content, number, ..
+        } => {
+            replace(content);
+            if let Some(number) = number {
+                replace(number);
+            }
+        }
+        Token::Paragraph(paragraph, metadatas, _, _, breaks, ..) => {
+            text(paragraph, metadatas, breaks);
+            metadatas.iter_mut().for_each(metadata);
+        }
+        Token::DefinitionList { items, .. } => {
+            for (term, definitions) in items {
+                replace(term);
+                for (definition, metadatas) in definitions {
+                    text(definition, metadatas, &mut []);
+                    metadatas.iter_mut().for_each(metadata);
+                }
+            }
+        }
+        Token::Abbreviation {
+            abbr, expansion, ..
+        } => {
+            replace(abbr);
+            replace(expansion);
+        }
+        Token::Code {
+            language,
+            content,
+            info,
+            flags,
+            attrs,
+            ..
+        } => {
+            replace(language);
+            replace(content);
+            replace(info);
+            flags.iter_mut().for_each(|flag| {
+                replace(flag);
+            });
+            if attrs
+                .keys()
+                .chain(attrs.values())
+                .any(|s| s.contains(disallowed))
+            {
+                *attrs = std::mem::take(attrs)
+                    .into_iter()
+                    .map(|(mut name, mut value)| {
+                        replace(&mut name);
+                        replace(&mut value);
+                        (name, value)
+                    })
+                    .collect();
+            }
+        }
+        Token::Container { name, attrs, .. } => {
+            replace(name);
+            replace(attrs);
+        }
+        Token::MathBlock { content, .. } => {
+            replace(content);
+        }
+        Token::HorizontalRule { .. } => {}
+    }
+}
+
+/// [`token`] on every token, those inside containers included.
+pub(crate) fn tokens(tokens: &mut [Token]) {
+    for t in tokens {
+        token(t);
+        if let Token::Container { content, .. } = t {
+            self::tokens(content);
+        }
+    }
+}
+
+/// `text` with its disallowed characters replaced by U+FFFD.
+pub(crate) fn string(text: &str) -> String {
+    let mut text = text.to_string();
+    replace(&mut text);
+    text
+}
@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+
+use lazy_static::lazy_static;
+use pyo3::{exceptions::PyValueError, prelude::*};
+use regex::Regex;
+
+use crate::parser;
+
+lazy_static! {
+    /// What GitHub drops from a heading: everything but letters, marks,
+    /// numbers, connector punctuation, hyphens and spaces.
+    static ref GITHUB_STRIP_RE: Regex = Regex::new(r"[^\p{L}\p{M}\p{N}\p{Pc}\- ]").unwrap();
+}
+
+/// Turns heading text into an anchor.
+///
+/// Letters and digits are lowercased and kept; every run of anything else
+/// becomes a single `-`, and leading/trailing hyphens are dropped.
+pub(crate) fn slugify(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+    let mut pending = false;
+
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            if pending && !slug.is_empty() {
+                slug.push('-');
+            }
+            slug.extend(c.to_lowercase());
+            pending = false;
+        } else {
+            pending = true;
+        }
+    }
+
+    slug
+}
+
+/// Turns heading text into the anchor GitHub gives it.
+///
+/// GitHub slugs the heading as rendered, so links count as their label and
+/// backslash escapes and character references as what they stand for. The
+/// text is then lowercased, punctuation other than `-` and `_` is removed
+/// (emoji included), and every space becomes a `-`. Runs are not collapsed,
+/// so `a - b` is `a---b`.
+pub(crate) fn github_slugify(text: &str) -> String {
+    GITHUB_STRIP_RE
+        .replace_all(&parser::plain_text(text).to_lowercase(), "")
+        .replace(' ', "-")
+}
+
+/// Which algorithm turns headings into anchors.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum SlugStyle {
+    /// [`slugify`].
+    #[default]
+    Simple,
+    /// [`github_slugify`].
+    Github,
+}
+
+impl SlugStyle {
+    pub(crate) fn parse(style: &str) -> PyResult<Self> {
+        match style {
+            "simple" => Ok(Self::Simple),
+            "github" => Ok(Self::Github),
+            _ => Err(PyValueError::new_err(format!(
+                "slug style must be \"simple\" or \"github\", got {style:?}"
+            ))),
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Simple => "simple",
+            Self::Github => "github",
+        }
+    }
+
+    pub(crate) fn slugify(self, text: &str) -> String {
+        match self {
+            Self::Simple => slugify(text),
+            Self::Github => github_slugify(text),
+        }
+    }
+}
+
+/// The slug of a heading that has no letters or digits to make one of.
+pub(crate) const EMPTY_SLUG: &str = "section";
+
+/// Hands out unique slugs within one document.
+///
+/// A slug seen before gets a `-1`, `-2`, ... suffix, the way GitHub does it.
+/// A heading that slugs to nothing, as one of only emoji or punctuation
+/// does, gets [`EMPTY_SLUG`] instead, so its id and the links to it still
+/// work.
+#[derive(Default)]
+pub(crate) struct Slugger {
+    style: SlugStyle,
+    seen: BTreeMap<String, usize>,
+}
+
+impl Slugger {
+    pub(crate) fn new(style: SlugStyle) -> Self {
+        Self {
+            style,
+            seen: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn slug(&mut self, text: &str) -> String {
+        let mut base = self.style.slugify(text);
+        if base.is_empty() {
+            base = EMPTY_SLUG.to_string();
+        }
+        let mut slug = base.clone();
+
+        while let Some(count) = self.seen.get_mut(&slug) {
+            *count += 1;
+            slug = format!("{base}-{count}");
+        }
+        self.seen.insert(slug.clone(), 0);
+
+        slug
+    }
+}
@@ -0,0 +1,183 @@
+use pyo3::{exceptions::PyValueError, prelude::*};
+
+use crate::{
+    options::ParseOptions,
+    parser::{self, Token, Tokens},
+    strict,
+};
+
+/// Parses Markdown fed to it a piece at a time, like a file that is still
+/// being written.
+///
+/// `drain` returns the tokens that no further input can change, and
+/// `finish` the rest once the input is over. All the tokens drained, in
+/// order, are what `parse` gives for the whole input, however it was cut
+/// into pieces.
+///
+/// Only the lines from the first block not yet drained are kept. When they
+/// are one block still open, a `feed` looks only at the lines it completes,
+/// and parses again only if one of them may end the block. Abbreviations are
+/// not supported, since a definition further down changes the paragraphs
+/// above it.
+#[pyclass]
+pub(crate) struct StreamParser {
+    options: ParseOptions,
+    /// The input from the start of the first block not drained.
+    pending: String,
+    /// Where `pending` starts in the input.
+    offset: usize,
+    /// Tokens that are done, but not drained yet.
+    ready: Tokens,
+    /// The id of the next token done.
+    next_id: usize,
+    /// The one block `pending` holds when it runs open to the end of the
+    /// lines parsed, and where in `pending` the lines not yet looked at start.
+    open: Option<(Token, usize)>,
+    finished: bool,
+}
+
+#[pymethods]
+impl StreamParser {
+    #[new]
+    #[pyo3(signature = (options = None))]
+    fn new(options: Option<ParseOptions>) -> PyResult<Self> {
+        let options = options.unwrap_or_default();
+        if options.abbreviations {
+            return Err(PyValueError::new_err(
+                "StreamParser does not support ParseOptions(abbreviations=True)",
+            ));
+        }
+
+        Ok(Self {
+            options,
+            pending: String::new(),
+            offset: 0,
+            ready: Vec::new(),
+            next_id: 0,
+            open: None,
+            finished: false,
+        })
+    }
+
+    /// Adds `chunk` to the input. It may end in the middle of a line.
+    fn feed(&mut self, py: Python<'_>, chunk: &str) -> PyResult<()> {
+        if self.finished {
+            return Err(PyValueError::new_err("cannot feed a finished StreamParser"));
+        }
+
+        self.pending.push_str(chunk);
+        if chunk.contains('\n') {
+            py.allow_threads(|| self.advance());
+        }
+
+        Ok(())
+    }
+
+    /// Returns the tokens done since the last `drain`.
+    fn drain(&mut self) -> Tokens {
+        std::mem::take(&mut self.ready)
+    }
+
+    /// Ends the input, and returns the tokens not drained yet, those of the
+    /// blocks still open included. With `ParseOptions(strict=True)`, an
+    /// unclosed fence or container raises `ParseError` here.
+    fn finish(&mut self, py: Python<'_>) -> PyResult<Tokens> {
+        if !self.finished {
+            self.finished = true;
+
+            let pending = std::mem::take(&mut self.pending);
+            let mut tokens: Tokens = Vec::new();
+            py.allow_threads(|| {
+                parser::parse_blocks(&pending, 0, &self.options, &mut tokens, |_, _| true)
+            });
+
+            if self.options.strict {
+                strict::check(&pending, self.offset, &tokens, &self.options)?;
+            }
+            self.emit(tokens);
+        }
+
+        Ok(self.drain())
+    }
+}
+
+impl StreamParser {
+    /// Parses the complete lines of `pending`, and moves the tokens of every
+    /// block that more lines cannot change to `ready`.
+    fn advance(&mut self) {
+        // A partial last line waits for the rest of it
+        let Some(end) = self.pending.rfind('\n') else {
+            return;
+        };
+        let complete = &self.pending[..=end];
+
+        // Lines that keep the open block open leave everything as it was
+        if let Some((block, scanned)) = &mut self.open {
+            let new = &complete[*scanned..end];
+            if new
+                .split('\n')
+                .all(|line| parser::continues(block, line, &self.options))
+            {
+                *scanned = complete.len();
+                return;
+            }
+            self.open = None;
+        }
+
+        let lines = memchr::memchr_iter(b'\n', complete.as_bytes()).count();
+        let mut tokens: Tokens = Vec::new();
+        let mut starts = Vec::new();
+        parser::parse_blocks(complete, 0, &self.options, &mut tokens, |line, count| {
+            starts.push((line, count));
+            true
+        });
+
+        // Where a block ends depends on the line after the next block start
+        // at most, so a block is done once that line is complete
+        let done = starts
+            .iter()
+            .skip(1)
+            .take_while(|&&(line, _)| line + 1 < lines)
+            .last();
+        let (start, count) = match done {
+            Some(&(line, count)) => (parser::line_offsets(complete)[line], count),
+            None => (0, 0),
+        };
+
+        // A last block that still runs to the end of the input is watched
+        // for the line that may end it
+        if starts.len() - starts.partition_point(|&(_, at)| at < count) == 1
+            && tokens.len() == count + 1
+        {
+            // A paragraph has no closing line, and any line may add to it
+            let block = &tokens[count];
+            let open = match block {
+                Token::Paragraph(..) => true,
+                _ => !strict::closed(complete, block),
+            };
+            if open && block.span().1 == end {
+                self.open = Some((block.clone(), complete.len() - start));
+            }
+        }
+
+        if start > 0 {
+            tokens.truncate(count);
+            self.emit(tokens);
+
+            self.pending.drain(..start);
+            self.offset += start;
+        }
+    }
+
+    /// Moves `tokens` parsed from `pending` to `ready`, with their spans in
+    /// the whole input and ids following those of the tokens before them.
+    fn emit(&mut self, mut tokens: Tokens) {
+        let offset = self.offset;
+        parser::number_tokens(&mut tokens, &mut self.next_id, None);
+
+        self.ready.extend(tokens.into_iter().map(|mut token| {
+            token.map_spans(&|at| at + offset);
+            token
+        }));
+    }
+}
@@ -0,0 +1,140 @@
+use pyo3::prelude::*;
+
+use crate::{
+    options::ParseOptions,
+    parser::{self, Token},
+};
+
+// The expansion of `create_exception!` in PyO3 0.22 checks for a `gil-refs`
+// feature this crate does not declare
+#[allow(unexpected_cfgs)]
+mod exception {
+    use pyo3::{create_exception, exceptions::PyValueError};
+
+    create_exception!(
+        md0,
+        ParseError,
+        PyValueError,
+        "Raised by `parse` with `ParseOptions(strict=True)` for a construct \
+         that is not closed, or a container nested deeper than \
+         `ParseOptions.max_depth`. `offset` is the byte in the source where \
+         the construct starts, and `code` says what is wrong with it: \
+         `unclosed-fence`, `unclosed-container`, `unclosed-math` or \
+         `max-depth`."
+    );
+}
+
+pub(crate) use exception::ParseError;
+
+fn error(message: String, offset: usize, code: &str) -> PyErr {
+    Python::with_gil(|py| {
+        let error = ParseError::new_err(message);
+        let value = error.value_bound(py);
+
+        match value
+            .setattr("offset", offset)
+            .and_then(|_| value.setattr("code", code))
+        {
+            Ok(()) => error,
+            Err(failed) => failed,
+        }
+    })
+}
+
+/// The last line of the source a token spans, if it spans more than one.
+fn last_line<'a>(source: &'a str, token: &Token) -> Option<&'a str> {
+    let (start, end) = token.span();
+    let (_, last) = source[start..end].rsplit_once('\n')?;
+
+    Some(last)
+}
+
+/// Whether `token`, parsed from `source`, is closed: for a fence, a
+/// container or a math block, whether it has its closing line. Other blocks
+/// have none to miss.
+pub(crate) fn closed(source: &str, token: &Token) -> bool {
+    match token {
+        Token::Code {
+            fence_char: Some(marker),
+            fence_length,
+            ..
+        } => {
+            let fence = marker.to_string().repeat(*fence_length);
+            last_line(source, token).is_some_and(|line| parser::closes(line, &fence))
+        }
+        Token::Container { content, span, .. } => {
+            // The last line is the closing marker if no token inside the
+            // container covers it
+            last_line(source, token).is_some_and(|line| {
+                let start = span.1 - line.len();
+
+                parser::closes(line, ":::")
+                    && content.last().is_none_or(|last| last.span().1 <= start)
+            })
+        }
+        // Past the opening `$$`, which a one-line block shares its line with
+        Token::MathBlock { span, .. } => source[span.0..span.1]
+            .trim()
+            .strip_prefix("$$")
+            .is_some_and(|rest| rest.ends_with("$$")),
+        _ => true,
+    }
+}
+
+/// Where the first line of `tokens`, which are `depth` containers deep,
+/// that would open a container if `options.max_depth` let it starts.
+///
+/// Such a line can only be in a paragraph: a container is never nested in
+/// anything else, and interrupts a paragraph wherever it may open.
+fn too_deep(source: &str, tokens: &[Token], depth: usize, options: &ParseOptions) -> Option<usize> {
+    tokens.iter().find_map(|token| match token {
+        Token::Container { content, .. } => too_deep(source, content, depth + 1, options),
+        Token::Paragraph(..) if depth >= options.max_depth => {
+            let (start, end) = token.span();
+            let text = &source[start..end];
+
+            parser::line_offsets(text)
+                .into_iter()
+                .zip(text.split('\n'))
+                .find(|(_, line)| parser::opens_container(line, options))
+                .map(|(at, _)| start + at)
+        }
+        _ => None,
+    })
+}
+
+/// Fails on the first fence, container or math block in `tokens` that is
+/// never closed, and so runs to the end of the input, then on the first
+/// container nested deeper than `options.max_depth`. The spans of `tokens`
+/// index into `source`, which starts at byte `offset` of the document.
+pub(crate) fn check(
+    source: &str,
+    offset: usize,
+    tokens: &[Token],
+    options: &ParseOptions,
+) -> PyResult<()> {
+    if let Some(token) = parser::walk(tokens)
+        .into_iter()
+        .find(|token| !closed(source, token))
+    {
+        let start = offset + token.span().0;
+        let (what, code) = match token {
+            Token::Container { .. } => ("container", "unclosed-container"),
+            Token::MathBlock { .. } => ("math block", "unclosed-math"),
+            _ => ("code fence", "unclosed-fence"),
+        };
+
+        let message = format!("{what} at byte {start} is never closed");
+        return Err(error(message, start, code));
+    }
+
+    if let Some(start) = too_deep(source, tokens, 0, options) {
+        let start = offset + start;
+        let max_depth = options.max_depth;
+
+        let message = format!("container at byte {start} is nested more than {max_depth} deep");
+        return Err(error(message, start, "max-depth"));
+    }
+
+    Ok(())
+}
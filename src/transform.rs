@@ -0,0 +1,155 @@
+use crate::parser::{Metadata, Token, Tokens};
+
+fn shift(metadata: &mut Metadata, by: usize) {
+    let location = match metadata {
+        Metadata::Link { location, .. }
+        | Metadata::Image { location, .. }
+        | Metadata::Abbreviation { location, .. }
+        | Metadata::Raw { location, .. }
+        | Metadata::LineBreak { location }
+        | Metadata::InlineMath { location, .. }
+        | Metadata::WikiLink { location, .. } => location,
+    };
+
+    location.0 += by;
+    location.1 += by;
+}
+
+/// Cleans up a token list after it was edited by hand, and the content of
+/// its containers.
+///
+/// Consecutive paragraphs are merged into one, joined by a space which counts
+/// as a soft break, with the metadata of the later ones moved to their new
+/// offsets. Empty paragraphs are dropped, and so is a horizontal rule right
+/// after another one. A merged paragraph spans all of its parts but keeps no
+/// `raw`, since the source between them is unknown.
+pub(crate) fn normalize(tokens: Tokens) -> Tokens {
+    let mut normalized: Tokens = Vec::with_capacity(tokens.len());
+
+    for mut token in tokens {
+        if let Token::Container { content, .. } = &mut token {
+            *content = normalize(std::mem::take(content));
+        }
+
+        match (normalized.last_mut(), token) {
+            (_, Token::Paragraph(text, ..)) if text.trim().is_empty() => {}
+            (
+                Some(Token::Paragraph(text, metadata, span, raw, breaks, ..)),
+                Token::Paragraph(next, next_metadata, next_span, _, next_breaks, ..),
+            ) => {
+                let offset = text.len() + 1;
+
+                breaks.push(text.len());
+                breaks.extend(next_breaks.into_iter().map(|i| i + offset));
+                metadata.extend(next_metadata.into_iter().map(|mut metadata| {
+                    shift(&mut metadata, offset);
+                    metadata
+                }));
+                text.push(' ');
+                text.push_str(&next);
+                *span = (span.0.min(next_span.0), span.1.max(next_span.1));
+                *raw = None;
+            }
+            (Some(Token::HorizontalRule { .. }), Token::HorizontalRule { .. }) => {}
+            (_, token) => normalized.push(token),
+        }
+    }
+
+    normalized
+}
+
+/// The heading a paragraph reads as, if it is one line of nothing but a bold
+/// span, or of at most `max_length` characters ending in one of `endings`.
+fn heading_like(
+    text: &str,
+    metadata: &[Metadata],
+    max_length: usize,
+    endings: &str,
+) -> Option<String> {
+    // Abbreviations are found in headings too; anything else would be lost
+    if metadata
+        .iter()
+        .any(|metadata| !matches!(metadata, Metadata::Abbreviation { .. }))
+    {
+        return None;
+    }
+
+    let text = text.trim();
+    if text.is_empty() || text.chars().count() > max_length {
+        return None;
+    }
+
+    for marker in ["**", "__"] {
+        let Some(inner) = text
+            .strip_prefix(marker)
+            .and_then(|text| text.strip_suffix(marker))
+        else {
+            continue;
+        };
+
+        if !inner.is_empty() && inner.trim() == inner && !inner.contains(marker) {
+            return Some(inner.to_string());
+        }
+    }
+
+    text.ends_with(|c| endings.contains(c))
+        .then(|| text.to_string())
+}
+
+/// Turns the paragraphs that read as headings into headings of `level`, in
+/// containers too: FAQ-style questions written as `**How do I install it?**`,
+/// or short lines like `Requirements:`.
+///
+/// A paragraph is promoted if it is a single line of at most `max_length`
+/// characters that is all one `**bold**` or `__bold__` span, which is taken
+/// off, or that ends in one of `endings`. Paragraphs with links, images or
+/// anything else besides the text are left alone.
+pub(crate) fn promote_headings(
+    tokens: Tokens,
+    level: u8,
+    max_length: usize,
+    endings: &str,
+) -> Tokens {
+    tokens
+        .into_iter()
+        .map(|token| match token {
+            Token::Paragraph(text, metadata, span, raw, breaks, id, parent_id) => {
+                let content = breaks
+                    .is_empty()
+                    .then(|| heading_like(&text, &metadata, max_length, endings))
+                    .flatten();
+
+                match content {
+                    Some(content) => Token::Heading {
+                        level,
+                        content,
+                        span,
+                        raw,
+                        number: None,
+                        id,
+                        parent_id,
+                    },
+                    None => Token::Paragraph(text, metadata, span, raw, breaks, id, parent_id),
+                }
+            }
+            Token::Container {
+                name,
+                attrs,
+                content,
+                span,
+                raw,
+                id,
+                parent_id,
+            } => Token::Container {
+                name,
+                attrs,
+                content: promote_headings(content, level, max_length, endings),
+                span,
+                raw,
+                id,
+                parent_id,
+            },
+            token => token,
+        })
+        .collect()
+}